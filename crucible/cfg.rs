@@ -0,0 +1,130 @@
+//! Conditional compilation.
+//!
+//! Every namespace and component whose `#[cfg]` predicates don't
+//! hold for the flags passed with `--cfg` is removed before names
+//! are resolved, so the rest of the compiler never sees them. The
+//! full id of each component removed is kept, so that a reference
+//! to one can be reported as such rather than as a plain typo.
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{bail, Result};
+
+use crate::diagnostic::Spanned;
+use crate::parser::{Crucible, Predicate, Unit};
+
+/// The flags passed with `--cfg`.
+#[derive(Debug, Default)]
+pub struct Flags {
+    names: HashSet<String>,
+    values: HashSet<(String, String)>,
+}
+
+impl Flags {
+    /// Reads flags written as `name` or `key=value`.
+    pub fn new(flags: &[String]) -> Result<Self> {
+        let valid = |name: &str| {
+            let mut chars = name.chars();
+            chars.next().map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        };
+        let mut parsed = Flags::default();
+        for flag in flags {
+            let name = flag.split_once('=').map_or(flag.as_str(), |(key, _)| key);
+            if !valid(name) {
+                bail!("`{}` is not a valid `--cfg` flag; expected `name` or `key=value`", flag);
+            }
+            match flag.split_once('=') {
+                Some((key, value)) => parsed.values.insert((key.to_owned(), value.to_owned())),
+                None => parsed.names.insert(flag.clone()),
+            };
+        }
+        Ok(parsed)
+    }
+
+    /// Whether `predicate` holds for these flags.
+    pub fn satisfy(&self, predicate: &Predicate) -> bool {
+        match predicate {
+            Predicate::Flag(name) => self.names.contains(name),
+            Predicate::Value(key, value) => self.values.contains(&(key.clone(), value.clone())),
+            Predicate::All(items) => items.iter().all(|p| self.satisfy(p)),
+            Predicate::Any(items) => items.iter().any(|p| self.satisfy(p)),
+            Predicate::Not(inner) => !self.satisfy(inner),
+        }
+    }
+}
+
+/// The full id of every component compiled out, with
+/// the predicate which didn't hold for it.
+pub type CompiledOut = HashMap<String, Spanned<Predicate>>;
+
+/// Removes every namespace and component from `crucible`
+/// which `flags` don't satisfy the predicates of.
+pub fn apply(crucible: &mut Crucible, flags: &Flags) -> CompiledOut {
+    let mut out = CompiledOut::new();
+    strip(crucible.units_mut(), "", flags, &mut out);
+    out
+}
+
+fn join(prefix: &str, id: &str) -> String {
+    if prefix.is_empty() {
+        id.to_owned()
+    } else {
+        format!("{}.{}", prefix, id)
+    }
+}
+
+fn strip(units: &mut Vec<Unit>, prefix: &str, flags: &Flags, out: &mut CompiledOut) {
+    units.retain_mut(|unit| match unit {
+        Unit::Namespace { id, cfg, units, .. } => {
+            let full = join(prefix, &id.0);
+            match cfg.iter().find(|p| !flags.satisfy(&p.item)) {
+                Some(failed) => {
+                    record(units, &full, failed, out);
+                    false
+                }
+                None => {
+                    strip(units, &full, flags, out);
+                    true
+                }
+            }
+        }
+        Unit::Component { id, cfg, .. } => match cfg.iter().find(|p| !flags.satisfy(&p.item)) {
+            Some(failed) => {
+                out.entry(join(prefix, &id.0)).or_insert_with(|| failed.clone());
+                false
+            }
+            None => true,
+        },
+        Unit::Expansion { units, .. } => {
+            strip(units, prefix, flags, out);
+            true
+        }
+        Unit::For { iterations, .. } => {
+            for iteration in iterations {
+                strip(&mut iteration.units, prefix, flags, out);
+            }
+            true
+        }
+        Unit::Use { .. } | Unit::Const { .. } | Unit::Template { .. } => true,
+    });
+}
+
+/// Records every component in a namespace compiled out by `failed`.
+fn record(units: &[Unit], prefix: &str, failed: &Spanned<Predicate>, out: &mut CompiledOut) {
+    for unit in units {
+        match unit {
+            Unit::Namespace { id, units, .. } => record(units, &join(prefix, &id.0), failed, out),
+            Unit::Component { id, .. } => {
+                out.entry(join(prefix, &id.0)).or_insert_with(|| failed.clone());
+            }
+            Unit::Expansion { units, .. } => record(units, prefix, failed, out),
+            Unit::For { iterations, .. } => {
+                for iteration in iterations {
+                    record(&iteration.units, prefix, failed, out);
+                }
+            }
+            Unit::Use { .. } | Unit::Const { .. } | Unit::Template { .. } => (),
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! Kind checking.
+//!
+//! Every id is just a string, so nothing in the grammar stops a
+//! recipe from drawing from a card, or from running in an aspect's
+//! verb. Each place a reference can appear expects a particular
+//! kind of component, and this pass reports references to a
+//! component of another kind. It runs after [crate::resolve], so
+//! references are full ids, and those to components outside this
+//! mod, whose kind isn't known, are left alone.
+//!
+//! For the same reason, an aspect given to a card twice is only
+//! found here, as it may be named differently each time.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::diagnostic::{Code, Diagnostic, Diagnostics, Span};
+use crate::inherit::article;
+use crate::parser::{CardDef, Component, Crucible, Iteration, Unit};
+
+/// Reports every reference in `crucible` to a
+/// component of a kind its position doesn't accept.
+pub fn check(crucible: &mut Crucible, diagnostics: &mut Diagnostics) {
+    let mut declared = HashMap::new();
+    collect(crucible.units(), &mut declared);
+    check_units(crucible.units_mut(), &declared, diagnostics);
+}
+
+/// The kind of a component and where it was declared.
+struct Declared {
+    kind: &'static str,
+    span: Span,
+}
+
+fn collect(units: &[Unit], declared: &mut HashMap<String, Declared>) {
+    for unit in units {
+        match unit {
+            Unit::Namespace { units, .. } | Unit::Expansion { units, .. } => collect(units, declared),
+            Unit::For { iterations, .. } => {
+                for iteration in iterations {
+                    collect(&iteration.units, declared);
+                }
+            }
+            Unit::Component { id, component, .. } => {
+                // Duplicates were already reported during
+                // resolution, so only the first one counts.
+                declared.entry(id.0.clone()).or_insert(Declared {
+                    kind: component.kind(),
+                    span: id.span,
+                });
+            }
+            Unit::Use { .. } | Unit::Const { .. } | Unit::Template { .. } => (),
+        }
+    }
+}
+
+fn check_units(units: &mut [Unit], declared: &HashMap<String, Declared>, diagnostics: &mut Diagnostics) {
+    // The verb of a recipe is copied into those lifted out of it,
+    // so a span already reported on is the same reference again.
+    let mut reported = HashSet::new();
+    for unit in units {
+        match unit {
+            Unit::Namespace { units, .. } => check_units(units, declared, diagnostics),
+            Unit::Component { id, component, .. } => {
+                let (kind, site) = (component.kind(), id.clone());
+                for (expected, reference) in component.references_mut() {
+                    let target = match declared.get(&reference.0) {
+                        Some(target) if !expected.accepts(target.kind) && reported.insert(reference.span) => target,
+                        _ => continue,
+                    };
+                    let declared_as = format!(
+                        "`{}` is declared as {} {} here",
+                        reference.0,
+                        article(target.kind),
+                        target.kind
+                    );
+                    let mut diagnostic = Diagnostic::error(
+                        Code::MismatchedKind,
+                        reference.span,
+                        format!("expected {}, found the {} `{}`", expected.describe(), target.kind, reference.0),
+                    )
+                    .with_label(target.span, declared_as);
+                    // A component referring to itself is already pointed out.
+                    if site.span != target.span {
+                        diagnostic = diagnostic.with_label(site.span, format!("referred to by the {} declared here", kind));
+                    }
+                    diagnostics.push(diagnostic);
+                }
+                if let Component::Card(card) = component {
+                    duplicate_aspects(card, diagnostics);
+                }
+            }
+            Unit::Expansion { template, units, span } => {
+                let mut found = Diagnostics::new();
+                check_units(units, declared, &mut found);
+                diagnostics.extend(found.into_iter().map(|d| d.in_expansion(&template.0, *span)));
+            }
+            Unit::For { variable, iterations, .. } => {
+                for Iteration { value, units } in iterations {
+                    let mut found = Diagnostics::new();
+                    check_units(units, declared, &mut found);
+                    let label = |d: Diagnostic| d.in_iteration(&variable.0, &value.item, value.span);
+                    diagnostics.extend(found.into_iter().map(label));
+                }
+            }
+            Unit::Use { .. } | Unit::Const { .. } | Unit::Template { .. } => (),
+        }
+    }
+}
+
+/// Reports every aspect given to `card` more than once.
+fn duplicate_aspects(card: &CardDef, diagnostics: &mut Diagnostics) {
+    for (n, (aspect, _)) in card.aspects.iter().enumerate() {
+        if let Some((first, _)) = card.aspects[..n].iter().find(|(first, _)| first.0 == aspect.0) {
+            diagnostics.push(
+                Diagnostic::error(
+                    Code::DuplicateAspect,
+                    aspect.span,
+                    format!("the aspect `{}` has already been declared on this card", aspect.0),
+                )
+                .with_label(first.span, "first declared here"),
+            );
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! Requirement combining.
+//!
+//! A requirement list may say more than one thing about the same
+//! element, and name it a different way each time, such as through
+//! a `use` alias. This pass runs after [crate::resolve], so that
+//! requirements on the same element have the same full id, and after
+//! [crate::inherit], so that a recipe's own requirements are combined
+//! with those it inherits. It turns every list into the requirements
+//! the game checks, with a way for each way an `or` can be met, as
+//! [crate::parser::combine] describes.
+
+use crate::diagnostic::{Diagnostic, Diagnostics};
+use crate::parser::{combine, BranchDef, Component, Crucible, Iteration, Unit};
+
+/// Combines the requirements of every recipe and branch
+/// in `crucible`, reporting any that can't be met or checked.
+pub fn combine_all(crucible: &mut Crucible, diagnostics: &mut Diagnostics) {
+    let mut found = Diagnostics::new();
+    combine_units(crucible.units_mut(), &mut found);
+    // A recipe that inherits from another is combined with the
+    // requirements and branches it inherits, and would otherwise
+    // repeat every problem with them.
+    let mut reported: Vec<Diagnostic> = Vec::new();
+    for diagnostic in found {
+        if !reported.iter().any(|d| d.repeats(&diagnostic)) {
+            reported.push(diagnostic);
+        }
+    }
+    diagnostics.extend(reported);
+}
+
+fn combine_units(units: &mut [Unit], diagnostics: &mut Diagnostics) {
+    for unit in units {
+        match unit {
+            Unit::Namespace { units, .. } => combine_units(units, diagnostics),
+            Unit::Component { component: Component::Recipe(recipe), .. } => {
+                (recipe.requirements, recipe.alternatives) = combine(&recipe.written, diagnostics);
+                for branch in recipe.branches.iter_mut() {
+                    let condition = match branch {
+                        BranchDef::Link { condition, .. } | BranchDef::Goto { condition, .. } => condition,
+                    };
+                    (condition.requirements, condition.alternatives) = combine(&condition.written, diagnostics);
+                }
+            }
+            Unit::Expansion { template, units, span } => {
+                let mut found = Diagnostics::new();
+                combine_units(units, &mut found);
+                diagnostics.extend(found.into_iter().map(|d| d.in_expansion(&template.0, *span)));
+            }
+            Unit::For { variable, iterations, .. } => {
+                for Iteration { value, units } in iterations {
+                    let mut found = Diagnostics::new();
+                    combine_units(units, &mut found);
+                    let label = |d: Diagnostic| d.in_iteration(&variable.0, &value.item, value.span);
+                    diagnostics.extend(found.into_iter().map(label));
+                }
+            }
+            Unit::Component { .. } | Unit::Use { .. } | Unit::Const { .. } | Unit::Template { .. } => (),
+        }
+    }
+}
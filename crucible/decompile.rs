@@ -0,0 +1,930 @@
+//! Turning a compiled mod, or the game's own JSON, back into
+//! Crucible source, with `crucible decompile`.
+//!
+//! Compiled `.lir` and `.lirc` files remember which namespace each
+//! component was declared in, so those namespaces are rebuilt as
+//! they were. The game's JSON has no namespaces, so they are worked
+//! out from the ids instead: two or more components whose ids share
+//! a dotted prefix, such as `mod.moth` and `mod.lantern`, are put in
+//! a `namespace` for it. References are written as the shortest
+//! name which resolves back to the same component.
+//!
+//! Members which Crucible has no statement for are written as `set`
+//! statements, so compiling the output gives back the same Lantern.
+//! If anything cannot be written that way, such as an id with spaces
+//! in it, every such problem is reported and nothing is written.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use either::Either;
+use mothlib::lantern::*;
+use tracing::{event, Level};
+use walkdir::WalkDir;
+
+use crate::parser::is_defkey_char;
+
+/// Words which a reference cannot be spelled as without being
+/// read as part of the statement around it. References to
+/// components with these names are written with a leading `.`.
+const KEYWORDS: &[&str] = &[
+    "aspect", "card", "deck", "recipe", "verb", "legacy", "ending", "namespace", "use", "import",
+    "const", "template", "for", "in", "from", "hidden", "set", "induce", "unique", "xtrigger",
+    "spawn", "mutate", "slot", "consume", "greedy", "craft", "hint", "max", "warmup", "apply",
+    "draw", "signal", "purge", "burn", "portal", "halt", "delete", "link", "goto", "if", "expel",
+    "table", "extant", "default", "as", "none", "grand", "melancholy", "pale", "vile", "important",
+    "true", "false", "null", "or", "roll",
+];
+
+/// The key that doc comments are stored under.
+const COMMENT: &str = "comment";
+
+/// Reads every input into a single mod and decompiles it. Inputs
+/// may be compiled `.lir` or `.lirc` files, the game's `.json`
+/// files, or directories, which are searched for `.json` files.
+pub fn run(inputs: &[PathBuf]) -> Result<String> {
+    let mut files = Vec::new();
+    for input in inputs {
+        if input.is_dir() {
+            for entry in WalkDir::new(input).sort_by_file_name() {
+                let entry = entry?;
+                if entry.file_type().is_file() && extension(entry.path()) == "json" {
+                    files.push(entry.into_path());
+                }
+            }
+        } else {
+            files.push(input.clone());
+        }
+    }
+
+    let compiled = files.iter().any(|file| matches!(extension(file).as_str(), "lir" | "lirc"));
+    if compiled && files.len() > 1 {
+        bail!("A compiled mod can only be decompiled on its own")
+    }
+    let mut lantern = Lantern::new();
+    for file in &files {
+        event!(Level::DEBUG, "Reading {}", file.display());
+        let bytes = std::fs::read(file).with_context(|| format!("Could not read `{}`", file.display()))?;
+        if compiled {
+            lantern = lir::decode(&bytes).with_context(|| format!("Invalid Lantern IR in `{}`", file.display()))?;
+            continue;
+        }
+        let text = String::from_utf8(bytes).with_context(|| format!("`{}` is not UTF-8", file.display()))?;
+        // The game's own files often start with a byte order mark.
+        let text = text.strip_prefix('\u{feff}').unwrap_or(&text);
+        let json: serde_json::Value =
+            serde_json::from_str(text).with_context(|| format!("Invalid JSON in `{}`", file.display()))?;
+        vanilla::read_json(&mut lantern, &json).with_context(|| format!("Could not read `{}`", file.display()))?;
+    }
+    if files.is_empty() {
+        bail!("No compiled mods or JSON files were found in the given paths")
+    }
+    decompile(&lantern)
+}
+
+fn extension(path: &Path) -> String {
+    path.extension().map_or_else(String::new, |ext| ext.to_string_lossy().to_ascii_lowercase())
+}
+
+/// Writes `lantern` as a single Crucible source file.
+pub fn decompile(lantern: &Lantern) -> Result<String> {
+    let root = if lantern.namespaces().is_empty() {
+        derived_scopes(lantern)
+    } else {
+        recorded_scopes(lantern)
+    };
+
+    let mut writer = Writer {
+        lantern,
+        declared: ids(lantern).into_iter().map(|id| id.0.clone()).collect(),
+        prefix: String::new(),
+        out: String::new(),
+        starts: HashSet::new(),
+        problems: Vec::new(),
+    };
+    for attribute in lantern.attributes() {
+        let attribute = writer.attribute(attribute);
+        writeln!(writer.out, "#![{}]", attribute)?;
+    }
+    if !lantern.attributes().is_empty() {
+        writer.out.push('\n');
+    }
+    writer.scope_contents(&root)?;
+
+    if !writer.problems.is_empty() {
+        for problem in &writer.problems {
+            event!(Level::ERROR, "{}", problem);
+        }
+        bail!("Found {} thing(s) which cannot be written in Crucible", writer.problems.len())
+    }
+    Ok(crate::fmt::format(&writer.out, &writer.starts))
+}
+
+/// The full id of every component in `lantern`.
+fn ids(lantern: &Lantern) -> Vec<&DefKey> {
+    let mut ids: Vec<&DefKey> = Vec::new();
+    ids.extend(lantern.aspects().keys());
+    ids.extend(lantern.cards().keys());
+    ids.extend(lantern.decks().keys());
+    ids.extend(lantern.verbs().keys());
+    ids.extend(lantern.recipes().keys());
+    ids.extend(lantern.legacies().keys());
+    ids.extend(lantern.endings().keys());
+    ids
+}
+
+/// Where components of each kind are written within a
+/// namespace, when there is no recorded order to follow.
+fn rank(lantern: &Lantern, id: &DefKey) -> usize {
+    let kinds = [
+        lantern.aspects().contains_key(id),
+        lantern.cards().contains_key(id),
+        lantern.decks().contains_key(id),
+        lantern.verbs().contains_key(id),
+        lantern.recipes().contains_key(id),
+        lantern.legacies().contains_key(id),
+        lantern.endings().contains_key(id),
+    ];
+    kinds.iter().position(|found| *found).unwrap_or(kinds.len())
+}
+
+/// Whether the only recipes that run in `verb` start there by being
+/// spawned, rather than by the player.
+fn spawned_into(lantern: &Lantern, verb: &DefKey) -> bool {
+    let recipes = || lantern.recipes().values().filter(|recipe| &recipe.verb == verb);
+    let spawned = |id: &DefKey| {
+        let mut branches = lantern.recipes().values().flat_map(|recipe| &recipe.branches);
+        branches.any(|branch| matches!(branch, Branch::Goto { target, action: Some(_), .. } if target == id))
+    };
+    !recipes().any(|recipe| recipe.craftable) && recipes().any(|recipe| spawned(&recipe.id))
+}
+
+fn join(prefix: &str, id: &str) -> String {
+    if prefix.is_empty() {
+        id.to_owned()
+    } else {
+        format!("{}.{}", prefix, id)
+    }
+}
+
+/// The namespace which contains `prefix`, or `None` at the root.
+fn parent(prefix: &str) -> Option<&str> {
+    match prefix.rsplit_once('.') {
+        Some((parent, _)) => Some(parent),
+        None if prefix.is_empty() => None,
+        None => Some(""),
+    }
+}
+
+/// A namespace to write, and what is declared directly inside it.
+#[derive(Default)]
+struct Scope {
+    /// The full path of the namespace, which is empty at the root.
+    path: String,
+    attributes: Vec<Attribute>,
+    /// The full ids of the components declared in this namespace.
+    components: Vec<DefKey>,
+    namespaces: Vec<Scope>,
+}
+
+/// Rebuilds the namespaces recorded by the compiler. Each one is
+/// written inside the closest recorded namespace containing it,
+/// and components which are not in any are written at the root.
+fn recorded_scopes(lantern: &Lantern) -> Scope {
+    let paths: Vec<&str> = lantern.namespaces().keys().map(|k| k.0.as_str()).filter(|p| !p.is_empty()).collect();
+    let container = |path: &str| -> String {
+        paths
+            .iter()
+            .filter(|outer| path.starts_with(&format!("{}.", outer)))
+            .max_by_key(|outer| outer.len())
+            .map_or_else(String::new, |outer| outer.to_string())
+    };
+    let mut children: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+    for path in &paths {
+        children.entry(container(path)).or_default().push(path);
+    }
+
+    fn build(lantern: &Lantern, path: &str, children: &mut BTreeMap<String, Vec<&str>>) -> Scope {
+        let meta = lantern.namespaces().get(&DefKey(path.to_owned())).cloned().unwrap_or_default();
+        let mut inner = children.remove(path).unwrap_or_default();
+        inner.sort_unstable();
+        Scope {
+            path: path.to_owned(),
+            attributes: meta.attributes,
+            components: meta.components,
+            namespaces: inner.into_iter().map(|inner| build(lantern, inner, children)).collect(),
+        }
+    }
+    let mut root = build(lantern, "", &mut children);
+
+    let listed: HashSet<&DefKey> = lantern.namespaces().values().flat_map(|meta| meta.components.iter()).collect();
+    let mut unlisted: Vec<&DefKey> = ids(lantern).into_iter().filter(|id| !listed.contains(id)).collect();
+    unlisted.sort_by(|a, b| (rank(lantern, a), &a.0).cmp(&(rank(lantern, b), &b.0)));
+    root.components.extend(unlisted.into_iter().cloned());
+    root
+}
+
+/// A tree of the dotted prefixes of component ids.
+#[derive(Default)]
+struct Prefixes {
+    /// Components whose id is this prefix followed by one more part.
+    components: Vec<DefKey>,
+    longer: BTreeMap<String, Prefixes>,
+}
+
+impl Prefixes {
+    fn count(&self) -> usize {
+        self.components.len() + self.longer.values().map(Prefixes::count).sum::<usize>()
+    }
+
+    fn into_ids(self) -> Vec<DefKey> {
+        let mut ids = self.components;
+        ids.extend(self.longer.into_values().flat_map(Prefixes::into_ids));
+        ids
+    }
+}
+
+/// Works out namespaces from the components' ids. A prefix shared
+/// by at least two components becomes a namespace, and a chain of
+/// namespaces with nothing else in them is written as one, as in
+/// `namespace a.b`.
+fn derived_scopes(lantern: &Lantern) -> Scope {
+    let mut root = Prefixes::default();
+    for id in ids(lantern) {
+        let parts: Vec<&str> = id.0.split('.').collect();
+        if parts.iter().any(|part| part.is_empty()) {
+            root.components.push(id.clone());
+            continue;
+        }
+        let mut node = &mut root;
+        for part in &parts[..parts.len() - 1] {
+            node = node.longer.entry(part.to_string()).or_default();
+        }
+        node.components.push(id.clone());
+    }
+
+    fn build(lantern: &Lantern, mut node: Prefixes, mut path: String) -> Scope {
+        while node.components.is_empty() && node.longer.len() == 1 && !path.is_empty() {
+            let part = node.longer.keys().next().cloned().unwrap_or_default();
+            path = join(&path, &part);
+            node = node.longer.remove(&part).unwrap_or_default();
+        }
+        let mut scope = Scope { path, components: node.components, ..Scope::default() };
+        for (part, inner) in node.longer {
+            if inner.count() < 2 {
+                scope.components.extend(inner.into_ids());
+            } else {
+                let inner_path = join(&scope.path, &part);
+                scope.namespaces.push(build(lantern, inner, inner_path));
+            }
+        }
+        scope.components.sort_by(|a, b| (rank(lantern, a), &a.0).cmp(&(rank(lantern, b), &b.0)));
+        scope
+    }
+    build(lantern, root, String::new())
+}
+
+struct Writer<'l> {
+    lantern: &'l Lantern,
+    /// The full id of every component, for checking what
+    /// a reference would resolve to.
+    declared: HashSet<String>,
+    /// The path of the namespace being written.
+    prefix: String,
+    out: String,
+    /// Where each unit starts in `out`, for the formatter.
+    starts: HashSet<usize>,
+    problems: Vec<String>,
+}
+
+impl Writer<'_> {
+    fn problem(&mut self, id: &DefKey, message: impl std::fmt::Display) {
+        self.problems.push(format!("`{}`: {}", id, message));
+    }
+
+    fn is_defkey(text: &str) -> bool {
+        !text.is_empty() && text.chars().all(is_defkey_char)
+    }
+
+    /// Writes `text` if it is a valid id, and reports it otherwise.
+    fn defkey(&mut self, owner: &DefKey, text: &str, what: &str) -> String {
+        if !Self::is_defkey(text) {
+            self.problem(owner, format!("the {} `{}` is not a valid id", what, text));
+        }
+        text.to_owned()
+    }
+
+    /// Would `name`, written inside the current namespace, resolve to `full`?
+    fn resolves_to(&self, name: &str, full: &str) -> bool {
+        let mut scope = Some(self.prefix.as_str());
+        while let Some(ns) = scope {
+            let candidate = join(ns, name);
+            if self.declared.contains(&candidate) {
+                return candidate == full;
+            }
+            scope = parent(ns);
+        }
+        false
+    }
+
+    /// Writes a reference to the component `full` as the shortest
+    /// name that resolves to it, or as `.full` if there is none.
+    fn reference(&mut self, owner: &DefKey, full: &DefKey) -> String {
+        let full = self.defkey(owner, &full.0, "reference");
+        if self.declared.contains(&full) {
+            let parts: Vec<&str> = full.split('.').collect();
+            for start in (0..parts.len()).rev() {
+                let name = parts[start..].join(".");
+                let usable = !name.starts_with('.')
+                    && !name.chars().all(|c| c.is_ascii_digit())
+                    && !KEYWORDS.iter().any(|kw| kw.eq_ignore_ascii_case(&name));
+                if usable && self.resolves_to(&name, &full) {
+                    return name;
+                }
+            }
+        }
+        format!(".{}", full)
+    }
+
+    /// Writes the id a component is declared with, relative
+    /// to the namespace it is written in.
+    fn declaration(&mut self, full: &DefKey) -> String {
+        let relative = match self.prefix.as_str() {
+            "" => full.0.as_str(),
+            prefix => full.0.strip_prefix(prefix).and_then(|id| id.strip_prefix('.')).unwrap_or(""),
+        };
+        if relative.is_empty() {
+            self.problem(full, format!("it cannot be declared inside `{}`", self.prefix));
+            return full.0.clone();
+        }
+        self.defkey(full, relative, "id")
+    }
+
+    fn attribute(&mut self, attribute: &Attribute) -> String {
+        let key = self.defkey(&attribute.key, &attribute.key.0, "attribute");
+        match &attribute.value {
+            Some(v) => format!("{} = {}", key, value(v)),
+            None => key,
+        }
+    }
+
+    fn scope_contents(&mut self, scope: &Scope) -> Result<()> {
+        let mut first = true;
+        for id in &scope.components {
+            if !first {
+                self.out.push('\n');
+            }
+            first = false;
+            self.component(id)?;
+        }
+        for inner in &scope.namespaces {
+            if !first {
+                self.out.push('\n');
+            }
+            first = false;
+            self.starts.insert(self.out.len());
+            for attribute in &inner.attributes {
+                let attribute = self.attribute(attribute);
+                writeln!(self.out, "#[{}]", attribute)?;
+            }
+            let name = inner.path.strip_prefix(&format!("{}.", scope.path)).unwrap_or(&inner.path);
+            let name = self.defkey(&DefKey(inner.path.clone()), name, "namespace");
+            writeln!(self.out, "namespace {} {{", name)?;
+            let outer = std::mem::replace(&mut self.prefix, inner.path.clone());
+            self.scope_contents(inner)?;
+            self.prefix = outer;
+            self.out.push_str("}\n");
+        }
+        Ok(())
+    }
+
+    /// Writes a component's doc comment and attributes, returning
+    /// its unmodelled members without the comment if it was written.
+    fn header(&mut self, id: &DefKey, others: &HashMap<DefKey, json::Value>) -> Result<HashMap<DefKey, json::Value>> {
+        self.starts.insert(self.out.len());
+        let mut others = others.clone();
+        let comment = DefKey(COMMENT.to_owned());
+        // A doc comment loses trailing whitespace, so
+        // any other comment is kept as a `set` instead.
+        if let Some(json::Value::Str(doc)) = others.get(&comment) {
+            let safe = doc.split('\n').all(|line| !line.contains('\r') && line.trim_end() == line);
+            if safe {
+                for line in doc.split('\n') {
+                    match line {
+                        "" => self.out.push_str("///\n"),
+                        line => writeln!(self.out, "/// {}", line)?,
+                    }
+                }
+                others.remove(&comment);
+            }
+        }
+        let attributes = self.lantern.component_attributes().get(id).cloned().unwrap_or_default();
+        for attribute in &attributes {
+            let attribute = self.attribute(attribute);
+            writeln!(self.out, "#[{}]", attribute)?;
+        }
+        Ok(others)
+    }
+
+    /// Writes `set` statements for unmodelled members, reporting any
+    /// whose key the component's own statements would claim instead.
+    fn sets(&mut self, id: &DefKey, others: &HashMap<DefKey, json::Value>, claimed: &[&str]) -> Vec<String> {
+        let mut keys: Vec<&DefKey> = others.keys().collect();
+        keys.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut statements = Vec::new();
+        for key in keys {
+            if claimed.contains(&key.0.as_str()) {
+                self.problem(id, format!("`{}` cannot be assigned with `set`", key));
+            }
+            let name = self.defkey(id, &key.0, "key");
+            statements.push(format!("set {} = {}", name, value(&others[key])));
+        }
+        statements
+    }
+
+    /// Writes a `{ ... }` block of statements, or nothing if
+    /// there are none and the block may be left out.
+    fn block(&mut self, statements: &[String], optional: bool) {
+        if statements.is_empty() && optional {
+            self.out.push('\n');
+            return;
+        }
+        self.out.push_str(" {\n");
+        for statement in statements {
+            self.out.push_str(statement);
+            self.out.push_str(";\n");
+        }
+        self.out.push_str("}\n");
+    }
+
+    fn component(&mut self, id: &DefKey) -> Result<()> {
+        let lantern = self.lantern;
+        if let Some(aspect) = lantern.aspects().get(id) {
+            self.aspect(aspect)
+        } else if let Some(card) = lantern.cards().get(id) {
+            self.card(card)
+        } else if let Some(deck) = lantern.decks().get(id) {
+            self.deck(deck)
+        } else if let Some(verb) = lantern.verbs().get(id) {
+            self.verb(verb)
+        } else if let Some(recipe) = lantern.recipes().get(id) {
+            self.recipe(recipe)
+        } else if let Some(legacy) = lantern.legacies().get(id) {
+            self.bare("legacy", &legacy.id, &legacy.others)
+        } else if let Some(ending) = lantern.endings().get(id) {
+            self.bare("ending", &ending.id, &ending.others)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn aspect(&mut self, aspect: &Aspect) -> Result<()> {
+        let id = &aspect.id;
+        let others = self.header(id, &aspect.others)?;
+        if aspect.hidden {
+            self.out.push_str("hidden ");
+        }
+        let name = self.declaration(id);
+        write!(self.out, "aspect {} {}", name, string(&aspect.label))?;
+        if !aspect.description.is_empty() {
+            write!(self.out, " {}", string(&aspect.description))?;
+        }
+        if let Some(decays_to) = &aspect.decays_to {
+            let target = self.reference(id, decays_to);
+            write!(self.out, " -> {}", target)?;
+        }
+
+        let mut statements = icons(&aspect.icon, &aspect.verbicon);
+        if let Some(induce) = self.induce(id, &aspect.induces) {
+            statements.push(induce);
+        }
+        statements.extend(aspect.xtriggers.iter().map(|x| self.xtrigger(id, x)).collect::<Vec<_>>());
+        statements.extend(self.sets(id, &others, &["id", "label", "description", "icon", "verbicon"]));
+        self.block(&statements, true);
+        Ok(())
+    }
+
+    fn card(&mut self, card: &Card) -> Result<()> {
+        let id = &card.id;
+        let others = self.header(id, &card.others)?;
+        if card.hidden {
+            self.out.push_str("hidden ");
+        }
+        let name = self.declaration(id);
+        write!(self.out, "card {} {}", name, string(&card.label))?;
+        if !card.description.is_empty() {
+            write!(self.out, " {}", string(&card.description))?;
+        }
+        let mut aspects: Vec<(&DefKey, &u32)> = card.aspects.iter().collect();
+        aspects.sort_by(|a, b| a.0 .0.cmp(&b.0 .0));
+        let aspects: Vec<String> = aspects.into_iter().map(|(aspect, n)| self.amount(id, aspect, *n as i64)).collect();
+        write!(self.out, " ({})", aspects.join(", "))?;
+        match (&card.decays_to, card.lifetime) {
+            (Some(decays_to), lifetime) => {
+                let target = self.reference(id, decays_to);
+                write!(self.out, " -> {}", target)?;
+                if let Some(lifetime) = lifetime {
+                    write!(self.out, " {}", lifetime)?;
+                }
+            }
+            (None, Some(lifetime)) => write!(self.out, " -> {}", lifetime)?,
+            (None, None) => (),
+        }
+
+        let mut statements = icons(&card.icon, &card.verbicon);
+        if card.resaturate {
+            statements.push("set resaturate = true".to_owned());
+        }
+        if let Some(induce) = self.induce(id, &card.induces) {
+            statements.push(induce);
+        }
+        if card.unique {
+            statements.push("unique".to_owned());
+        }
+        if let Some(group) = &card.uniqueness_group {
+            let group = self.reference(id, group);
+            statements.push(format!("unique {}", group));
+        }
+        let mut verbs: Vec<&DefKey> = card.slots.keys().collect();
+        verbs.sort_by(|a, b| a.0.cmp(&b.0));
+        for verb in verbs {
+            let name = self.reference(id, verb);
+            for slot in &card.slots[verb] {
+                let slot = self.slot(id, slot);
+                statements.push(format!("{} -> {}", name, slot));
+            }
+        }
+        statements.extend(card.xtriggers.iter().map(|x| self.xtrigger(id, x)).collect::<Vec<_>>());
+        let claimed = ["id", "label", "description", "icon", "verbicon", "resaturate"];
+        statements.extend(self.sets(id, &others, &claimed));
+        self.block(&statements, false);
+        Ok(())
+    }
+
+    fn deck(&mut self, deck: &Deck) -> Result<()> {
+        let id = &deck.id;
+        let others = self.header(id, &deck.others)?;
+        let name = self.declaration(id);
+        write!(self.out, "deck {}", name)?;
+        if !deck.label.is_empty() || !deck.description.is_empty() {
+            write!(self.out, " {}", string(&deck.label))?;
+        }
+        if !deck.description.is_empty() {
+            write!(self.out, " {}", string(&deck.description))?;
+        }
+
+        let mut statements = self.cards(id, deck);
+        statements.extend(self.sets(id, &others, &["id", "label", "description"]));
+        self.block(&statements, false);
+        Ok(())
+    }
+
+    /// Writes the cards of a deck, with `* n` after a card
+    /// followed by copies of itself.
+    fn cards(&mut self, owner: &DefKey, deck: &Deck) -> Vec<String> {
+        let mut default = deck.default.as_ref();
+        if default.map_or(false, |card| !deck.cards.iter().any(|(c, _)| c == card)) {
+            self.problem(owner, "its default card is not one of its cards");
+        }
+        let has_messages = deck.cards.iter().any(|(_, message)| message.is_some());
+        if deck.is_portal_deck != has_messages {
+            self.problem(owner, "it can only be a portal deck if its cards have draw messages");
+        }
+        let mut entries = Vec::new();
+        let mut rest = &deck.cards[..];
+        while let Some(first @ (card, message)) = rest.first() {
+            let copies = rest.iter().take_while(|&entry| entry == first).count();
+            rest = &rest[copies..];
+            let mut entry = String::new();
+            // Only the first copy of the default card is marked.
+            if default == Some(card) {
+                entry.push('!');
+                default = None;
+            }
+            entry.push_str(&self.reference(owner, card));
+            if copies > 1 {
+                entry.push_str(&format!(" * {}", copies));
+            }
+            if let Some(message) = message {
+                entry.push_str(&format!(" {}", string(message)));
+            }
+            entries.push(entry);
+        }
+        entries
+    }
+
+    /// Writes `draw { .. } n` for a recipe's own deck.
+    fn internal_deck(&mut self, owner: &DefKey, deck: &Deck, amount: i32) -> String {
+        let mut others = deck.others.clone();
+        let reset = DefKey("resetonexhaustion".to_owned());
+        let once = deck.default.is_none() && others.get(&reset) == Some(&json::Value::Boolean(false));
+        if once {
+            others.remove(&reset);
+        }
+        if !deck.label.is_empty() || !deck.description.is_empty() || !others.is_empty() {
+            self.problem(owner, "its internal deck has fields which cannot be written in Crucible");
+        }
+        let cards = self.cards(owner, deck);
+        let once = if once { "once " } else { "" };
+        match amount {
+            1 => format!("draw {}{{ {} }}", once, cards.join(", ")),
+            n => format!("draw {}{{ {} }} {}", once, cards.join(", "), n),
+        }
+    }
+
+    fn verb(&mut self, verb: &Verb) -> Result<()> {
+        let id = &verb.id;
+        let others = self.header(id, &verb.others)?;
+        // Such a verb was most likely written inline with `in verb`,
+        // so it was never meant to have a craftable recipe.
+        if spawned_into(self.lantern, id) {
+            writeln!(self.out, "#[allow(uncraftable_verb)]")?;
+        }
+        let name = self.declaration(id);
+        write!(self.out, "verb {} {} {}", name, string(&verb.label), string(&verb.description))?;
+        if let Some(slot) = &verb.slot {
+            let slot = self.slot(id, slot);
+            write!(self.out, " ({})", slot)?;
+        }
+        let statements = self.sets(id, &others, &["id", "label", "description"]);
+        self.block(&statements, true);
+        Ok(())
+    }
+
+    fn bare(&mut self, kind: &str, id: &DefKey, others: &HashMap<DefKey, json::Value>) -> Result<()> {
+        let others = self.header(id, others)?;
+        let name = self.declaration(id);
+        write!(self.out, "{} {}", kind, name)?;
+        let statements = self.sets(id, &others, &["id"]);
+        self.block(&statements, true);
+        Ok(())
+    }
+
+    fn recipe(&mut self, recipe: &Recipe) -> Result<()> {
+        let id = &recipe.id;
+        let others = self.header(id, &recipe.others)?;
+        if recipe.craftable {
+            self.out.push_str("craft ");
+        }
+        if recipe.hint_only {
+            self.out.push_str("hint ");
+        }
+        let verb = self.reference(id, &recipe.verb);
+        let name = self.declaration(id);
+        let requirements = self.requirements(id, &recipe.requirements);
+        write!(self.out, "{} recipe {} ({})", verb, name, requirements)?;
+        if recipe.max_executions > 0 {
+            write!(self.out, " max {}", recipe.max_executions)?;
+        }
+
+        let mut statements = Vec::new();
+        for (key, text) in [("label", &recipe.label), ("description", &recipe.description), ("end_description", &recipe.end_description)] {
+            if !text.is_empty() {
+                statements.push(format!("set {} = {}", key, value(&json::Value::Str(text.clone()))));
+            }
+        }
+        if recipe.warmup > 0 {
+            statements.push(format!("warmup {}", recipe.warmup));
+        }
+        if let Some(slot) = &recipe.slot {
+            statements.push(self.slot(id, slot));
+        }
+        for (element, operation) in sorted(&recipe.effects) {
+            let element = self.reference(id, element);
+            statements.push(format!("apply {} {}", element, operation_text(operation)));
+        }
+        for mutation in &recipe.mutations {
+            let target = self.reference(id, &mutation.id);
+            let aspect = self.reference(id, &mutation.aspect);
+            statements.push(format!("apply {} {} {}", target, aspect, operation_text(&mutation.operation)));
+        }
+        for (element, amount) in sorted(&recipe.purge) {
+            statements.push(format!("purge {}", self.counted(id, element, *amount as i64)));
+        }
+        for (deck, amount) in sorted(&recipe.draws) {
+            statements.push(format!("draw {}", self.counted(id, deck, *amount as i64)));
+        }
+        if let Some((deck, amount)) = &recipe.internal_deck {
+            statements.push(self.internal_deck(id, deck, *amount));
+        }
+        for (verb, amount) in recipe.halt.as_ref().map(sorted).unwrap_or_default() {
+            statements.push(format!("halt {}", self.counted(id, verb, *amount as i64)));
+        }
+        for (verb, amount) in recipe.delete.as_ref().map(sorted).unwrap_or_default() {
+            statements.push(format!("delete {}", self.counted(id, verb, *amount as i64)));
+        }
+        let style = match recipe.style {
+            WarmupStyle::None => None,
+            WarmupStyle::Grand => Some("grand"),
+            WarmupStyle::Mellancholy => Some("melancholy"),
+            WarmupStyle::Pale => Some("pale"),
+            WarmupStyle::Vile => Some("vile"),
+            WarmupStyle::Important => Some("important"),
+        };
+        if let Some(style) = style {
+            statements.push(format!("signal {}", style));
+        }
+        for (statement, image) in [("burn", &recipe.burn), ("portal", &recipe.portal)] {
+            if let Some(image) = image {
+                let image = self.defkey(id, image, statement);
+                statements.push(format!("{} {}", statement, image));
+            }
+        }
+        if let Some(ending) = &recipe.ending {
+            statements.push(format!("ending {}", self.reference(id, ending)));
+        }
+        for branch in &recipe.branches {
+            statements.push(self.branch(id, branch));
+        }
+        if !recipe.aspects.is_empty() {
+            self.problem(id, "changes to its aspects cannot be written in Crucible");
+        }
+        let claimed = ["id", "verb", "label", "description", "end_description"];
+        statements.extend(self.sets(id, &others, &claimed));
+        self.block(&statements, false);
+        Ok(())
+    }
+
+    /// Writes `element:n`, leaving out an amount of 1.
+    fn amount(&mut self, owner: &DefKey, element: &DefKey, amount: i64) -> String {
+        let element = self.reference(owner, element);
+        match amount {
+            1 => element,
+            n => format!("{}:{}", element, n),
+        }
+    }
+
+    /// Writes `element n`, leaving out an amount of 1.
+    fn counted(&mut self, owner: &DefKey, element: &DefKey, amount: i64) -> String {
+        let element = self.reference(owner, element);
+        match amount {
+            1 => element,
+            n => format!("{} {}", element, n),
+        }
+    }
+
+    fn requirements(&mut self, owner: &DefKey, requirements: &[RecipeRequirement]) -> String {
+        let mut written = Vec::new();
+        for requirement in requirements {
+            let (qualifier, element, amount) = match requirement {
+                RecipeRequirement::Basic { element, amount } => ("", element, amount),
+                RecipeRequirement::Table { element, amount } => ("table ", element, amount),
+                RecipeRequirement::Extant { element, amount } => ("extant ", element, amount),
+            };
+            let amount = match amount {
+                Either::Left(ValueOperation::Add(n)) => *n as i64,
+                Either::Right(other) => {
+                    let compared = format!("{} >= {}", self.reference(owner, element), self.reference(owner, other));
+                    written.push(format!("{}{}", qualifier, compared));
+                    continue;
+                }
+                _ => {
+                    self.problem(owner, format!("its requirement on `{}` is not a plain amount", element));
+                    continue;
+                }
+            };
+            let requirement = self.amount(owner, element, amount);
+            written.push(format!("{}{}", qualifier, requirement));
+        }
+        written.join(", ")
+    }
+
+    fn branch(&mut self, owner: &DefKey, branch: &Branch) -> String {
+        let (keyword, target, condition, action) = match branch {
+            Branch::Link { target, condition } => ("link", target, condition, None),
+            Branch::Goto { target, condition, action } => ("goto", target, condition, action.as_ref()),
+        };
+        let mut statement = format!("{} {}", keyword, self.reference(owner, target));
+        let chance = condition.chance.map(|chance| format!("{}%", *chance));
+        if condition.requirements.is_empty() {
+            if let Some(chance) = chance {
+                statement.push(' ');
+                statement.push_str(&chance);
+            }
+        } else {
+            let requirements = self.requirements(owner, &condition.requirements);
+            statement.push_str(" if ");
+            if let Some(chance) = chance {
+                statement.push_str(&chance);
+                statement.push(' ');
+            }
+            statement.push_str(&format!("({})", requirements));
+        }
+        match action {
+            Some(SpawningKind::Spawn) => statement.push_str(" -> spawn"),
+            Some(SpawningKind::Expel(elements)) if elements.is_empty() => statement.push_str(" -> expel"),
+            Some(SpawningKind::Expel(elements)) => {
+                let elements: Vec<String> = elements.iter().map(|(e, n)| self.amount(owner, e, *n as i64)).collect();
+                statement.push_str(&format!(" -> expel {{{}}}", elements.join(", ")));
+            }
+            None => (),
+        }
+        statement
+    }
+
+    fn induce(&mut self, owner: &DefKey, induces: &Option<(DefKey, Probability)>) -> Option<String> {
+        let (target, chance) = induces.as_ref()?;
+        let target = self.reference(owner, target);
+        Some(format!("induce {}{}", target, chance_text(*chance)))
+    }
+
+    fn xtrigger(&mut self, owner: &DefKey, xtrigger: &Xtrigger) -> String {
+        let (catalyst, effect, chance) = match xtrigger {
+            Xtrigger::Transform { catalyst, transforms_to, amount: 1, chance } => {
+                (catalyst, self.reference(owner, transforms_to), chance)
+            }
+            Xtrigger::Transform { catalyst, transforms_to, amount, chance } => {
+                (catalyst, format!("{}:{}", self.reference(owner, transforms_to), amount), chance)
+            }
+            Xtrigger::Spawn { catalyst, creates, amount, chance } => {
+                (catalyst, format!("spawn {}:{}", self.reference(owner, creates), amount), chance)
+            }
+            Xtrigger::Mutate { catalyst, adds_to_catalyst, amount, chance } => {
+                (catalyst, format!("mutate {}:{}", self.reference(owner, adds_to_catalyst), amount), chance)
+            }
+        };
+        let catalyst = self.reference(owner, catalyst);
+        format!("xtrigger {} -> {}{}", catalyst, effect, chance_text(*chance))
+    }
+
+    fn slot(&mut self, owner: &DefKey, slot: &Slot) -> String {
+        let mut text = String::new();
+        if slot.consumes {
+            text.push_str("consume ");
+        }
+        if slot.greedy {
+            text.push_str("greedy ");
+        }
+        let name = self.defkey(owner, &slot.id.0, "slot id");
+        text.push_str(&format!("slot {} {} {}", name, string(&slot.label), string(&slot.description)));
+        if !slot.requirements.is_empty() {
+            let filters: Vec<String> = slot
+                .requirements
+                .iter()
+                .map(|filter| match filter {
+                    SlotFilter::Accept { element, amount } => self.amount(owner, element, *amount as i64),
+                    SlotFilter::Forbid { element, amount } => format!("!{}", self.amount(owner, element, *amount as i64)),
+                })
+                .collect();
+            text.push_str(&format!(" ({})", filters.join(", ")));
+        }
+        text
+    }
+}
+
+fn icons(icon: &Option<String>, verbicon: &Option<String>) -> Vec<String> {
+    let mut statements = Vec::new();
+    for (key, icon) in [("icon", icon), ("verbicon", verbicon)] {
+        if let Some(icon) = icon {
+            statements.push(format!("set {} = {}", key, value(&json::Value::Str(icon.clone()))));
+        }
+    }
+    statements
+}
+
+fn sorted<V>(map: &HashMap<DefKey, V>) -> Vec<(&DefKey, &V)> {
+    let mut entries: Vec<(&DefKey, &V)> = map.iter().collect();
+    entries.sort_by(|a, b| a.0 .0.cmp(&b.0 .0));
+    entries
+}
+
+fn chance_text(chance: Probability) -> String {
+    match *chance {
+        100 => String::new(),
+        n => format!(" {}%", n),
+    }
+}
+
+fn operation_text(operation: &ValueOperation) -> String {
+    match operation {
+        ValueOperation::Set(n) => format!("= {}", n),
+        // `-= n` negates n, which `i32::MIN` cannot be.
+        ValueOperation::Add(n) if *n < 0 && *n != i32::MIN => format!("-= {}", -n),
+        ValueOperation::Add(n) => format!("+= {}", n),
+    }
+}
+
+/// Writes a string literal in Crucible's syntax.
+fn string(text: &str) -> String {
+    let mut out = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Writes a value in the JSON syntax used by `set` and attributes.
+fn value(v: &json::Value) -> String {
+    vanilla::value(v).to_string()
+}
@@ -0,0 +1,491 @@
+//! Source locations and compiler diagnostics.
+//!
+//! Every file Crucible reads is registered in a [SourceMap],
+//! and every construct it parses remembers the [Span] of
+//! source text it came from. Problems found at any stage of
+//! compilation are reported as [Diagnostic]s, which carry a
+//! stable [Code] and render with the offending source line
+//! and a caret underneath it.
+
+use std::fmt::{self, Display};
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+
+/// An index into a [SourceMap].
+pub type FileId = usize;
+
+/// A half-open range of bytes within a single source file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    pub file: FileId,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(file: FileId, start: usize, end: usize) -> Self {
+        Span { file, start, end }
+    }
+
+    /// Returns the smallest span that covers both `self` and `other`.
+    /// Both spans must belong to the same file.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            file: self.file,
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+}
+
+/// A value paired with the span of source text it was parsed from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Spanned<T> {
+    pub item: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(item: T, span: Span) -> Self {
+        Spanned { item, span }
+    }
+}
+
+impl<T> Deref for Spanned<T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        &self.item
+    }
+}
+
+impl<T> DerefMut for Spanned<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.item
+    }
+}
+
+impl<T: Display> Display for Spanned<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.item.fmt(f)
+    }
+}
+
+/// The text of a single source file, along with
+/// the byte offset of the start of each of its lines.
+#[derive(Debug)]
+pub struct SourceFile {
+    path: PathBuf,
+    text: String,
+    line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+    pub fn new(path: PathBuf, text: String) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        SourceFile { path, text, line_starts }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Converts a byte offset into a 1-based `(line, column)` pair.
+    /// Columns are counted in characters, not bytes.
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.min(self.text.len());
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+        let col = self.text[self.line_starts[line]..offset].chars().count();
+        (line + 1, col + 1)
+    }
+
+    /// Returns the text of the given 1-based line, without its line ending.
+    pub fn line(&self, line: usize) -> &str {
+        let start = self.line_starts[line - 1];
+        let end = self
+            .line_starts
+            .get(line)
+            .copied()
+            .unwrap_or(self.text.len());
+        self.text[start..end].trim_end_matches(['\n', '\r'])
+    }
+}
+
+/// Owns the text of every file read during a compilation,
+/// so that spans can be resolved back to lines and columns.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap { files: Vec::new() }
+    }
+
+    pub fn add(&mut self, path: PathBuf, text: String) -> FileId {
+        self.files.push(SourceFile::new(path, text));
+        self.files.len() - 1
+    }
+
+    pub fn get(&self, id: FileId) -> &SourceFile {
+        &self.files[id]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (FileId, &SourceFile)> {
+        self.files.iter().enumerate()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+/// Stable identifiers for each kind of problem Crucible reports.
+/// Codes are never renumbered or reused, so that they can be
+/// searched for and referred to in documentation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Code {
+    /// The source text does not match the Crucible grammar.
+    Syntax,
+    /// `set` was used on a key that is part of the component's signature.
+    ReservedKey,
+    /// A key was assigned with `set` more than once.
+    DuplicateKey,
+    /// A value does not have the type its key requires.
+    MismatchedType,
+    /// A statement that may only appear once in a component was repeated.
+    DuplicateStatement,
+    /// The same aspect was listed more than once on a card.
+    DuplicateAspect,
+    /// A deck declared more than one default card.
+    DuplicateDefault,
+    /// A file reference names a file that could not be read.
+    UnreadableFile,
+    /// A file reference names a `.json` file that is not valid JSON.
+    InvalidJsonFile,
+    /// A file imports itself, directly or through other files.
+    ImportCycle,
+    /// A constant or template parameter is used but was never declared.
+    UnresolvedConstant,
+    /// A number does not fit where it is used, or an
+    /// expression overflows or divides by zero.
+    NumberOutOfRange,
+    /// An instantiation names a template that has not been declared.
+    UnresolvedTemplate,
+    /// A template was given the wrong number of arguments.
+    MismatchedArguments,
+    /// A template instantiates itself, directly or through others.
+    RecursiveTemplate,
+    /// Requirements on an element can never all be met.
+    UnsatisfiableRequirement,
+    /// Requirements on an element can't be written the way
+    /// the game stores them, such as an exact amount.
+    UnsupportedRequirement,
+    /// A `roll` holds both `link`s and `goto`s.
+    MixedRoll,
+    /// A temporary verb is given to a recipe that isn't spawned.
+    UnspawnedTemporaryVerb,
+    /// A reference does not name any component in scope.
+    UnresolvedReference,
+    /// Two components were declared with the same full id.
+    DuplicateDefinition,
+    /// A `use` directive names something that was never declared.
+    UnresolvedUse,
+    /// A component inherits from something it cannot inherit from,
+    /// such as a component of another kind.
+    InvalidInheritance,
+    /// A component inherits from itself, directly or through others.
+    InheritanceCycle,
+    /// A reference names a component of a kind that
+    /// can't be used where the reference is, such as
+    /// a recipe drawing from a card instead of a deck.
+    MismatchedKind,
+    /// A lint attribute names a lint that doesn't exist.
+    UnknownLint,
+    /// The weights of a `roll` can't be turned into
+    /// whole percentage chances, so they were rounded.
+    InexactRoll,
+    /// One way of meeting a requirement list with an `or` can
+    /// never hold, so it is left out.
+    UnsatisfiableAlternative,
+    /// An aspect is never referred to by anything in the mod.
+    UnusedAspect,
+    /// A card that isn't hidden has no `icon`.
+    MissingArt,
+    /// A recipe with no warmup is never linked to or induced.
+    UnreachableRecipe,
+    /// A deck is never drawn from.
+    UndrawnDeck,
+    /// A card's uniqueness group isn't an aspect declared in the mod.
+    UndeclaredUniquenessGroup,
+    /// An xtrigger's catalyst is never on any card in the mod.
+    UnusedCatalyst,
+    /// No craftable recipe in the mod runs in a verb.
+    UncraftableVerb,
+}
+
+impl Code {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Code::Syntax => "E0001",
+            Code::ReservedKey => "E0101",
+            Code::DuplicateKey => "E0102",
+            Code::MismatchedType => "E0103",
+            Code::DuplicateStatement => "E0104",
+            Code::DuplicateAspect => "E0105",
+            Code::DuplicateDefault => "E0106",
+            Code::UnreadableFile => "E0107",
+            Code::InvalidJsonFile => "E0108",
+            Code::ImportCycle => "E0109",
+            Code::UnresolvedConstant => "E0110",
+            Code::NumberOutOfRange => "E0111",
+            Code::UnresolvedTemplate => "E0112",
+            Code::MismatchedArguments => "E0113",
+            Code::RecursiveTemplate => "E0114",
+            Code::UnsatisfiableRequirement => "E0115",
+            Code::UnsupportedRequirement => "E0116",
+            Code::MixedRoll => "E0117",
+            Code::UnspawnedTemporaryVerb => "E0118",
+            Code::UnresolvedReference => "E0201",
+            Code::DuplicateDefinition => "E0202",
+            Code::UnresolvedUse => "E0203",
+            Code::InvalidInheritance => "E0204",
+            Code::InheritanceCycle => "E0205",
+            Code::MismatchedKind => "E0206",
+            Code::UnknownLint => "W0001",
+            Code::InexactRoll => "W0002",
+            Code::UnsatisfiableAlternative => "W0003",
+            Code::UnusedAspect => "W0101",
+            Code::MissingArt => "W0102",
+            Code::UnreachableRecipe => "W0103",
+            Code::UndrawnDeck => "W0104",
+            Code::UndeclaredUniquenessGroup => "W0105",
+            Code::UnusedCatalyst => "W0106",
+            Code::UncraftableVerb => "W0107",
+        }
+    }
+}
+
+impl Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A secondary message attached to some other location
+/// relevant to a [Diagnostic].
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+/// A single problem found in the source, pointing at the
+/// text responsible for it.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Code,
+    pub message: String,
+    pub span: Span,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(code: Code, span: Span, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code,
+            message: message.into(),
+            span,
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn warning(code: Code, span: Span, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(code, span, message)
+        }
+    }
+
+    /// Points at another location that helps explain this diagnostic.
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    /// Appends a free-standing note to the end of this diagnostic.
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Points out the instantiation of `template` at `site`, for
+    /// a problem found in the units it produced.
+    pub fn in_expansion(self, template: &str, site: Span) -> Self {
+        self.with_label(site, format!("in this instantiation of `{}`", template))
+    }
+
+    /// Points out the iteration of a loop over `variable` at `site`,
+    /// in which it was `value`, for a problem found in that iteration.
+    pub fn in_iteration(self, variable: &str, value: &str, site: Span) -> Self {
+        self.with_label(site, format!("in the iteration where `{}` is `{}`", variable, value))
+    }
+
+    /// Whether this says the same as `other`, about the same places.
+    pub fn repeats(&self, other: &Diagnostic) -> bool {
+        self.span == other.span
+            && self.message == other.message
+            && self.labels
+                .iter()
+                .map(|l| (l.span, &l.message))
+                .eq(other.labels.iter().map(|l| (l.span, &l.message)))
+    }
+
+    /// Returns a value which renders this diagnostic
+    /// against the source text in `sources`.
+    pub fn display<'a>(&'a self, sources: &'a SourceMap) -> DisplayDiagnostic<'a> {
+        DisplayDiagnostic {
+            diagnostic: self,
+            sources,
+        }
+    }
+}
+
+/// Renders a [Diagnostic] in the same layout rustc uses:
+///
+/// ```text
+/// error[E0102]: key `icon` is already assigned in this aspect
+///   --> src/aspects.crucible:12:9
+///    |
+/// 12 |         set icon = "lantern2";
+///    |         ^^^^^^^^^^^^^^^^^^^^^
+///    = note: ...
+/// ```
+pub struct DisplayDiagnostic<'a> {
+    diagnostic: &'a Diagnostic,
+    sources: &'a SourceMap,
+}
+
+impl DisplayDiagnostic<'_> {
+    fn snippet(&self, f: &mut fmt::Formatter<'_>, span: Span, message: &str, gutter: usize, arrow: &str) -> fmt::Result {
+        let file = self.sources.get(span.file);
+        let (line, col) = file.line_col(span.start);
+        let (end_line, end_col) = file.line_col(span.end);
+        let text = file.line(line);
+        // Spans covering several lines are underlined up to the end of their first line.
+        let width = if end_line == line {
+            end_col.saturating_sub(col).max(1)
+        } else {
+            text.chars().count().saturating_sub(col - 1).max(1)
+        };
+        writeln!(f, "{:>gutter$}{} {}:{}:{}", "", arrow, file.path().display(), line, col)?;
+        writeln!(f, "{:>gutter$} |", "")?;
+        writeln!(f, "{:>gutter$} | {}", line, text)?;
+        write!(f, "{:>gutter$} | {}{}", "", " ".repeat(col - 1), "^".repeat(width))?;
+        if message.is_empty() {
+            writeln!(f)
+        } else {
+            writeln!(f, " {}", message)
+        }
+    }
+}
+
+impl Display for DisplayDiagnostic<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let d = self.diagnostic;
+        let gutter = std::iter::once(d.span)
+            .chain(d.labels.iter().map(|l| l.span))
+            .map(|s| self.sources.get(s.file).line_col(s.start).0.to_string().len())
+            .max()
+            .unwrap_or(1);
+
+        writeln!(f, "{}[{}]: {}", d.severity, d.code, d.message)?;
+        self.snippet(f, d.span, "", gutter, "-->")?;
+        for label in &d.labels {
+            self.snippet(f, label.span, &label.message, gutter, ":::")?;
+        }
+        for note in &d.notes {
+            writeln!(f, "{:>gutter$} = note: {}", "", note)?;
+        }
+        Ok(())
+    }
+}
+
+/// Collects every [Diagnostic] produced during a compilation
+/// so that they can all be reported at once.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    items: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Diagnostics { items: Vec::new() }
+    }
+
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.items.push(diagnostic);
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.error_count() > 0
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.items
+            .iter()
+            .filter(|d| d.severity == Severity::Error)
+            .count()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.items.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+impl Extend<Diagnostic> for Diagnostics {
+    fn extend<T: IntoIterator<Item = Diagnostic>>(&mut self, iter: T) {
+        self.items.extend(iter)
+    }
+}
+
+impl IntoIterator for Diagnostics {
+    type Item = Diagnostic;
+    type IntoIter = std::vec::IntoIter<Diagnostic>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.items.into_iter()
+    }
+}
@@ -0,0 +1,103 @@
+//! Dumps of the compiler's intermediate stages, for `--emit`.
+//!
+//! Every dump can be narrowed down to a single component
+//! by giving its full id, e.g. `core.aspects.lantern`.
+
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use mothlib::lantern::{vanilla, Lantern};
+use serde_json::Value;
+
+use crate::parser::Unit;
+
+/// A stage of compilation which can be printed instead of
+/// writing the compiled mod.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Stage {
+    /// The syntax tree, as parsed.
+    Ast,
+    /// The syntax tree after name resolution and inheritance.
+    Resolved,
+    /// The mod lowered to Lantern IR, as JSON.
+    Lantern,
+    /// The mod as the JSON read by Cultist Simulator.
+    Json,
+}
+
+/// Prints a syntax tree. Before resolution, component ids are
+/// relative to their namespace, so `resolved` says whether the
+/// namespace prefix still has to be added to match `filter`.
+pub fn units(units: &[Unit], resolved: bool, filter: Option<&str>) -> Result<String> {
+    let filter = match filter {
+        Some(filter) => filter,
+        None => return Ok(format!("{:#?}\n", units)),
+    };
+    match find_unit(units, "", resolved, filter) {
+        Some(unit) => Ok(format!("{:#?}\n", unit)),
+        None => not_found(filter),
+    }
+}
+
+fn find_unit<'u>(units: &'u [Unit], prefix: &str, resolved: bool, filter: &str) -> Option<&'u Unit> {
+    let join = |id: &str| {
+        if prefix.is_empty() {
+            id.to_owned()
+        } else {
+            format!("{}.{}", prefix, id)
+        }
+    };
+    units.iter().find_map(|unit| match unit {
+        Unit::Namespace { id, units, .. } => find_unit(units, &join(&id.0), resolved, filter),
+        Unit::Component { id, .. } => {
+            let full = if resolved { id.0.clone() } else { join(&id.0) };
+            (full == filter).then_some(unit)
+        }
+        Unit::Expansion { units, .. } => find_unit(units, prefix, resolved, filter),
+        Unit::For { iterations, .. } => iterations
+            .iter()
+            .find_map(|iteration| find_unit(&iteration.units, prefix, resolved, filter)),
+        Unit::Use { .. } | Unit::Const { .. } | Unit::Template { .. } => None,
+    })
+}
+
+/// Prints the Lantern IR of a mod, or of a single component.
+pub fn lantern(lantern: &Lantern, filter: Option<&str>) -> Result<String> {
+    let value = serde_json::to_value(lantern)?;
+    let value = match filter {
+        None => value,
+        Some(filter) => {
+            let kinds = ["aspects", "cards", "decks", "recipes", "verbs", "legacies", "endings"];
+            match kinds.iter().find_map(|kind| value[*kind].get(filter)) {
+                Some(component) => component.clone(),
+                None => return not_found(filter),
+            }
+        }
+    };
+    Ok(format!("{}\n", serde_json::to_string_pretty(&value)?))
+}
+
+/// Prints the game's JSON for a mod, or for a single component.
+pub fn json(lantern: &Lantern, filter: Option<&str>) -> Result<String> {
+    let value = vanilla::to_json(lantern);
+    let value = match filter {
+        None => value,
+        Some(filter) => {
+            let found = value
+                .as_object()
+                .into_iter()
+                .flat_map(|lists| lists.values())
+                .filter_map(Value::as_array)
+                .flatten()
+                .find(|component| component["id"] == filter);
+            match found {
+                Some(component) => component.clone(),
+                None => return not_found(filter),
+            }
+        }
+    };
+    Ok(format!("{}\n", serde_json::to_string_pretty(&value)?))
+}
+
+fn not_found<T>(id: &str) -> Result<T> {
+    bail!("No component with the id `{}` was found", id)
+}
@@ -0,0 +1,576 @@
+//! The canonical source formatter, run with `crucible fmt`.
+//!
+//! Formatting only ever changes the whitespace between tokens,
+//! along with the spelling of component modifiers, so comments
+//! are kept and the compiled mod is always the same. The layout
+//! it produces is:
+//!
+//! - Four spaces of indentation for each level of `namespace`,
+//!   statement block or broken list.
+//! - Every unit, attribute and statement on a line of its own,
+//!   keeping at most one blank line wherever there were several.
+//! - Lists such as aspect lists and JSON values on a single line
+//!   where they fit, and otherwise broken into one entry per line.
+//!   A list which starts with a line break is always broken, so
+//!   that long JSON values can be kept spread out.
+//! - `hidden`, `consume`, `greedy`, `craft` and `hint` written as
+//!   words rather than as `?` and `!`, in that order.
+//!
+//! Files with syntax errors are left alone, since there is no way
+//! to know how they were meant to be laid out.
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use tracing::{event, Level};
+
+use crate::diagnostic::{Code, Diagnostics, FileId, SourceMap};
+use crate::parser::{self, Unit};
+
+/// Lists which would make a line longer than this are broken.
+const MAX_WIDTH: usize = 100;
+const INDENT: &str = "    ";
+
+/// Formats every file in `files` and everything they import.
+/// With `check`, files are only compared against their
+/// formatted form, and any that differ are an error.
+pub fn run(files: &[PathBuf], check: bool) -> Result<()> {
+    let mut sources = SourceMap::new();
+    let mut diagnostics = Diagnostics::new();
+    let crucible = parser::parse(files, &mut sources, &mut diagnostics)?;
+
+    let broken: HashSet<FileId> = diagnostics
+        .iter()
+        .filter(|d| d.code == Code::Syntax)
+        .map(|d| d.span.file)
+        .collect();
+    for diagnostic in diagnostics.iter().filter(|d| broken.contains(&d.span.file)) {
+        eprintln!("{}", diagnostic.display(&sources));
+    }
+
+    let mut starts = HashSet::new();
+    unit_starts(crucible.units(), &mut starts);
+
+    let mut changed = 0;
+    for (id, file) in sources.iter() {
+        if broken.contains(&id) {
+            event!(Level::WARN, "Skipping {}, which has syntax errors", file.path().display());
+            continue;
+        }
+        let starts: HashSet<usize> = starts
+            .iter()
+            .filter(|(file, _)| *file == id)
+            .map(|(_, start)| *start)
+            .collect();
+        let formatted = format(file.text(), &starts);
+        if formatted == file.text() {
+            continue;
+        }
+        changed += 1;
+        if check {
+            event!(Level::WARN, "{} is not formatted", file.path().display());
+        } else {
+            std::fs::write(file.path(), formatted)?;
+            event!(Level::INFO, "Formatted {}", file.path().display());
+        }
+    }
+
+    if !broken.is_empty() {
+        bail!("{} file(s) could not be formatted", broken.len())
+    }
+    if check && changed > 0 {
+        bail!("{} file(s) would be reformatted", changed)
+    }
+    Ok(())
+}
+
+/// Collects the offset at which every unit starts, by file.
+fn unit_starts(units: &[Unit], starts: &mut HashSet<(FileId, usize)>) {
+    let mut end = 0;
+    for unit in units {
+        let span = match unit {
+            Unit::Namespace { units, span, .. }
+            | Unit::Template { units, span, .. }
+            | Unit::Expansion { units, span, .. } => {
+                unit_starts(units, starts);
+                span
+            }
+            Unit::For { iterations, span, .. } => {
+                for iteration in iterations {
+                    unit_starts(&iteration.units, starts);
+                }
+                span
+            }
+            Unit::Component { span, .. } | Unit::Use { span, .. } | Unit::Const { span, .. } => span,
+        };
+        // Recipes and verbs lifted out of a recipe were written inside it.
+        if span.start >= end {
+            starts.insert((span.file, span.start));
+            end = span.end;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Word,
+    Str,
+    Punct,
+    LineComment,
+    BlockComment,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    kind: Kind,
+    text: &'a str,
+    start: usize,
+    /// How many line breaks came between this token and the last one.
+    newlines: usize,
+}
+
+impl Token<'_> {
+    fn is(&self, text: &str) -> bool {
+        self.kind != Kind::Str && self.text == text
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        self.kind == Kind::Word && self.text.eq_ignore_ascii_case(keyword)
+    }
+
+    fn is_comment(&self) -> bool {
+        matches!(self.kind, Kind::LineComment | Kind::BlockComment)
+    }
+}
+
+/// Splits source text into tokens. The text has already been
+/// parsed, so every string and comment is known to be closed.
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut newlines = 0;
+    let mut i = 0;
+    while let Some(c) = text[i..].chars().next() {
+        let rest = &text[i..];
+        let (kind, len) = if c.is_whitespace() {
+            if c == '\n' {
+                newlines += 1;
+            }
+            i += c.len_utf8();
+            continue;
+        } else if rest.starts_with("//") {
+            (Kind::LineComment, rest.find('\n').unwrap_or(rest.len()))
+        } else if let Some(body) = rest.strip_prefix("/*") {
+            (Kind::BlockComment, body.find("*/").map_or(rest.len(), |end| end + 4))
+        } else if c == '"' {
+            let mut escaped = false;
+            let end = rest[1..].char_indices().find(|&(_, c)| {
+                let end = c == '"' && !escaped;
+                escaped = c == '\\' && !escaped;
+                end
+            });
+            (Kind::Str, end.map_or(rest.len(), |(end, _)| end + 2))
+        } else if ["->", "+=", "-=", ">=", "<=", "=="].iter().any(|op| rest.starts_with(op)) {
+            (Kind::Punct, 2)
+        } else if parser::is_defkey_char(c) || interpolation_len(rest) > 0 {
+            (Kind::Word, word_len(rest))
+        } else {
+            (Kind::Punct, c.len_utf8())
+        };
+        tokens.push(Token {
+            kind,
+            text: rest[..len].trim_end(),
+            start: i,
+            newlines,
+        });
+        newlines = 0;
+        i += len;
+    }
+    tokens
+}
+
+/// The length of the word at the start of `text`. Numbers in JSON
+/// values may have an exponent with a `+`, which is kept with them,
+/// and ids in templates may have `{name}`s, which are part of them.
+fn word_len(text: &str) -> usize {
+    let mut len = 0;
+    loop {
+        let part = text[len..].find(|c| !parser::is_defkey_char(c)).unwrap_or(text.len() - len);
+        let interpolation = interpolation_len(&text[len + part..]);
+        len += part + interpolation;
+        if interpolation == 0 {
+            break;
+        }
+    }
+    let word = &text[..len];
+    let numeric = word.trim_start_matches('-').starts_with(|c: char| c.is_ascii_digit());
+    if numeric && (word.ends_with('e') || word.ends_with('E')) && text[len..].starts_with('+') {
+        len += 1;
+        len += text[len..].find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len() - len);
+    }
+    len
+}
+
+/// The length of the `{name}` at the start of `text`, or 0 if there isn't one.
+fn interpolation_len(text: &str) -> usize {
+    let name = match text.strip_prefix('{') {
+        Some(rest) => &rest[..rest.find('}').unwrap_or(0)],
+        None => return 0,
+    };
+    let mut chars = name.chars();
+    let valid = chars.next().map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        name.len() + 2
+    } else {
+        0
+    }
+}
+
+/// Rewrites `?` and `!` modifiers into words, in canonical order.
+fn normalize_modifiers(tokens: Vec<Token>) -> Vec<Token> {
+    let is_modifier = |t: &Token| {
+        t.is("!") || t.is("?") || ["hidden", "consume", "greedy", "craft", "hint"].iter().any(|k| t.is_keyword(k))
+    };
+    let mut out = Vec::with_capacity(tokens.len());
+    let mut i = 0;
+    while i < tokens.len() {
+        let run = tokens[i..].iter().take_while(|t| is_modifier(t)).count();
+        // The parser takes as many modifiers as it can, so the
+        // longest run that fits what follows is the one it took.
+        let found = (1..=run.min(2)).rev().find_map(|len| {
+            let words = modifier_words(&tokens[i..i + len], &tokens[i + len..])?;
+            Some((len, words))
+        });
+        match found {
+            Some((len, words)) => {
+                let newlines = tokens[i].newlines;
+                out.extend(words.iter().enumerate().map(|(n, word)| Token {
+                    kind: Kind::Word,
+                    text: word,
+                    start: tokens[i + n].start,
+                    newlines: if n == 0 { newlines } else { 0 },
+                }));
+                i += len;
+            }
+            None => {
+                out.push(tokens[i]);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// The canonical spelling of the modifiers in `run`, if they are
+/// modifiers of the declaration at the start of `next`.
+fn modifier_words(run: &[Token], next: &[Token]) -> Option<Vec<&'static str>> {
+    let first = next.first()?;
+    let recipe = next.get(1).map_or(false, |t| t.is_keyword("recipe")) && first.kind == Kind::Word;
+    // Each kind of declaration has two modifiers, written in this order.
+    let spellings: [(&str, &str, &str); 2] = if first.is_keyword("aspect") || first.is_keyword("card") {
+        return (run.len() == 1 && (run[0].is("?") || run[0].is_keyword("hidden"))).then(|| vec!["hidden"]);
+    } else if first.is_keyword("slot") {
+        [("!", "consume", "consume"), ("?", "greedy", "greedy")]
+    } else if recipe {
+        [("!", "craft", "craft"), ("?", "hint", "hint")]
+    } else {
+        return None;
+    };
+
+    let mut words = Vec::new();
+    for (symbol, keyword, word) in spellings {
+        let count = run.iter().filter(|t| t.is(symbol) || t.is_keyword(keyword)).count();
+        match count {
+            0 => (),
+            1 => words.push(word),
+            _ => return None,
+        }
+    }
+    (words.len() == run.len()).then_some(words)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Frame {
+    /// The body of a namespace.
+    Namespace,
+    /// A block of statements, each ending in `;`.
+    Block { empty: bool },
+    /// A list in brackets, such as an aspect list or JSON value.
+    List { broken: bool, attribute: bool },
+}
+
+/// What has to come between two tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Break {
+    None,
+    Line,
+    Blank,
+}
+
+/// Formats a single file, given the offset at which each unit in it starts.
+pub fn format(text: &str, unit_starts: &HashSet<usize>) -> String {
+    let tokens = normalize_modifiers(tokenize(text));
+    let mut printer = Printer {
+        tokens: &tokens,
+        unit_starts,
+        out: String::new(),
+        frames: Vec::new(),
+        next: Break::None,
+        after_from: false,
+    };
+    for i in 0..tokens.len() {
+        printer.token(i);
+    }
+    let mut out = printer.out.trim_end().to_owned();
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out
+}
+
+struct Printer<'t, 'a> {
+    tokens: &'t [Token<'a>],
+    unit_starts: &'t HashSet<usize>,
+    out: String,
+    frames: Vec<Frame>,
+    /// The break which the last token asked for after it.
+    next: Break,
+    /// Whether the last token was a `from` on a line of its own.
+    after_from: bool,
+}
+
+impl Printer<'_, '_> {
+    fn token(&mut self, i: usize) {
+        let token = self.tokens[i];
+        let in_units = matches!(self.frames.last(), None | Some(Frame::Namespace));
+        let closes = token.is(")") || token.is("]") || token.is("}");
+        let closed = if closes { self.frames.pop() } else { None };
+
+        let mut before = std::mem::replace(&mut self.next, Break::None);
+        if self.unit_starts.contains(&token.start)
+            || token.is("#")
+            || (self.frames.is_empty() && token.is_keyword("import"))
+        {
+            before = before.max(Break::Line);
+        }
+        match closed {
+            Some(Frame::Namespace | Frame::Block { empty: false } | Frame::List { broken: true, .. }) => {
+                before = Break::Line
+            }
+            Some(_) => before = Break::None,
+            None => (),
+        }
+        if token.is_comment() {
+            // A comment written at the end of a line stays there.
+            before = if token.newlines == 0 { Break::None } else { before.max(Break::Line) };
+        }
+        let opened = self.out.ends_with(['{', '(', '[']);
+        if before == Break::Line && token.newlines > 1 && !closes && !opened {
+            before = Break::Blank;
+        }
+
+        let starts_line = before != Break::None || self.out.is_empty();
+        self.separate(i, before);
+        self.out.push_str(token.text);
+
+        if token.kind == Kind::LineComment {
+            self.next = Break::Line;
+        } else if token.kind == Kind::BlockComment {
+            if self.tokens.get(i + 1).map_or(false, |next| next.newlines > 0) {
+                self.next = Break::Line;
+            }
+        } else if token.is("{") || token.is("(") || token.is("[") {
+            self.open(i);
+        } else {
+            let list = self.frames.last().and_then(|frame| match frame {
+                Frame::List { broken, .. } => Some(*broken),
+                _ => None,
+            });
+            let ends_statement = token.is(";") && list.is_none();
+            let ends_entry = token.is(",") && list == Some(true);
+            let ends_attribute = matches!(closed, Some(Frame::List { attribute: true, .. }));
+            if ends_statement || ends_entry || ends_attribute {
+                self.next = Break::Line;
+            }
+        }
+
+        // `from parent` goes on a line of its own above the component.
+        if self.after_from {
+            self.next = self.next.max(Break::Line);
+        }
+        self.after_from = in_units && starts_line && token.is_keyword("from");
+    }
+
+    /// Pushes the frame for the bracket at `i`, which has just been printed.
+    fn open(&mut self, i: usize) {
+        let tokens = self.tokens;
+        let close = matching(tokens, i);
+        let empty = close == i + 1;
+        let previous = |back: usize| i.checked_sub(back).map(|p| tokens[p]);
+        let after = |text: &str| previous(1).map_or(false, |p| p.is(text) || p.is_keyword(text));
+
+        let in_list = matches!(self.frames.last(), Some(Frame::List { .. }));
+        let value = ["=", ":", ",", "expel", "draw", "once"].iter().any(|text| after(text));
+        let frame = if tokens[i].is("{") && !in_list && !value {
+            let namespace = previous(2).map_or(false, |p| p.is_keyword("namespace"));
+            if namespace && !empty {
+                Frame::Namespace
+            } else {
+                Frame::Block { empty }
+            }
+        } else {
+            let attribute = tokens[i].is("[") && (after("#") || (after("!") && previous(2).map_or(false, |p| p.is("#"))));
+            let broken = !empty
+                && (tokens[i + 1].newlines > 0
+                    || tokens[i + 1..close].iter().any(Token::is_comment)
+                    || self.column() + inline_width(tokens, i + 1, close) > MAX_WIDTH);
+            Frame::List { broken, attribute }
+        };
+        if matches!(frame, Frame::Namespace | Frame::Block { empty: false } | Frame::List { broken: true, .. }) {
+            self.next = Break::Line;
+        }
+        self.frames.push(frame);
+    }
+
+    /// Writes whatever has to come between the last token and the one at `i`.
+    fn separate(&mut self, i: usize, before: Break) {
+        if self.out.is_empty() {
+            return;
+        }
+        if before == Break::None {
+            if needs_space(self.tokens, i) {
+                self.out.push(' ');
+            }
+            return;
+        }
+        self.out.truncate(self.out.trim_end_matches(' ').len());
+        self.out.push('\n');
+        if before == Break::Blank {
+            self.out.push('\n');
+        }
+        let depth = self
+            .frames
+            .iter()
+            .filter(|frame| matches!(frame, Frame::Namespace | Frame::Block { empty: false } | Frame::List { broken: true, .. }))
+            .count();
+        self.out.push_str(&INDENT.repeat(depth));
+    }
+
+    /// The width of the current line so far.
+    fn column(&self) -> usize {
+        let line = self.out.rfind('\n').map_or(&self.out[..], |i| &self.out[i + 1..]);
+        line.chars().count()
+    }
+}
+
+/// The index of the bracket which closes the one at `open`.
+fn matching(tokens: &[Token], open: usize) -> usize {
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate().skip(open) {
+        if token.is("(") || token.is("[") || token.is("{") {
+            depth += 1;
+        } else if token.is(")") || token.is("]") || token.is("}") {
+            depth -= 1;
+            if depth == 0 {
+                return i;
+            }
+        }
+    }
+    tokens.len() - 1
+}
+
+/// The width of the tokens from `start` up to and including `end`,
+/// if they were all written on a single line.
+fn inline_width(tokens: &[Token], start: usize, end: usize) -> usize {
+    (start..=end)
+        .map(|i| tokens[i].text.chars().count() + usize::from(needs_space(tokens, i)))
+        .sum()
+}
+
+/// Whether the token at `i` is inside the brackets of an attribute.
+fn in_attribute(tokens: &[Token], i: usize) -> bool {
+    let mut depth = 0;
+    // Predicates hold no braces or `;`, so there's no need to look past one.
+    for p in (0..i).rev().take_while(|&p| !["{", "}", ";"].iter().any(|text| tokens[p].is(text))) {
+        if tokens[p].is("]") {
+            depth += 1;
+        } else if tokens[p].is("[") {
+            if depth == 0 {
+                return p >= 1 && (tokens[p - 1].is("#") || tokens[p - 1].is("!"));
+            }
+            depth -= 1;
+        }
+    }
+    false
+}
+
+/// Whether the token at `i` is inside the parameters of a template.
+fn in_template_params(tokens: &[Token], i: usize) -> bool {
+    let mut depth = 0;
+    // Parameters hold no braces or `;`, so there's no need to look past one.
+    for p in (0..i).rev().take_while(|&p| !["{", "}", ";"].iter().any(|text| tokens[p].is(text))) {
+        if tokens[p].is(")") {
+            depth += 1;
+        } else if tokens[p].is("(") {
+            if depth == 0 {
+                return p >= 2 && tokens[p - 2].is_keyword("template");
+            }
+            depth -= 1;
+        }
+    }
+    false
+}
+
+/// Whether the token at `i` is written with a space between it
+/// and the token before it, when they are on the same line.
+fn needs_space(tokens: &[Token], i: usize) -> bool {
+    let (before, token) = match i.checked_sub(1) {
+        Some(p) => (tokens[p], tokens[i]),
+        None => return false,
+    };
+    if token.is_comment() {
+        return true;
+    }
+    if [")", "]", ",", ";", ":", "%"].iter().any(|text| token.is(text)) {
+        return false;
+    }
+    if ["(", "[", "@", "#", "!", "?"].iter().any(|text| before.is(text)) {
+        return false;
+    }
+    if before.is("{") && token.is("}") {
+        return false;
+    }
+    // The `=` of an inclusive range is part of its `..=`.
+    if token.is("=") && before.text.ends_with("..") {
+        return false;
+    }
+    if before.is("=") && i >= 2 && tokens[i - 2].text.ends_with("..") {
+        return false;
+    }
+    // A `-` written against a `(` negates it, as in `-(A + B)`.
+    if before.is("-") && token.is("(") && before.start + 1 == token.start {
+        return false;
+    }
+    // `aspect:2` is written without a space, but JSON's `"key": value`,
+    // a template parameter's `name: type` and a roll's `30: link` have one.
+    if before.is(":") {
+        let rolled = token.is_keyword("link") || token.is_keyword("goto");
+        return rolled || (i >= 2 && (tokens[i - 2].kind == Kind::Str || in_template_params(tokens, i)));
+    }
+    // A recipe's requirements and a template's parameters
+    // follow straight on from its id.
+    if token.is("(") && i >= 2 && (tokens[i - 2].is_keyword("recipe") || tokens[i - 2].is_keyword("template")) {
+        return false;
+    }
+    // A `#[cfg]` predicate's arguments follow straight on from its name.
+    if token.is("(") && before.kind == Kind::Word && in_attribute(tokens, i) {
+        return false;
+    }
+    // An instantiation's `!` and arguments follow straight on from the
+    // template's name, as long as it was written that way to begin with.
+    if token.is("!") && before.kind == Kind::Word && before.start + before.text.len() == token.start {
+        return false;
+    }
+    true
+}
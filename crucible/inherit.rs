@@ -0,0 +1,350 @@
+//! Component inheritance.
+//!
+//! A component declared with `from parent` starts out as a copy
+//! of `parent`, which must be a component of the same kind
+//! declared in this mod. Anything the component sets itself
+//! replaces the inherited value:
+//!
+//! - Strings left empty and options left unset are inherited.
+//! - Keyed lists, such as a card's aspects or a recipe's effects,
+//!   keep the parent's entries for keys the child does not use.
+//! - Unkeyed lists, such as xtriggers and slots, are concatenated.
+//!   Recipe branches are the exception: the child's come first,
+//!   so that they are tried before the inherited ones.
+//! - Flags set on either component are set on the result.
+//!
+//! Parents are flattened before their children, so inheritance
+//! can be chained through any number of components.
+
+use std::collections::HashMap;
+
+use mothlib::lantern::{json, DefKey, WarmupStyle};
+
+use crate::diagnostic::{Code, Diagnostic, Diagnostics, Span, Spanned};
+use crate::parser::*;
+
+/// Replaces every component that inherits from another with
+/// its flattened form. This runs after [crate::resolve], so
+/// every `from` already names a full id.
+pub fn inherit(crucible: &mut Crucible, diagnostics: &mut Diagnostics) {
+    let mut declared = HashMap::new();
+    collect(crucible.units(), &mut declared);
+    let mut flattener = Flattener {
+        declared,
+        done: HashMap::new(),
+        stack: Vec::new(),
+        diagnostics,
+    };
+    apply(crucible.units_mut(), &mut flattener);
+}
+
+#[derive(Clone)]
+struct Declared {
+    component: Component,
+    inherits: Option<Spanned<DefKey>>,
+}
+
+fn collect(units: &[Unit], declared: &mut HashMap<String, Declared>) {
+    for unit in units {
+        match unit {
+            Unit::Namespace { units, .. } | Unit::Expansion { units, .. } => collect(units, declared),
+            Unit::For { iterations, .. } => {
+                for iteration in iterations {
+                    collect(&iteration.units, declared);
+                }
+            }
+            Unit::Component { id, component, inherits, .. } => {
+                // Duplicates were already reported during
+                // resolution, so only the first one counts.
+                declared.entry(id.0.clone()).or_insert_with(|| Declared {
+                    component: component.clone(),
+                    inherits: inherits.clone(),
+                });
+            }
+            Unit::Use { .. } | Unit::Const { .. } | Unit::Template { .. } => (),
+        }
+    }
+}
+
+fn apply(units: &mut [Unit], flattener: &mut Flattener) {
+    for unit in units {
+        match unit {
+            Unit::Namespace { units, .. } | Unit::Expansion { units, .. } => apply(units, flattener),
+            Unit::For { iterations, .. } => {
+                for iteration in iterations {
+                    apply(&mut iteration.units, flattener);
+                }
+            }
+            Unit::Component { id, component, inherits: Some(_), .. } => {
+                if let Some(flat) = flattener.flatten(&id.0) {
+                    *component = flat;
+                }
+            }
+            Unit::Component { .. } | Unit::Use { .. } | Unit::Const { .. } | Unit::Template { .. } => (),
+        }
+    }
+}
+
+struct Flattener<'d> {
+    declared: HashMap<String, Declared>,
+    /// The flattened form of each component seen so far, or
+    /// `None` if it could not be flattened.
+    done: HashMap<String, Option<Component>>,
+    /// The components currently being flattened, each one
+    /// inheriting from the one after it.
+    stack: Vec<String>,
+    diagnostics: &'d mut Diagnostics,
+}
+
+impl<'d> Flattener<'d> {
+    fn flatten(&mut self, id: &str) -> Option<Component> {
+        if let Some(done) = self.done.get(id) {
+            return done.clone();
+        }
+        let declared = self.declared.get(id)?.clone();
+        let parent = match declared.inherits {
+            Some(parent) => parent,
+            None => return Some(declared.component),
+        };
+
+        self.stack.push(id.to_owned());
+        let result = self.flatten_from(declared.component, &parent);
+        self.stack.pop();
+
+        self.done.insert(id.to_owned(), result.clone());
+        result
+    }
+
+    fn flatten_from(&mut self, mut component: Component, parent: &Spanned<DefKey>) -> Option<Component> {
+        if let Some(start) = self.stack.iter().position(|id| *id == parent.0) {
+            let cycle: Vec<&str> = self.stack[start..]
+                .iter()
+                .map(String::as_str)
+                .chain(std::iter::once(parent.0.as_str()))
+                .collect();
+            self.diagnostics.push(
+                Diagnostic::error(
+                    Code::InheritanceCycle,
+                    parent.span,
+                    format!("inheriting from `{}` creates a cycle", parent),
+                )
+                .with_note(format!("the inheritance cycle is {}", cycle.join(" -> "))),
+            );
+            return None;
+        }
+        if !self.declared.contains_key(&parent.0) {
+            self.diagnostics.push(
+                Diagnostic::error(
+                    Code::InvalidInheritance,
+                    parent.span,
+                    format!("cannot inherit from `{}`, which is not declared in this mod", parent),
+                )
+                .with_note("components can only inherit from components declared in Crucible source"),
+            );
+            return None;
+        }
+
+        let base = self.flatten(&parent.0)?;
+        if merge(&mut component, &base) {
+            Some(component)
+        } else {
+            self.diagnostics.push(mismatched_kind(&component, &base, parent.span));
+            None
+        }
+    }
+}
+
+fn mismatched_kind(component: &Component, parent: &Component, span: Span) -> Diagnostic {
+    let parent_id = parent.id();
+    Diagnostic::error(
+        Code::InvalidInheritance,
+        span,
+        format!(
+            "the {} `{}` cannot inherit from `{}`, which is {} {}",
+            component.kind(),
+            component.id(),
+            parent_id,
+            article(parent.kind()),
+            parent.kind(),
+        ),
+    )
+    .with_label(parent_id.span, format!("`{}` is declared here", parent_id))
+}
+
+/// The indefinite article for `word`.
+pub(crate) fn article(word: &str) -> &'static str {
+    match word.chars().next() {
+        Some('a' | 'e' | 'i' | 'o' | 'u') => "an",
+        _ => "a",
+    }
+}
+
+/// Merges `parent` into `child`, returning false if
+/// they are not the same kind of component.
+fn merge(child: &mut Component, parent: &Component) -> bool {
+    match (child, parent) {
+        (Component::Aspect(c), Component::Aspect(p)) => c.inherit(p),
+        (Component::Card(c), Component::Card(p)) => c.inherit(p),
+        (Component::Deck(c), Component::Deck(p)) => c.inherit(p),
+        (Component::Recipe(c), Component::Recipe(p)) => c.inherit(p),
+        (Component::Verb(c), Component::Verb(p)) => c.inherit(p),
+        (Component::Legacy(c), Component::Legacy(p)) => others(&mut c.others, &p.others),
+        (Component::Ending(c), Component::Ending(p)) => others(&mut c.others, &p.others),
+        _ => return false,
+    }
+    true
+}
+
+trait Inherit {
+    /// Fills in everything this component leaves unset from `parent`.
+    fn inherit(&mut self, parent: &Self);
+}
+
+fn string(child: &mut String, parent: &str) {
+    if child.is_empty() {
+        *child = parent.to_owned();
+    }
+}
+
+fn option<T: Clone>(child: &mut Option<T>, parent: &Option<T>) {
+    if child.is_none() {
+        *child = parent.clone();
+    }
+}
+
+fn list<T: Clone>(child: &mut Vec<T>, parent: &[T]) {
+    let own = std::mem::replace(child, parent.to_vec());
+    child.extend(own);
+}
+
+/// Puts the parent's entries first, skipping any whose key the child uses.
+fn keyed<T: Clone, K: PartialEq>(child: &mut Vec<T>, parent: &[T], key: impl Fn(&T) -> K) {
+    let mut merged: Vec<T> = parent
+        .iter()
+        .filter(|p| !child.iter().any(|c| key(c) == key(p)))
+        .cloned()
+        .collect();
+    merged.append(child);
+    *child = merged;
+}
+
+/// Merges the child's requirements with the parent's, as written.
+/// If either has alternatives, each way of meeting the child's is
+/// merged with each way of meeting the parent's, in order, and the
+/// result is only combined, and its ways kept apart, after this.
+fn requirements(child: &mut RecipeDef, parent: &RecipeDef) {
+    let ways = |recipe: &RecipeDef| {
+        if recipe.written.is_empty() {
+            vec![Vec::new()]
+        } else {
+            recipe.written.clone()
+        }
+    };
+    let mut merged = Vec::new();
+    for way in ways(child) {
+        for theirs in ways(parent) {
+            let mut way = way.clone();
+            keyed(&mut way, &theirs, Written::key);
+            merged.push(way);
+        }
+    }
+    child.written = merged;
+}
+
+fn others(child: &mut HashMap<DefKey, json::Value>, parent: &HashMap<DefKey, json::Value>) {
+    for (key, value) in parent {
+        child.entry(key.clone()).or_insert_with(|| value.clone());
+    }
+}
+
+fn pair_key<T>(pair: &(Spanned<DefKey>, T)) -> DefKey {
+    pair.0.item.clone()
+}
+
+impl Inherit for AspectDef {
+    fn inherit(&mut self, parent: &Self) {
+        string(&mut self.description, &parent.description);
+        option(&mut self.icon, &parent.icon);
+        option(&mut self.verbicon, &parent.verbicon);
+        option(&mut self.induces, &parent.induces);
+        option(&mut self.decays_to, &parent.decays_to);
+        self.hidden |= parent.hidden;
+        list(&mut self.xtriggers, &parent.xtriggers);
+        others(&mut self.others, &parent.others);
+    }
+}
+
+impl Inherit for CardDef {
+    fn inherit(&mut self, parent: &Self) {
+        string(&mut self.description, &parent.description);
+        option(&mut self.icon, &parent.icon);
+        option(&mut self.verbicon, &parent.verbicon);
+        option(&mut self.induces, &parent.induces);
+        option(&mut self.decays_to, &parent.decays_to);
+        option(&mut self.lifetime, &parent.lifetime);
+        option(&mut self.uniqueness_group, &parent.uniqueness_group);
+        self.hidden |= parent.hidden;
+        self.resaturate |= parent.resaturate;
+        self.unique |= parent.unique;
+        keyed(&mut self.aspects, &parent.aspects, pair_key);
+        list(&mut self.slots, &parent.slots);
+        list(&mut self.xtriggers, &parent.xtriggers);
+        others(&mut self.others, &parent.others);
+    }
+}
+
+impl Inherit for DeckDef {
+    fn inherit(&mut self, parent: &Self) {
+        string(&mut self.label, &parent.label);
+        string(&mut self.description, &parent.description);
+        option(&mut self.default, &parent.default);
+        list(&mut self.cards, &parent.cards);
+        self.is_portal_deck |= parent.is_portal_deck;
+        others(&mut self.others, &parent.others);
+    }
+}
+
+impl Inherit for VerbDef {
+    fn inherit(&mut self, parent: &Self) {
+        option(&mut self.slot, &parent.slot);
+        others(&mut self.others, &parent.others);
+    }
+}
+
+impl Inherit for RecipeDef {
+    fn inherit(&mut self, parent: &Self) {
+        string(&mut self.label, &parent.label);
+        string(&mut self.description, &parent.description);
+        string(&mut self.end_description, &parent.end_description);
+        option(&mut self.burn, &parent.burn);
+        option(&mut self.portal, &parent.portal);
+        option(&mut self.slot, &parent.slot);
+        option(&mut self.ending, &parent.ending);
+        option(&mut self.internal_deck, &parent.internal_deck);
+        if self.max_executions == 0 {
+            self.max_executions = parent.max_executions;
+        }
+        if self.warmup == 0 {
+            self.warmup = parent.warmup;
+        }
+        if matches!(self.style, WarmupStyle::None) {
+            self.style = parent.style.clone();
+        }
+        // A stage is only reached by the link from the stage before
+        // it, so it is never started by the player and checks no
+        // requirements, whichever recipe it inherits from.
+        if !self.stage {
+            self.craftable |= parent.craftable;
+            self.hint_only |= parent.hint_only;
+            requirements(self, parent);
+        }
+        keyed(&mut self.effects, &parent.effects, pair_key);
+        keyed(&mut self.purge, &parent.purge, pair_key);
+        keyed(&mut self.draws, &parent.draws, pair_key);
+        keyed(&mut self.halt, &parent.halt, pair_key);
+        keyed(&mut self.delete, &parent.delete, pair_key);
+        list(&mut self.mutations, &parent.mutations);
+        self.branches.extend(parent.branches.iter().cloned());
+        others(&mut self.others, &parent.others);
+    }
+}
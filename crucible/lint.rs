@@ -0,0 +1,395 @@
+//! Lints.
+//!
+//! A lint points out something a mod is allowed to do, but which is
+//! usually a mistake, such as declaring a deck that nothing draws
+//! from. Each lint has a name, and a level which decides whether it
+//! is ignored, reported as a warning, or reported as an error. The
+//! level is set with `--allow`, `--warn` and `--deny` on the command
+//! line, and with `#[allow(...)]`, `#[warn(...)]` and `#[deny(...)]`
+//! attributes on components and namespaces, or with `#!` for the
+//! whole mod. The level set closest to a component wins, so an
+//! attribute on a component overrides one on its namespace, which
+//! overrides the command line.
+//!
+//! The pass runs after [crate::inherit], so references are full ids
+//! and inherited contents are in place. Only what the mod declares is
+//! known, so components from the base game are never linted.
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{bail, Result};
+
+use crate::diagnostic::{Code, Diagnostic, Diagnostics, Severity, Span};
+use crate::parser::{Component, Crucible, Expected, Iteration, LintAttr, LintLevel, Unit, XtriggerDef};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    /// An aspect that nothing refers to.
+    UnusedAspect,
+    /// A card that isn't hidden, and has no `icon`.
+    MissingArt,
+    /// A recipe with no warmup that nothing links to.
+    UnreachableRecipe,
+    /// A deck that no recipe draws from.
+    UndrawnDeck,
+    /// A card whose uniqueness group isn't an aspect of the mod.
+    UndeclaredUniquenessGroup,
+    /// An xtrigger whose catalyst is never on any card.
+    UnusedCatalyst,
+    /// A verb that no craftable recipe runs in.
+    UncraftableVerb,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 7] = [
+        Lint::UnusedAspect,
+        Lint::MissingArt,
+        Lint::UnreachableRecipe,
+        Lint::UndrawnDeck,
+        Lint::UndeclaredUniquenessGroup,
+        Lint::UnusedCatalyst,
+        Lint::UncraftableVerb,
+    ];
+
+    /// The name this lint is given in attributes and on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::UnusedAspect => "unused_aspect",
+            Lint::MissingArt => "missing_art",
+            Lint::UnreachableRecipe => "unreachable_recipe",
+            Lint::UndrawnDeck => "undrawn_deck",
+            Lint::UndeclaredUniquenessGroup => "undeclared_uniqueness_group",
+            Lint::UnusedCatalyst => "unused_catalyst",
+            Lint::UncraftableVerb => "uncraftable_verb",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Lint> {
+        Lint::ALL.into_iter().find(|lint| lint.name() == name)
+    }
+
+    fn code(self) -> Code {
+        match self {
+            Lint::UnusedAspect => Code::UnusedAspect,
+            Lint::MissingArt => Code::MissingArt,
+            Lint::UnreachableRecipe => Code::UnreachableRecipe,
+            Lint::UndrawnDeck => Code::UndrawnDeck,
+            Lint::UndeclaredUniquenessGroup => Code::UndeclaredUniquenessGroup,
+            Lint::UnusedCatalyst => Code::UnusedCatalyst,
+            Lint::UncraftableVerb => Code::UncraftableVerb,
+        }
+    }
+
+    fn default_level(self) -> LintLevel {
+        match self {
+            // Most mods name their art after each card's id
+            // rather than setting `icon`, so this is only
+            // worth turning on for those that don't.
+            Lint::MissingArt => LintLevel::Allow,
+            _ => LintLevel::Warn,
+        }
+    }
+}
+
+/// Every lint name, for messages about a name that isn't one.
+fn known() -> String {
+    let names = Lint::ALL.iter().map(|lint| format!("`{}`", lint.name())).collect::<Vec<_>>();
+    names.join(", ")
+}
+
+/// Where the level of a lint was set.
+#[derive(Debug, Clone, Copy)]
+enum Source {
+    Default,
+    CommandLine,
+    Attribute(Span),
+}
+
+/// The level of every lint in some part of the mod.
+#[derive(Debug, Clone)]
+pub struct Levels(HashMap<Lint, (LintLevel, Source)>);
+
+impl Default for Levels {
+    fn default() -> Self {
+        Levels(
+            Lint::ALL
+                .into_iter()
+                .map(|lint| (lint, (lint.default_level(), Source::Default)))
+                .collect(),
+        )
+    }
+}
+
+impl Levels {
+    /// The default levels, with the lints passed to `--allow`,
+    /// `--warn` and `--deny` changed, in that order.
+    pub fn new(allow: &[String], warn: &[String], deny: &[String]) -> Result<Self> {
+        let mut levels = Levels::default();
+        for (level, names) in [(LintLevel::Allow, allow), (LintLevel::Warn, warn), (LintLevel::Deny, deny)] {
+            for name in names {
+                match Lint::from_name(name) {
+                    Some(lint) => levels.0.insert(lint, (level, Source::CommandLine)),
+                    None => bail!("`{}` passed to `--{}` is not a lint; the lints are {}", name, level.name(), known()),
+                };
+            }
+        }
+        Ok(levels)
+    }
+
+    /// These levels, with those set by `attrs` changed.
+    /// Names which aren't lints are reported.
+    fn with(&self, attrs: &[LintAttr], diagnostics: &mut Diagnostics) -> Levels {
+        let mut levels = self.clone();
+        for attr in attrs {
+            for name in &attr.lints {
+                match Lint::from_name(&name.item) {
+                    Some(lint) => {
+                        levels.0.insert(lint, (attr.level, Source::Attribute(name.span)));
+                    }
+                    None => diagnostics.push(
+                        Diagnostic::warning(Code::UnknownLint, name.span, format!("unknown lint `{}`", name.item))
+                            .with_note(format!("the lints are {}", known())),
+                    ),
+                }
+            }
+        }
+        levels
+    }
+
+    /// Reports `diagnostic` for `lint`, as its level in this part of
+    /// the mod asks, pointing out where that level was set.
+    fn report(&self, lint: Lint, diagnostic: Diagnostic, diagnostics: &mut Diagnostics) {
+        let (level, source) = self.0[&lint];
+        let mut diagnostic = match level {
+            LintLevel::Allow => return,
+            LintLevel::Warn => diagnostic,
+            LintLevel::Deny => Diagnostic {
+                severity: Severity::Error,
+                ..diagnostic
+            },
+        };
+        diagnostic = match source {
+            Source::Default => diagnostic.with_note(format!("`#[{}({})]` on by default", level.name(), lint.name())),
+            Source::CommandLine => diagnostic.with_note(format!("`--{} {}` was passed", level.name(), lint.name())),
+            Source::Attribute(span) => {
+                diagnostic.with_label(span, format!("`{}` is set to `{}` here", lint.name(), level.name()))
+            }
+        };
+        diagnostics.push(diagnostic);
+    }
+}
+
+/// Reports every lint in `crucible`, at the levels
+/// given by `levels` and the mod's attributes.
+pub fn lint(crucible: &mut Crucible, levels: &Levels, diagnostics: &mut Diagnostics) {
+    let mut usage = Usage::default();
+    usage.collect(crucible.units_mut());
+    let levels = levels.with(crucible.lints(), diagnostics);
+    lint_units(crucible.units(), &levels, &usage, diagnostics);
+}
+
+/// How the components of the mod are used by each other.
+#[derive(Default)]
+struct Usage {
+    /// Every aspect, and where it was declared.
+    aspects: HashMap<String, Span>,
+    /// Every id referred to, other than by the component itself.
+    referenced: HashSet<String>,
+    /// Every recipe linked to, gone to or induced.
+    linked: HashSet<String>,
+    /// Every deck drawn from.
+    drawn: HashSet<String>,
+    /// Every card, and every aspect on a card or added to one.
+    present: HashSet<String>,
+    /// Every verb a craftable recipe runs in.
+    crafted: HashSet<String>,
+}
+
+impl Usage {
+    fn collect(&mut self, units: &mut [Unit]) {
+        for unit in units {
+            match unit {
+                Unit::Namespace { units, .. } | Unit::Expansion { units, .. } => self.collect(units),
+                Unit::For { iterations, .. } => {
+                    for iteration in iterations {
+                        self.collect(&mut iteration.units);
+                    }
+                }
+                Unit::Component { id, component, inherits, .. } => {
+                    if let Some(parent) = inherits {
+                        self.referenced.insert(parent.0.clone());
+                    }
+                    match component {
+                        Component::Aspect(_) => {
+                            self.aspects.entry(id.0.clone()).or_insert(id.span);
+                        }
+                        Component::Card(card) => {
+                            self.present.insert(id.0.clone());
+                            self.present.extend(card.aspects.iter().map(|(aspect, _)| aspect.0.clone()));
+                        }
+                        Component::Recipe(recipe) => {
+                            self.present.extend(recipe.mutations.iter().map(|m| m.aspect.0.clone()));
+                            if recipe.craftable {
+                                self.crafted.insert(recipe.verb.0.clone());
+                            }
+                        }
+                        _ => (),
+                    }
+                    for (expected, reference) in component.references_mut() {
+                        if reference.0 == id.0 {
+                            continue;
+                        }
+                        match expected {
+                            Expected::Recipe => self.linked.insert(reference.0.clone()),
+                            Expected::Deck => self.drawn.insert(reference.0.clone()),
+                            _ => false,
+                        };
+                        self.referenced.insert(reference.0.clone());
+                    }
+                    let xtriggers = match component {
+                        Component::Aspect(aspect) => &aspect.xtriggers,
+                        Component::Card(card) => &card.xtriggers,
+                        _ => continue,
+                    };
+                    for xtrigger in xtriggers {
+                        if let XtriggerDef::Mutate { adds_to_catalyst, .. } = xtrigger {
+                            self.present.insert(adds_to_catalyst.0.clone());
+                        }
+                    }
+                }
+                Unit::Use { .. } | Unit::Const { .. } | Unit::Template { .. } => (),
+            }
+        }
+    }
+}
+
+fn lint_units(units: &[Unit], levels: &Levels, usage: &Usage, diagnostics: &mut Diagnostics) {
+    for unit in units {
+        match unit {
+            Unit::Namespace { lints, units, .. } => {
+                let levels = levels.with(lints, diagnostics);
+                lint_units(units, &levels, usage, diagnostics);
+            }
+            Unit::Component { id, lints, component, .. } => {
+                let levels = levels.with(lints, diagnostics);
+                lint_component(&id.0, id.span, component, &levels, usage, diagnostics);
+            }
+            Unit::Expansion { template, units, span } => {
+                let mut found = Diagnostics::new();
+                lint_units(units, levels, usage, &mut found);
+                diagnostics.extend(found.into_iter().map(|d| d.in_expansion(&template.0, *span)));
+            }
+            Unit::For { variable, iterations, .. } => {
+                for Iteration { value, units } in iterations {
+                    let mut found = Diagnostics::new();
+                    lint_units(units, levels, usage, &mut found);
+                    let label = |d: Diagnostic| d.in_iteration(&variable.0, &value.item, value.span);
+                    diagnostics.extend(found.into_iter().map(label));
+                }
+            }
+            Unit::Use { .. } | Unit::Const { .. } | Unit::Template { .. } => (),
+        }
+    }
+}
+
+fn lint_component(
+    id: &str,
+    span: Span,
+    component: &Component,
+    levels: &Levels,
+    usage: &Usage,
+    diagnostics: &mut Diagnostics,
+) {
+    let mut report = |lint, diagnostic| levels.report(lint, diagnostic, diagnostics);
+    let xtriggers = match component {
+        Component::Aspect(aspect) => {
+            if !usage.referenced.contains(id) {
+                report(
+                    Lint::UnusedAspect,
+                    Diagnostic::warning(Code::UnusedAspect, span, format!("aspect `{}` is never used", id)),
+                );
+            }
+            &aspect.xtriggers
+        }
+        Component::Card(card) => {
+            if card.icon.is_none() && !card.hidden {
+                report(
+                    Lint::MissingArt,
+                    Diagnostic::warning(Code::MissingArt, span, format!("card `{}` has no art", id))
+                        .with_note("set its `icon`, or make it `hidden`"),
+                );
+            }
+            if let Some(group) = card.uniqueness_group.as_ref().filter(|g| !usage.aspects.contains_key(&g.0)) {
+                report(
+                    Lint::UndeclaredUniquenessGroup,
+                    Diagnostic::warning(
+                        Code::UndeclaredUniquenessGroup,
+                        group.span,
+                        format!("uniqueness group `{}` is not an aspect declared in this mod", group.0),
+                    ),
+                );
+            }
+            &card.xtriggers
+        }
+        Component::Recipe(recipe) => {
+            if recipe.warmup == 0 && !usage.linked.contains(id) {
+                report(
+                    Lint::UnreachableRecipe,
+                    Diagnostic::warning(
+                        Code::UnreachableRecipe,
+                        span,
+                        format!("recipe `{}` has no warmup, but nothing links to it", id),
+                    )
+                    .with_note("a recipe with no warmup usually only runs when another recipe links to it"),
+                );
+            }
+            return;
+        }
+        Component::Deck(_) => {
+            if !usage.drawn.contains(id) {
+                report(
+                    Lint::UndrawnDeck,
+                    Diagnostic::warning(Code::UndrawnDeck, span, format!("deck `{}` is never drawn from", id)),
+                );
+            }
+            return;
+        }
+        Component::Verb(verb) => {
+            // A temporary verb only runs the recipe that spawned it.
+            if !verb.temporary && !usage.crafted.contains(id) {
+                report(
+                    Lint::UncraftableVerb,
+                    Diagnostic::warning(
+                        Code::UncraftableVerb,
+                        span,
+                        format!("verb `{}` has no craftable recipe, so the player can't start anything in it", id),
+                    )
+                    .with_note("if its recipes are only ever spawned, write it inline with `in verb` on the `goto` instead"),
+                );
+            }
+            return;
+        }
+        Component::Legacy(_) | Component::Ending(_) => return,
+    };
+    for xtrigger in xtriggers {
+        let catalyst = match xtrigger {
+            XtriggerDef::Transform { catalyst, .. }
+            | XtriggerDef::Spawn { catalyst, .. }
+            | XtriggerDef::Mutate { catalyst, .. } => catalyst,
+        };
+        // A catalyst from the base game may well be on its cards.
+        let declared = match usage.aspects.get(&catalyst.0) {
+            Some(declared) if !usage.present.contains(&catalyst.0) => *declared,
+            _ => continue,
+        };
+        report(
+            Lint::UnusedCatalyst,
+            Diagnostic::warning(
+                Code::UnusedCatalyst,
+                catalyst.span,
+                format!("catalyst `{}` is never on any card, so this xtrigger never fires", catalyst.0),
+            )
+            .with_label(declared, format!("`{}` is declared here", catalyst.0)),
+        );
+    }
+}
@@ -0,0 +1,374 @@
+//! Lowering the resolved syntax tree into [Lantern].
+//!
+//! By the time a tree is lowered, every id in it has been
+//! rewritten into its full form by [crate::resolve], so this
+//! pass only has to strip away spans and reshape each
+//! component into its Lantern counterpart.
+//!
+//! A component's `///` doc comment becomes its `comment`,
+//! unless the component assigns one itself with `set`.
+//!
+//! A recipe whose requirements hold an `or` becomes a recipe for
+//! each way they can be met: the first keeps the recipe's id, and
+//! the others are given `.__or1`, `.__or2` and so on after it, so
+//! that they sort in the order they were written. Every branch to
+//! such a recipe becomes a branch to each of its variants in turn,
+//! and a branch whose own condition holds an `or` becomes a branch
+//! for each way it can be met. Each way requires that those before
+//! it don't hold, so at most one of these branches can be taken, and
+//! each keeps the chance that was written for the branch.
+
+use std::collections::HashMap;
+
+use either::Either;
+use mothlib::lantern::*;
+
+use crate::diagnostic::Spanned;
+use crate::parser::*;
+
+/// The key that doc comments are stored under.
+const COMMENT: &str = "comment";
+
+/// The full id of every variant of each recipe, in order.
+type Variants = HashMap<DefKey, Vec<DefKey>>;
+
+pub fn lower(crucible: &Crucible) -> Lantern {
+    let mut lantern = Lantern::new();
+    let mut variants = Variants::new();
+    collect_variants(crucible.units(), &mut variants);
+    lantern.attributes_mut().extend(crucible.attributes().iter().cloned());
+    lower_units(crucible.units(), "", &variants, &mut lantern);
+    lantern
+}
+
+fn collect_variants(units: &[Unit], variants: &mut Variants) {
+    for unit in units {
+        match unit {
+            Unit::Namespace { units, .. } | Unit::Expansion { units, .. } => collect_variants(units, variants),
+            Unit::For { iterations, .. } => {
+                for iteration in iterations {
+                    collect_variants(&iteration.units, variants);
+                }
+            }
+            Unit::Component { id, component: Component::Recipe(def), .. } => {
+                let count = def.alternatives.len() + 1;
+                // Padded, so that `__or10` doesn't sort before `__or2`.
+                let width = (count - 1).to_string().len();
+                let ids = (0..count).map(|n| match n {
+                    0 => id.item.clone(),
+                    n => DefKey(format!("{}.__or{:0width$}", id.item, n, width = width)),
+                });
+                variants.insert(id.item.clone(), ids.collect());
+            }
+            Unit::Component { .. } | Unit::Use { .. } | Unit::Const { .. } | Unit::Template { .. } => (),
+        }
+    }
+}
+
+fn lower_units(units: &[Unit], prefix: &str, variants: &Variants, lantern: &mut Lantern) {
+    for unit in units {
+        match unit {
+            Unit::Namespace { id, attrs, units, .. } => {
+                let path = if prefix.is_empty() {
+                    id.0.clone()
+                } else {
+                    format!("{}.{}", prefix, id.0)
+                };
+                lantern
+                    .namespace_mut(DefKey(path.clone()))
+                    .attributes
+                    .extend(attrs.iter().cloned());
+                lower_units(units, &path, variants, lantern);
+            }
+            Unit::Component { id, doc, attrs, component, .. } => {
+                let ids = variants.get(&id.item).cloned().unwrap_or_else(|| vec![id.item.clone()]);
+                if !attrs.is_empty() {
+                    for id in &ids {
+                        lantern.component_attributes_mut().insert(id.clone(), attrs.clone());
+                    }
+                }
+                lantern
+                    .namespace_mut(DefKey(prefix.to_owned()))
+                    .components
+                    .extend(ids);
+                lower_component(component, doc.as_deref(), variants, lantern);
+            }
+            Unit::Expansion { units, .. } => lower_units(units, prefix, variants, lantern),
+            Unit::For { iterations, .. } => {
+                for iteration in iterations {
+                    lower_units(&iteration.units, prefix, variants, lantern);
+                }
+            }
+            Unit::Use { .. } | Unit::Const { .. } | Unit::Template { .. } => (),
+        }
+    }
+}
+
+fn lower_component(component: &Component, doc: Option<&str>, variants: &Variants, lantern: &mut Lantern) {
+    match component {
+        Component::Aspect(def) => {
+            let aspect = lower_aspect(def, doc);
+            lantern.aspects_mut().insert(aspect.id.clone(), aspect);
+        }
+        Component::Card(def) => {
+            let card = lower_card(def, doc);
+            lantern.cards_mut().insert(card.id.clone(), card);
+        }
+        Component::Deck(def) => {
+            let deck = lower_deck(def, doc);
+            lantern.decks_mut().insert(deck.id.clone(), deck);
+        }
+        Component::Recipe(def) => {
+            let recipe = lower_recipe(def, doc, variants);
+            let ways = std::iter::once(&def.requirements).chain(def.alternatives.iter());
+            for (id, requirements) in variants[&recipe.id].iter().zip(ways) {
+                let variant = Recipe {
+                    id: id.clone(),
+                    requirements: requirements.iter().map(lower_requirement).collect(),
+                    // The deck belongs to each variant alone, as to the recipe.
+                    internal_deck: recipe.internal_deck.clone().map(|(deck, draws)| {
+                        (Deck { id: id.clone(), ..deck }, draws)
+                    }),
+                    ..recipe.clone()
+                };
+                lantern.recipes_mut().insert(variant.id.clone(), variant);
+            }
+        }
+        Component::Verb(def) => {
+            let verb = lower_verb(def, doc);
+            lantern.verbs_mut().insert(verb.id.clone(), verb);
+        }
+        Component::Legacy(def) => {
+            let legacy = Legacy { id: def.id.item.clone(), others: others(&def.others, doc) };
+            lantern.legacies_mut().insert(legacy.id.clone(), legacy);
+        }
+        Component::Ending(def) => {
+            let ending = Ending { id: def.id.item.clone(), others: others(&def.others, doc) };
+            lantern.endings_mut().insert(ending.id.clone(), ending);
+        }
+    }
+}
+
+/// The unmodelled members of a component, with its doc comment added.
+fn others(others: &HashMap<DefKey, json::Value>, doc: Option<&str>) -> HashMap<DefKey, json::Value> {
+    let mut others = others.clone();
+    if let Some(doc) = doc {
+        others
+            .entry(DefKey(COMMENT.to_owned()))
+            .or_insert_with(|| json::Value::Str(doc.to_owned()));
+    }
+    others
+}
+
+fn key(key: &Spanned<DefKey>) -> DefKey {
+    key.item.clone()
+}
+
+fn lower_aspect(def: &AspectDef, doc: Option<&str>) -> Aspect {
+    Aspect {
+        id: key(&def.id),
+        label: def.label.clone(),
+        description: def.description.clone(),
+        icon: def.icon.clone(),
+        verbicon: def.verbicon.clone(),
+        induces: def.induces.as_ref().map(|(target, chance)| (key(target), *chance)),
+        decays_to: def.decays_to.as_ref().map(key),
+        hidden: def.hidden,
+        xtriggers: def.xtriggers.iter().map(lower_xtrigger).collect(),
+        others: others(&def.others, doc),
+    }
+}
+
+fn lower_card(def: &CardDef, doc: Option<&str>) -> Card {
+    let mut slots: HashMap<DefKey, Vec<Slot>> = HashMap::new();
+    for (verb, slot) in def.slots.iter() {
+        slots.entry(key(verb)).or_default().push(lower_slot(slot));
+    }
+
+    Card {
+        id: key(&def.id),
+        label: def.label.clone(),
+        description: def.description.clone(),
+        icon: def.icon.clone(),
+        verbicon: def.verbicon.clone(),
+        induces: def.induces.as_ref().map(|(target, chance)| (key(target), *chance)),
+        decays_to: def.decays_to.as_ref().map(key),
+        hidden: def.hidden,
+        aspects: def.aspects.iter().map(|(aspect, amount)| (key(aspect), *amount)).collect(),
+        lifetime: def.lifetime,
+        resaturate: def.resaturate,
+        unique: def.unique,
+        uniqueness_group: def.uniqueness_group.as_ref().map(key),
+        slots,
+        xtriggers: def.xtriggers.iter().map(lower_xtrigger).collect(),
+        others: others(&def.others, doc),
+    }
+}
+
+fn lower_deck(def: &DeckDef, doc: Option<&str>) -> Deck {
+    Deck {
+        id: key(&def.id),
+        label: def.label.clone(),
+        description: def.description.clone(),
+        default: def.default.as_ref().map(key),
+        cards: def
+            .cards
+            .iter()
+            .flat_map(|(card, desc, copies)| std::iter::repeat((key(card), desc.clone())).take(*copies as usize))
+            .collect(),
+        is_portal_deck: def.is_portal_deck,
+        others: others(&def.others, doc),
+    }
+}
+
+fn lower_verb(def: &VerbDef, doc: Option<&str>) -> Verb {
+    Verb {
+        id: key(&def.id),
+        label: def.label.clone(),
+        description: def.description.clone(),
+        slot: def.slot.as_ref().map(lower_slot),
+        others: others(&def.others, doc),
+    }
+}
+
+fn lower_recipe(def: &RecipeDef, doc: Option<&str>, variants: &Variants) -> Recipe {
+    // The game leaves `halt` and `delete` out entirely when unused.
+    let targets = |targets: &[(Spanned<DefKey>, u32)]| -> Option<HashMap<DefKey, u32>> {
+        if targets.is_empty() {
+            None
+        } else {
+            Some(targets.iter().map(|(verb, amount)| (key(verb), *amount)).collect())
+        }
+    };
+
+    Recipe {
+        id: key(&def.id),
+        verb: key(&def.verb),
+        label: def.label.clone(),
+        description: def.description.clone(),
+        end_description: def.end_description.clone(),
+        burn: def.burn.clone(),
+        portal: def.portal.clone(),
+        requirements: def.requirements.iter().map(lower_requirement).collect(),
+        max_executions: def.max_executions,
+        warmup: def.warmup,
+        craftable: def.craftable,
+        hint_only: def.hint_only,
+        slot: def.slot.as_ref().map(lower_slot),
+        effects: def.effects.iter().map(|(element, op)| (key(element), op.clone())).collect(),
+        purge: def.purge.iter().map(|(element, amount)| (key(element), *amount)).collect(),
+        aspects: HashMap::new(),
+        draws: def.draws.iter().map(|(deck, amount)| (key(deck), *amount)).collect(),
+        // The game gives a recipe's own deck the recipe's id.
+        internal_deck: def.internal_deck.as_ref().map(|(deck, amount)| {
+            (Deck { id: key(&def.id), ..lower_deck(deck, None) }, *amount)
+        }),
+        mutations: def
+            .mutations
+            .iter()
+            .map(|m| Mutation { id: key(&m.id), aspect: key(&m.aspect), operation: m.operation.clone() })
+            .collect(),
+        halt: targets(&def.halt),
+        delete: targets(&def.delete),
+        ending: def.ending.as_ref().map(key),
+        style: def.style.clone(),
+        branches: def.branches.iter().flat_map(|branch| lower_branch(branch, variants)).collect(),
+        others: others(&def.others, doc),
+    }
+}
+
+fn lower_requirement(def: &RequirementDef) -> RecipeRequirement {
+    let element = key(&def.element);
+    let amount = match &def.threshold {
+        Threshold::Amount(n) => Either::Left(ValueOperation::Add(*n)),
+        Threshold::Element(other) => Either::Right(key(other)),
+    };
+    match def.kind {
+        RequirementKind::Basic => RecipeRequirement::Basic { element, amount },
+        RequirementKind::Table => RecipeRequirement::Table { element, amount },
+        RequirementKind::Extant => RecipeRequirement::Extant { element, amount },
+    }
+}
+
+/// The condition for each way `def` can be met.
+fn lower_condition(def: &ConditionDef) -> Vec<BranchCondition> {
+    std::iter::once(&def.requirements)
+        .chain(def.alternatives.iter())
+        .map(|requirements| BranchCondition {
+            chance: def.chance,
+            requirements: requirements.iter().map(lower_requirement).collect(),
+        })
+        .collect()
+}
+
+/// A branch for each way its condition can be met,
+/// to each variant of its target in turn.
+fn lower_branch(def: &BranchDef, variants: &Variants) -> Vec<Branch> {
+    let (target, condition) = match def {
+        BranchDef::Link { target, condition } => (target, condition),
+        BranchDef::Goto { target, condition, .. } => (target, condition),
+    };
+    // Targets in the base game are never split.
+    let targets = variants.get(&target.item).cloned().unwrap_or_else(|| vec![key(target)]);
+    let mut branches = Vec::new();
+    for condition in lower_condition(condition) {
+        for target in targets.iter().cloned() {
+            let condition = condition.clone();
+            branches.push(match def {
+                BranchDef::Link { .. } => Branch::Link { target, condition },
+                BranchDef::Goto { action, .. } => Branch::Goto {
+                    target,
+                    condition,
+                    action: action.as_ref().map(|action| match action {
+                        SpawningDef::Spawn => SpawningKind::Spawn,
+                        SpawningDef::Expel(elements) => SpawningKind::Expel(
+                            elements.iter().map(|(element, amount)| (key(element), *amount)).collect(),
+                        ),
+                    }),
+                },
+            });
+        }
+    }
+    branches
+}
+
+fn lower_xtrigger(def: &XtriggerDef) -> Xtrigger {
+    match def {
+        XtriggerDef::Transform { catalyst, transforms_to, amount, chance } => Xtrigger::Transform {
+            catalyst: key(catalyst),
+            transforms_to: key(transforms_to),
+            amount: *amount,
+            chance: *chance,
+        },
+        XtriggerDef::Spawn { catalyst, creates, amount, chance } => Xtrigger::Spawn {
+            catalyst: key(catalyst),
+            creates: key(creates),
+            amount: *amount,
+            chance: *chance,
+        },
+        XtriggerDef::Mutate { catalyst, adds_to_catalyst, amount, chance } => Xtrigger::Mutate {
+            catalyst: key(catalyst),
+            adds_to_catalyst: key(adds_to_catalyst),
+            amount: *amount,
+            chance: *chance,
+        },
+    }
+}
+
+fn lower_slot(def: &SlotDef) -> Slot {
+    Slot {
+        id: key(&def.id),
+        label: def.label.clone(),
+        description: def.description.clone(),
+        consumes: def.consumes,
+        greedy: def.greedy,
+        requirements: def
+            .requirements
+            .iter()
+            .map(|filter| match filter {
+                SlotFilterDef::Accept { element, amount } => SlotFilter::Accept { element: key(element), amount: *amount },
+                SlotFilterDef::Forbid { element, amount } => SlotFilter::Forbid { element: key(element), amount: *amount },
+            })
+            .collect(),
+    }
+}
@@ -0,0 +1,622 @@
+//! The Crucible language server, started with `crucible lsp`.
+//!
+//! The server speaks the Language Server Protocol over the standard
+//! input and output. Whenever a document is opened, changed or saved,
+//! the whole project is parsed and resolved again, with the editor's
+//! text standing in for any file that has unsaved changes. The result
+//! is kept as an [Analysis], which answers every request until the
+//! next change.
+//!
+//! The project is found the same way as on the command line: if the
+//! editor opened a directory, every source file in it is compiled,
+//! as described by its `Crucible.toml` if it has one. Open documents
+//! outside of that directory are compiled along with it.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, DidSaveTextDocument,
+    Notification as _, PublishDiagnostics,
+};
+use lsp_types::request::{Completion, GotoDefinition, HoverRequest, References, Request as _};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, CompletionResponse,
+    CompletionTextEdit, DiagnosticRelatedInformation, DiagnosticSeverity, Documentation,
+    GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents, HoverParams,
+    HoverProviderCapability, InitializeParams, Location, MarkupContent, MarkupKind,
+    NumberOrString, OneOf, Position, PublishDiagnosticsParams, Range, ReferenceParams,
+    ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Url,
+};
+use mothlib::lantern::DefKey;
+use tracing::{event, Level};
+
+use crate::diagnostic::{Diagnostic, Diagnostics, FileId, Severity, SourceMap, Span, Spanned};
+use crate::parser::{self, Unit};
+use crate::{cfg, check, combine, lint, project, resolve};
+
+/// The kinds of component that can be put in an aspect list,
+/// a requirement or anything else that holds elements.
+const ELEMENTS: &[&str] = &["aspect", "card"];
+const ALL: &[&str] = &["aspect", "card", "deck", "recipe", "verb", "legacy", "ending"];
+
+/// Runs the language server until the client shuts it down.
+pub fn run() -> Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+    let params = connection.initialize(serde_json::to_value(capabilities())?)?;
+    let params: InitializeParams = serde_json::from_value(params)?;
+    event!(Level::INFO, "Language server started");
+
+    let mut server = Server {
+        connection: &connection,
+        root: root(&params),
+        documents: HashMap::new(),
+        analysis: Analysis::default(),
+        published: HashSet::new(),
+    };
+    server.refresh()?;
+    server.main_loop()?;
+    // The writer thread only stops once the connection is dropped.
+    drop(server);
+    drop(connection);
+    io_threads.join()?;
+    event!(Level::INFO, "Language server stopped");
+    Ok(())
+}
+
+fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        references_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec![".".into(), "(".into(), ",".into()]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// The directory the editor opened, if any.
+fn root(params: &InitializeParams) -> Option<PathBuf> {
+    #[allow(deprecated)]
+    let uri = match &params.workspace_folders {
+        Some(folders) if !folders.is_empty() => Some(&folders[0].uri),
+        _ => params.root_uri.as_ref(),
+    };
+    uri.and_then(|uri| uri.to_file_path().ok())
+}
+
+/// The path a file is stored under, which is canonical
+/// unless the file has never been saved.
+fn canonical(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())
+}
+
+fn document_path(uri: &Url) -> Option<PathBuf> {
+    uri.to_file_path().ok().map(|path| canonical(&path))
+}
+
+struct Server<'c> {
+    connection: &'c Connection,
+    root: Option<PathBuf>,
+    /// The text of every open document, keyed by path.
+    documents: HashMap<PathBuf, String>,
+    analysis: Analysis,
+    /// Every document that diagnostics were last published for.
+    published: HashSet<Url>,
+}
+
+impl Server<'_> {
+    fn main_loop(&mut self) -> Result<()> {
+        for message in &self.connection.receiver {
+            match message {
+                Message::Request(request) => {
+                    if self.connection.handle_shutdown(&request)? {
+                        return Ok(());
+                    }
+                    let response = self.request(request);
+                    self.connection.sender.send(Message::Response(response))?;
+                }
+                Message::Notification(notification) => self.notification(notification)?,
+                Message::Response(_) => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn request(&self, request: Request) -> Response {
+        let method = request.method.as_str();
+        if method == GotoDefinition::METHOD {
+            self.dispatch::<GotoDefinition>(request, Self::definition)
+        } else if method == References::METHOD {
+            self.dispatch::<References>(request, Self::references)
+        } else if method == Completion::METHOD {
+            self.dispatch::<Completion>(request, Self::completion)
+        } else if method == HoverRequest::METHOD {
+            self.dispatch::<HoverRequest>(request, Self::hover)
+        } else {
+            let message = format!("unsupported request `{}`", method);
+            Response::new_err(request.id, ErrorCode::MethodNotFound as i32, message)
+        }
+    }
+
+    fn dispatch<R: lsp_types::request::Request>(
+        &self,
+        request: Request,
+        handler: fn(&Self, R::Params) -> R::Result,
+    ) -> Response {
+        let id = request.id.clone();
+        match request.extract::<R::Params>(R::METHOD) {
+            Ok((id, params)) => Response::new_ok(id, handler(self, params)),
+            Err(e) => Response::new_err(id, ErrorCode::InvalidParams as i32, e.to_string()),
+        }
+    }
+
+    fn notification(&mut self, notification: Notification) -> Result<()> {
+        let method = notification.method.as_str();
+        if method == DidOpenTextDocument::METHOD {
+            let params = params::<DidOpenTextDocument>(notification)?;
+            if let Some(path) = document_path(&params.text_document.uri) {
+                self.documents.insert(path, params.text_document.text);
+            }
+        } else if method == DidChangeTextDocument::METHOD {
+            // Only full synchronization is offered, so the
+            // last change holds the whole of the new text.
+            let mut params = params::<DidChangeTextDocument>(notification)?;
+            match (document_path(&params.text_document.uri), params.content_changes.pop()) {
+                (Some(path), Some(change)) => {
+                    self.documents.insert(path, change.text);
+                }
+                _ => return Ok(()),
+            }
+        } else if method == DidCloseTextDocument::METHOD {
+            let params = params::<DidCloseTextDocument>(notification)?;
+            if let Some(path) = document_path(&params.text_document.uri) {
+                self.documents.remove(&path);
+            }
+        } else if method != DidSaveTextDocument::METHOD {
+            return Ok(());
+        }
+        self.refresh()
+    }
+
+    /// Analyzes the project again and publishes its diagnostics.
+    fn refresh(&mut self) -> Result<()> {
+        self.analysis = Analysis::new(&self.files(), &self.documents);
+
+        let mut by_file: HashMap<Url, Vec<lsp_types::Diagnostic>> = HashMap::new();
+        for path in self.analysis.files.keys() {
+            if let Ok(uri) = Url::from_file_path(path) {
+                by_file.entry(uri).or_default();
+            }
+        }
+        for diagnostic in self.analysis.diagnostics.iter() {
+            if let Some(location) = self.analysis.location(diagnostic.span) {
+                let converted = self.analysis.diagnostic(diagnostic, location.range);
+                by_file.entry(location.uri).or_default().push(converted);
+            }
+        }
+        // Files which no longer have any diagnostics still have
+        // to be told so, or the old ones will stay on screen.
+        for uri in self.published.drain() {
+            by_file.entry(uri).or_default();
+        }
+        for (uri, diagnostics) in by_file {
+            if !diagnostics.is_empty() {
+                self.published.insert(uri.clone());
+            }
+            let params = PublishDiagnosticsParams::new(uri, diagnostics, None);
+            let notification = Notification::new(PublishDiagnostics::METHOD.to_owned(), params);
+            self.connection.sender.send(Message::Notification(notification))?;
+        }
+        Ok(())
+    }
+
+    /// The files to compile: the project in the directory the
+    /// editor opened, along with every open document.
+    fn files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = match &self.root {
+            Some(root) => match project::discover(&[root.clone()], &[], &[]) {
+                Ok(project) => project
+                    .files
+                    .into_iter()
+                    .filter_map(|file| std::fs::canonicalize(file).ok())
+                    .collect(),
+                Err(e) => {
+                    event!(Level::DEBUG, "No project found in {}: {}", root.display(), e);
+                    Vec::new()
+                }
+            },
+            None => Vec::new(),
+        };
+        let mut open: Vec<&PathBuf> = self.documents.keys().filter(|path| !files.contains(path)).collect();
+        open.sort();
+        files.extend(open.into_iter().cloned());
+        files
+    }
+
+    fn definition(&self, params: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let position = params.text_document_position_params;
+        let id = self.analysis.symbol_at(&position.text_document.uri, position.position)?;
+        let declaration = self.analysis.declarations.get(id)?;
+        self.analysis.location(declaration.span).map(GotoDefinitionResponse::Scalar)
+    }
+
+    fn references(&self, params: ReferenceParams) -> Option<Vec<Location>> {
+        let position = params.text_document_position;
+        let id = self.analysis.symbol_at(&position.text_document.uri, position.position)?;
+        let declaration = self
+            .analysis
+            .declarations
+            .get(id)
+            .filter(|_| params.context.include_declaration)
+            .map(|declaration| declaration.span);
+        let references = self
+            .analysis
+            .references
+            .iter()
+            .filter(|reference| reference.0 == id)
+            .map(|reference| reference.span);
+        Some(
+            declaration
+                .into_iter()
+                .chain(references)
+                .filter_map(|span| self.analysis.location(span))
+                .collect(),
+        )
+    }
+
+    fn completion(&self, params: CompletionParams) -> Option<CompletionResponse> {
+        let position = params.text_document_position;
+        let text = self.documents.get(&document_path(&position.text_document.uri)?)?;
+        let cursor = offset(text, position.position);
+        let before = &text[..cursor];
+        let kinds = expected_kinds(before)?;
+
+        let start = before.trim_end_matches(parser::is_defkey_char).len();
+        let range = Range::new(self::position(text, start), position.position);
+        let mut items: Vec<CompletionItem> = self
+            .analysis
+            .declarations
+            .iter()
+            .filter(|(_, declaration)| kinds.contains(&declaration.kind))
+            .map(|(id, declaration)| CompletionItem {
+                label: id.clone(),
+                kind: Some(completion_kind(declaration.kind)),
+                detail: Some(declaration.detail()),
+                documentation: declaration
+                    .description
+                    .clone()
+                    .map(Documentation::String),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(range, id.clone()))),
+                ..Default::default()
+            })
+            .collect();
+        items.sort_by(|a, b| a.label.cmp(&b.label));
+        Some(CompletionResponse::Array(items))
+    }
+
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let position = params.text_document_position_params;
+        let id = self.analysis.symbol_at(&position.text_document.uri, position.position)?;
+        let declaration = self.analysis.declarations.get(id)?;
+
+        let mut value = format!("```crucible\n{} {}\n```", declaration.kind, id);
+        if let Some(label) = &declaration.label {
+            value.push_str(&format!("\n**{}**", label));
+        }
+        if let Some(description) = &declaration.description {
+            value.push_str(&format!("\n\n{}", description));
+        }
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }),
+            range: None,
+        })
+    }
+}
+
+fn params<N: lsp_types::notification::Notification>(notification: Notification) -> Result<N::Params> {
+    Ok(serde_json::from_value(notification.params)?)
+}
+
+/// Editors only use this to choose an icon for each item.
+fn completion_kind(kind: &str) -> CompletionItemKind {
+    match kind {
+        "aspect" => CompletionItemKind::CONSTANT,
+        "card" => CompletionItemKind::VALUE,
+        "deck" => CompletionItemKind::STRUCT,
+        "recipe" => CompletionItemKind::METHOD,
+        "verb" => CompletionItemKind::FUNCTION,
+        _ => CompletionItemKind::EVENT,
+    }
+}
+
+/// A component, as shown in hovers and completions.
+struct Declaration {
+    kind: &'static str,
+    span: Span,
+    label: Option<String>,
+    description: Option<String>,
+}
+
+impl Declaration {
+    fn detail(&self) -> String {
+        match &self.label {
+            Some(label) => format!("{} \"{}\"", self.kind, label),
+            None => self.kind.to_owned(),
+        }
+    }
+}
+
+/// Everything known about the project as of the last change.
+#[derive(Default)]
+struct Analysis {
+    sources: SourceMap,
+    diagnostics: Diagnostics,
+    /// Every component, keyed by full id.
+    declarations: HashMap<String, Declaration>,
+    /// Every reference to a component, after resolution.
+    references: Vec<Spanned<DefKey>>,
+    /// The file id of every file read, keyed by canonical path.
+    files: HashMap<PathBuf, FileId>,
+}
+
+impl Analysis {
+    fn new(files: &[PathBuf], overlay: &HashMap<PathBuf, String>) -> Self {
+        let mut analysis = Analysis::default();
+        match parser::parse_with(files, overlay, &mut analysis.sources, &mut analysis.diagnostics) {
+            Ok(mut crucible) => {
+                // Without any `--cfg` flags, as a plain build would be.
+                let compiled_out = cfg::apply(&mut crucible, &cfg::Flags::default());
+                resolve::resolve(&mut crucible, compiled_out, &mut analysis.diagnostics);
+                check::check(&mut crucible, &mut analysis.diagnostics);
+                combine::combine_all(&mut crucible, &mut analysis.diagnostics);
+                if !analysis.diagnostics.has_errors() {
+                    lint::lint(&mut crucible, &lint::Levels::default(), &mut analysis.diagnostics);
+                }
+                analysis.index(crucible.units_mut());
+            }
+            Err(e) => event!(Level::WARN, "Could not analyze the project: {}", e),
+        }
+        for (id, file) in analysis.sources.iter() {
+            analysis.files.entry(canonical(file.path())).or_insert(id);
+        }
+        analysis
+    }
+
+    fn index(&mut self, units: &mut [Unit]) {
+        for unit in units {
+            match unit {
+                Unit::Namespace { units, .. } => self.index(units),
+                Unit::Component { id, component, inherits, .. } => {
+                    let nonempty = |text: Option<&str>| text.filter(|text| !text.is_empty()).map(str::to_owned);
+                    self.declarations.entry(id.0.clone()).or_insert(Declaration {
+                        kind: component.kind(),
+                        span: id.span,
+                        label: nonempty(component.label()),
+                        description: nonempty(component.description()),
+                    });
+                    let references = component.references_mut().into_iter().map(|(_, r)| r).chain(inherits.as_mut());
+                    self.references.extend(references.map(|reference| reference.clone()));
+                }
+                Unit::Expansion { units, .. } => self.index(units),
+                Unit::For { iterations, .. } => {
+                    for iteration in iterations {
+                        self.index(&mut iteration.units);
+                    }
+                }
+                Unit::Use { .. } | Unit::Const { .. } | Unit::Template { .. } => (),
+            }
+        }
+    }
+
+    /// The full id of the component declared or referred to at `position`.
+    fn symbol_at(&self, uri: &Url, position: Position) -> Option<&str> {
+        let file = *self.files.get(&document_path(uri)?)?;
+        let offset = offset(self.sources.get(file).text(), position);
+        let contains = |span: &Span| span.file == file && span.start <= offset && offset <= span.end;
+        self.references
+            .iter()
+            .find(|reference| contains(&reference.span))
+            .map(|reference| reference.0.as_str())
+            .or_else(|| {
+                self.declarations
+                    .iter()
+                    .find(|(_, declaration)| contains(&declaration.span))
+                    .map(|(id, _)| id.as_str())
+            })
+    }
+
+    fn location(&self, span: Span) -> Option<Location> {
+        let file = self.sources.get(span.file);
+        let uri = Url::from_file_path(canonical(file.path())).ok()?;
+        let range = Range::new(position(file.text(), span.start), position(file.text(), span.end));
+        Some(Location::new(uri, range))
+    }
+
+    fn diagnostic(&self, diagnostic: &Diagnostic, range: Range) -> lsp_types::Diagnostic {
+        let severity = match diagnostic.severity {
+            Severity::Error => DiagnosticSeverity::ERROR,
+            Severity::Warning => DiagnosticSeverity::WARNING,
+            Severity::Note => DiagnosticSeverity::INFORMATION,
+        };
+        let mut message = diagnostic.message.clone();
+        for note in &diagnostic.notes {
+            message.push_str(&format!("\nnote: {}", note));
+        }
+        let related: Vec<DiagnosticRelatedInformation> = diagnostic
+            .labels
+            .iter()
+            .filter_map(|label| {
+                Some(DiagnosticRelatedInformation {
+                    location: self.location(label.span)?,
+                    message: label.message.clone(),
+                })
+            })
+            .collect();
+        lsp_types::Diagnostic {
+            range,
+            severity: Some(severity),
+            code: Some(NumberOrString::String(diagnostic.code.to_string())),
+            source: Some("crucible".to_owned()),
+            message,
+            related_information: (!related.is_empty()).then_some(related),
+            ..Default::default()
+        }
+    }
+}
+
+/// Converts a byte offset into a protocol position,
+/// whose columns are counted in UTF-16 code units.
+fn position(text: &str, offset: usize) -> Position {
+    let offset = offset.min(text.len());
+    let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line = text[..line_start].matches('\n').count();
+    let character = text[line_start..offset].encode_utf16().count();
+    Position::new(line as u32, character as u32)
+}
+
+/// Converts a protocol position into a byte offset, clamping
+/// positions past the end of a line to the end of that line.
+fn offset(text: &str, position: Position) -> usize {
+    let line_start = match position.line {
+        0 => 0,
+        line => match text.match_indices('\n').nth(line as usize - 1) {
+            Some((i, _)) => i + 1,
+            None => return text.len(),
+        },
+    };
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= position.character as usize || c == '\n' {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+/// The kinds of component which can be named at the end of `before`,
+/// the text leading up to the cursor, or `None` if the cursor is
+/// not somewhere that a reference can be written.
+fn expected_kinds(before: &str) -> Option<&'static [&'static str]> {
+    let before = before.trim_end_matches(parser::is_defkey_char).trim_end();
+    // `!` marks a deck's default card or a forbidden slot element.
+    let before = before.strip_suffix('!').unwrap_or(before).trim_end();
+    if before.ends_with("->") {
+        return Some(ELEMENTS);
+    }
+    match before.chars().last()? {
+        '(' | ',' => return Some(ELEMENTS),
+        '{' | ';' => {
+            let header = code_words(enclosing_header(before)?);
+            return if header.contains(&"deck") {
+                Some(&["card"])
+            } else if header.last() == Some(&"expel") {
+                Some(ELEMENTS)
+            } else {
+                None
+            };
+        }
+        _ => (),
+    }
+
+    let word = trailing_word(before);
+    let previous = trailing_word(before[..before.len() - word.len()].trim_end());
+    let is = |keyword: &str| word.eq_ignore_ascii_case(keyword);
+    if is("from") || is("use") {
+        Some(ALL)
+    } else if is("draw") {
+        Some(&["deck"])
+    } else if is("purge") || is("apply") || previous.eq_ignore_ascii_case("apply") {
+        Some(ELEMENTS)
+    } else if is("halt") || is("delete") {
+        Some(&["verb"])
+    } else if is("link") || is("goto") {
+        Some(&["recipe"])
+    } else if is("ending") {
+        // Outside of a recipe, `ending` declares one instead.
+        let header = code_words(enclosing_header(before)?);
+        header.contains(&"recipe").then_some(&["ending"])
+    } else {
+        None
+    }
+}
+
+fn trailing_word(text: &str) -> &str {
+    &text[text.trim_end_matches(parser::is_defkey_char).len()..]
+}
+
+/// The text before the innermost `{` still open at the end of
+/// `text`, going back as far as the end of the previous statement.
+fn enclosing_header(text: &str) -> Option<&str> {
+    let mut open = Vec::new();
+    let mut statement = 0;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' => break,
+                        _ => (),
+                    }
+                }
+            }
+            '/' if matches!(chars.peek(), Some((_, '/'))) => {
+                for (_, c) in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '{' => {
+                open.push((statement, i));
+                statement = i + 1;
+            }
+            '}' => {
+                open.pop();
+                statement = i + 1;
+            }
+            ';' => statement = i + 1,
+            _ => (),
+        }
+    }
+    open.last().map(|&(start, end)| &text[start..end])
+}
+
+/// The words of `text`, leaving out strings and comments.
+fn code_words(text: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("//") {
+            rest = rest.find('\n').map_or("", |i| &rest[i..]);
+        } else if c == '"' {
+            let mut escaped = false;
+            let end = rest[1..].char_indices().find(|&(_, c)| {
+                let end = c == '"' && !escaped;
+                escaped = c == '\\' && !escaped;
+                end
+            });
+            rest = end.map_or("", |(i, _)| &rest[i + 2..]);
+        } else if parser::is_defkey_char(c) {
+            let end = rest.find(|c| !parser::is_defkey_char(c)).unwrap_or(rest.len());
+            words.push(&rest[..end]);
+            rest = &rest[end..];
+        } else {
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    words
+}
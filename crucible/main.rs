@@ -1,12 +1,29 @@
 #![allow(dead_code)]
 
-use std::path::PathBuf;
-use anyhow::{Result, bail};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use anyhow::{Context, Result, bail};
 use tracing::{event, Level};
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use tracing_subscriber::FmtSubscriber;
 
+mod cfg;
+mod check;
+mod combine;
+mod decompile;
+mod diagnostic;
+mod emit;
+mod fmt;
+mod inherit;
+mod lint;
+mod lower;
+mod lsp;
 mod parser;
+mod project;
+mod resolve;
+
+use diagnostic::{Diagnostics, SourceMap};
+use mothlib::lantern::lir;
 
 static LONG_ABOUT: &str = r#"Compile source code written in the Crucible programming language to Cultist Simulator JSON mod files.\n\"IN THE DESERT I WAIT IN THE RUINS I BURN - METAL IS WATER - STONE IS WAX - FLESH IS SMOKE - ENTER ME AND BE NO LONGER.\" - King Crucible"#;
 /* 
@@ -29,14 +46,31 @@ struct Args {
 #[derive(Parser, Debug)]
 #[command(author, version, about, about = "Compile source code written in the Crucible programming language to Cultist Simulator JSON mod files.", long_about = LONG_ABOUT)]
 #[command(propagate_version = true)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// A list of input files to operate on.
     /// If one or more directories are specified,
     /// Crucible will walk those directory trees
     /// and attempt to compile any file that ends
-    /// with the `.crucible` extension.
+    /// with the `.crucible` extension. A directory
+    /// containing a `Crucible.toml` project file is
+    /// built as described by that file instead.
+    /// Defaults to the current directory.
     input: Vec<PathBuf>,
 
+    /// Only compile files in input directories whose
+    /// path matches this glob. May be given more than once.
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// Skip files in input directories whose path
+    /// matches this glob. May be given more than once.
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
     /// By default, Crucible will compress its
     /// output and emit a `.lirc` file. Enable
     /// this flag to force it to output non-compressed
@@ -46,10 +80,46 @@ struct Args {
     
     /// Specify a custom output file to emit to.
     /// If no path is specified, defaults to the
-    /// standard output.
+    /// standard output, where the file is sent
+    /// using the Lantern IR transmission protocol.
     #[arg(short, long)]
     output: Option<PathBuf>,
 
+    /// Print a stage of compilation instead of the
+    /// compiled mod, either to the output file or to
+    /// the standard output.
+    #[arg(long, value_enum, value_name = "STAGE")]
+    emit: Option<emit::Stage>,
+
+    /// Set a flag for `#[cfg]` attributes to check,
+    /// written as `name` or `key=value`. May be given
+    /// more than once.
+    #[arg(long = "cfg", value_name = "FLAG")]
+    cfg: Vec<String>,
+
+    /// Don't report a lint, unless an attribute in
+    /// the source asks for it. May be given more
+    /// than once.
+    #[arg(long, value_name = "LINT")]
+    allow: Vec<String>,
+
+    /// Report a lint as a warning, unless an
+    /// attribute in the source says otherwise.
+    /// May be given more than once.
+    #[arg(long, value_name = "LINT")]
+    warn: Vec<String>,
+
+    /// Report a lint as an error, unless an
+    /// attribute in the source says otherwise.
+    /// May be given more than once.
+    #[arg(long, value_name = "LINT")]
+    deny: Vec<String>,
+
+    /// Only print the component with this full id
+    /// when using `--emit`.
+    #[arg(long, value_name = "ID", requires = "emit")]
+    component: Option<String>,
+
     /// Increase log output. Use multiple times to further increase verbosity.
     #[arg(global = true, short, long, action = clap::ArgAction::Count, conflicts_with = "quiet")]
     verbose: u8,
@@ -59,6 +129,40 @@ struct Args {
     quiet: u8,
 }
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run a language server for Crucible source files,
+    /// speaking the Language Server Protocol over the
+    /// standard input and output.
+    Lsp,
+    /// Rewrite Crucible source files in the canonical layout.
+    Fmt {
+        /// The files to format. Directories are searched
+        /// the same way as when compiling. Defaults to the
+        /// current directory.
+        input: Vec<PathBuf>,
+
+        /// Don't write any files, but fail if
+        /// any of them are not formatted.
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        check: bool,
+    },
+    /// Turn a compiled mod, or the game's JSON, back
+    /// into Crucible source.
+    Decompile {
+        /// The `.lir` or `.lirc` file to decompile, or any
+        /// number of `.json` files. Directories are searched
+        /// for `.json` files.
+        #[arg(required = true)]
+        input: Vec<PathBuf>,
+
+        /// Write the source to this file instead
+        /// of to the standard output.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     if let Err(e) = color_eyre::install() { bail!(e) };
@@ -85,39 +189,119 @@ async fn main() -> Result<()> {
 
     // Quiet > 2 means be totally silent - panics only.
     if cli.quiet <= 3 {
+        // The standard output is reserved for compiled data.
         let subscriber = FmtSubscriber::builder()
         .with_max_level(level)
+        .with_writer(std::io::stderr)
         .finish();
         tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
     }
 
-    let valid_paths = {
-        let mut valid_paths: Vec<PathBuf> = Vec::new();
-        for path in cli.input {
-            let meta = std::fs::metadata(path.clone())
-                .unwrap_or_else(|_| panic!("Path did not exist: {}", path.to_str().unwrap()));
-            if meta.is_file() {
-                valid_paths.push(path);
-            }
-            else {
-                event!(
-                    Level::ERROR, 
-                    "Only file processing is currently supported. Path `{}` was type `{:?}`", 
-                    path.to_str().unwrap(), 
-                    std::fs::metadata(path.clone())
-                        .unwrap_or_else(|_| panic!("Path did not exist: {}", path.to_str().unwrap()))
-                        .file_type()
-                );
-                bail!("Invalid Operation")
-            }
+    match cli.command {
+        Some(Command::Lsp) => return lsp::run(),
+        Some(Command::Fmt { input, check }) => {
+            let inputs = if input.is_empty() { vec![PathBuf::from(".")] } else { input };
+            let project = project::discover(&inputs, &[], &[])?;
+            return fmt::run(&project.files, check);
+        }
+        Some(Command::Decompile { input, output }) => {
+            let source = decompile::run(&input)?;
+            return write_dump(output.as_deref(), &source);
+        }
+        None => (),
+    }
+
+    let flags = cfg::Flags::new(&cli.cfg)?;
+    let levels = lint::Levels::new(&cli.allow, &cli.warn, &cli.deny)?;
+    let inputs = if cli.input.is_empty() { vec![PathBuf::from(".")] } else { cli.input };
+    let project = project::discover(&inputs, &cli.include, &cli.exclude)?;
+    if let Some(name) = &project.name {
+        event!(Level::INFO, "Building mod `{}`", name);
+    }
+
+    let mut sources = SourceMap::new();
+    let mut diagnostics = Diagnostics::new();
+    let mut crucible = parser::parse(&project.files, &mut sources, &mut diagnostics)?;
+    let filter = cli.component.as_deref();
+    if cli.emit == Some(emit::Stage::Ast) {
+        report(&diagnostics, &sources);
+        let dump = emit::units(crucible.units(), false, filter)?;
+        return write_dump(cli.output.as_deref(), &dump);
+    }
+
+    let compiled_out = cfg::apply(&mut crucible, &flags);
+    resolve::resolve(&mut crucible, compiled_out, &mut diagnostics);
+    // Checking or inheriting from a component that failed
+    // to resolve would only repeat the same errors.
+    if !diagnostics.has_errors() {
+        check::check(&mut crucible, &mut diagnostics);
+        inherit::inherit(&mut crucible, &mut diagnostics);
+        // After inheriting, so that a child's requirements
+        // are combined with those it inherits.
+        combine::combine_all(&mut crucible, &mut diagnostics);
+    }
+    // Lints are only worth reading once the mod compiles.
+    if !diagnostics.has_errors() {
+        lint::lint(&mut crucible, &levels, &mut diagnostics);
+    }
+
+    report(&diagnostics, &sources);
+    if diagnostics.has_errors() {
+        event!(Level::ERROR, "Compilation failed with {} error(s)", diagnostics.error_count());
+        bail!("Encountered errors during Parsing")
+    }
+    for input in crucible.inputs() {
+        event!(Level::DEBUG, "Read input file {}", input.display());
+    }
+    if cli.emit == Some(emit::Stage::Resolved) {
+        let dump = emit::units(crucible.units(), true, filter)?;
+        return write_dump(cli.output.as_deref(), &dump);
+    }
+
+    let lantern = lower::lower(&crucible);
+    match cli.emit {
+        Some(emit::Stage::Lantern) => return write_dump(cli.output.as_deref(), &emit::lantern(&lantern, filter)?),
+        Some(emit::Stage::Json) => return write_dump(cli.output.as_deref(), &emit::json(&lantern, filter)?),
+        _ => (),
+    }
+
+    let compress = !cli.no_compression && project.compression.unwrap_or(true);
+    let file = lir::encode(&lantern, compress)?;
+    match cli.output.or(project.output) {
+        Some(path) => write_file(&path, &file)?,
+        None => {
+            let mut stdout = std::io::stdout().lock();
+            lir::write_frame(&mut stdout, &file)?;
+            lir::write_end(&mut stdout)?;
         }
-        valid_paths
-    };
-    match parser::parse(valid_paths) {
-        Ok(c) => event!(Level::INFO, "{:#?}", c),
-        Err(e) => event!(Level::ERROR, "{}", e),
     }
 
     event!(Level::INFO, "Done");
     Ok(())
-}
\ No newline at end of file
+}
+fn report(diagnostics: &Diagnostics, sources: &SourceMap) {
+    for diagnostic in diagnostics.iter() {
+        eprintln!("{}", diagnostic.display(sources));
+    }
+}
+
+fn write_file(path: &Path, contents: &[u8]) -> Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, contents)
+        .with_context(|| format!("Could not write output file `{}`", path.display()))?;
+    event!(Level::INFO, "Wrote {}", path.display());
+    Ok(())
+}
+
+/// Writes an `--emit` dump to `path`, or to the standard output.
+fn write_dump(path: Option<&Path>, dump: &str) -> Result<()> {
+    match path {
+        Some(path) => write_file(path, dump.as_bytes()),
+        None => {
+            std::io::stdout().lock().write_all(dump.as_bytes())?;
+            Ok(())
+        }
+    }
+}
@@ -9,7 +9,6 @@ use tracing::{event, Level};
 use anyhow::{bail, Result};
 
 use nom::{
-    IResult,
     bytes::complete::*,
     character::complete::*,
     multi::*,
@@ -20,124 +19,121 @@ use nom::{
 };
 use super::*;
 
-pub fn parse(input: &str) -> IResult<&str, Component> {
-    let (remain, (hidden, _, id, title, desc, decays_to, statements)) = tuple((
+#[derive(Debug, Clone)]
+pub struct AspectDef {
+    pub id: Spanned<DefKey>,
+    pub label: String,
+    pub description: String,
+    pub icon: Option<String>,
+    pub verbicon: Option<String>,
+    pub induces: Option<(Spanned<DefKey>, Probability)>,
+    pub decays_to: Option<Spanned<DefKey>>,
+    pub hidden: bool,
+    pub xtriggers: Vec<XtriggerDef>,
+    pub others: HashMap<DefKey, json::Value>,
+}
+
+pub fn parse(input: Input) -> IResult<Component> {
+    let (remain, (hidden, _, (id, title, desc, decays_to, statements))) = tuple((
         opt(ws(hidden)),
-        ws(tag_no_case("aspect")),
-        ws(defkey),
-        ws(string::parse),
-        ws(string::parse),
-        opt(ws(aspect_decays)),
-        delimited(
-            ws(tag("{")),
-            separated_list0(line_ending, ws(aspect_statement)),
-            ws(tag("}"))
-        ),
-    ))(input)?; 
+        ws(keyword("aspect")),
+        cut(tuple((
+            ws(spanned(defkey)),
+            ws(string),
+            opt(ws(string)),
+            opt(ws(aspect_decays)),
+            opt(block(aspect_statement)),
+        ))),
+    ))(input)?;
 
-    Ok((remain, Component::Aspect(Box::new(aspect_from_tokens(id, title, desc, hidden.is_some(), decays_to, statements)?))))
+    let statements = statements.unwrap_or_default();
+    Ok((remain, Component::Aspect(Box::new(aspect_from_tokens(input.extra, id, title, desc, hidden.is_some(), decays_to, statements)))))
 }
 
-fn aspect_decays(input: &str) -> IResult<&str, DefKey> {
-    let (remain, (_, key)) = pair(ws(tag("->")), ws(defkey))(input)?;
+fn aspect_decays(input: Input) -> IResult<Spanned<DefKey>> {
+    let (remain, (_, key)) = pair(ws(tag("->")), cut(ws(spanned(defkey))))(input)?;
     Ok((remain, key))
 }
 enum AspectStatement {
     Set(DefKey, json::Value),
-    Induce(DefKey, Probability),
-    Xtrigger(Xtrigger),
+    Induce(Spanned<DefKey>, Probability),
+    Xtrigger(XtriggerDef),
 }
 
-fn aspect_statement(input: &str) -> IResult<&str, AspectStatement> {
-    fn set(input: &str) -> IResult<&str, AspectStatement> {
-        let (remain, (_, (key, val))) = pair(ws(tag_no_case("set")),
-        separated_pair(
+fn aspect_statement(input: Input) -> IResult<AspectStatement> {
+    fn set(input: Input) -> IResult<AspectStatement> {
+        let (remain, (_, (key, val))) = pair(ws(keyword("set")),
+        cut(separated_pair(
             ws(defkey),
-            char('='),
-            ws(json::parse)
-        ))(input)?;
+            context("`=`", char('=')),
+            ws(json_value)
+        )))(input)?;
 
         Ok((remain, AspectStatement::Set(key, val)))
     }
 
-    fn induce(input: &str) -> IResult<&str, AspectStatement> {
-        let (remain, (_, (key, chance))) = pair(ws(tag_no_case("induce")),
-        pair(
-            ws(defkey),
-            ws(chance)
-        ))(input)?;
+    fn induce(input: Input) -> IResult<AspectStatement> {
+        let (remain, (_, (key, chance))) = pair(ws(keyword("induce")),
+        cut(pair(
+            ws(spanned(defkey)),
+            opt(ws(chance))
+        )))(input)?;
 
+        let chance = chance.unwrap_or_else(|| Probability::new(100).unwrap());
         Ok((remain, AspectStatement::Induce(key, chance)))
     }
 
-    fn xtrigger(input: &str) -> IResult<&str, AspectStatement> {
+    fn xtrigger(input: Input) -> IResult<AspectStatement> {
         let (remain, xtrigger) = super::xtrigger(input)?;
         Ok((remain, AspectStatement::Xtrigger(xtrigger)))
     }
 
-    alt((
+    context("an aspect statement", alt((
         ws(set),
         ws(induce),
         ws(xtrigger),
-    ))(input)
+    )))(input)
 }
 
-fn aspect_from_tokens<I>(id: DefKey, title: String, desc: String, hidden: bool, decays_to: Option<DefKey>, statements: Vec<AspectStatement>) -> Result<Aspect, nom::Err<nom::error::Error<I>>> {
+fn aspect_from_tokens(state: &ParseState, id: Spanned<DefKey>, title: String, desc: Option<String>, hidden: bool, decays_to: Option<Spanned<DefKey>>, statements: Vec<Spanned<AspectStatement>>) -> AspectDef {
     // Initialize Defaults
     let id = id;
     let label = title;
-    let description = desc;
+    let has_description = desc.is_some();
+    let mut description = desc.unwrap_or_default();
     let mut icon: Option<String> = None;
     let mut verbicon: Option<String> = None;
-    let mut induces: Option<(DefKey, Probability)> = None;
-    let mut xtriggers: Vec<Xtrigger> = Vec::new();
+    let mut induces: Option<(Spanned<DefKey>, Probability)> = None;
+    let mut induce_span: Option<Span> = None;
+    let mut xtriggers: Vec<XtriggerDef> = Vec::new();
     let mut others: HashMap<DefKey, json::Value> = HashMap::new();
+    let mut assigned: HashMap<DefKey, Span> = HashMap::new();
 
-    for st in statements {
+    for Spanned { item: st, span } in statements {
         match st {
             AspectStatement::Set(k, v) => {
                 match k.0.as_str() {
-                    "id" => todo!("Failure! id cannot be set outside of the aspect signature"),
-                    "label" => todo!("Failure! label cannot be set outside of the aspect signature"),
-                    "description" => todo!("Failure! Description cannot be set outside of the aspect signature"),
-                    "icon" => {
-                        if let Some(old) = icon {
-                            todo!("Failure! Key '{}' is already assigned with SET for this aspect: {:?}", k.0.as_str(), old)
-                        }
-                        else if let json::Value::Str(s) = v {
-                            icon = Some(s)
-                        }
-                        else {
-                            todo!("Failure! Key '{}' must be of type 'string': {:?}", k.0.as_str(), v)
-                        }
-                    },
-                    "verbicon" => {
-                        if let Some(old) = verbicon {
-                            todo!("Failure! Key '{}' is already assigned with SET for this aspect: {:?}", k.0.as_str(), old)
-                        }
-                        else if let json::Value::Str(s) = v {
-                            verbicon = Some(s)
-                        }
-                        else {
-                            todo!("Failure! Key '{}' must be of type 'string': {:?}", k.0.as_str(), v)
-                        }
-                    },
-                    _ => if let Some(old) = others.insert(k.clone(), v) {
-                        todo!("Failure! Key '{}' is already assigned with SET for this aspect: {:?}", k.0.as_str(), old)
-                    },
+                    "id" | "label" => reserved_key(state, &k, span, "aspect"),
+                    "description" if has_description => description_in_signature(state, &k, span, "aspect"),
+                    _ if !first_assignment(state, &mut assigned, &k, span, "aspect") => (),
+                    "description" => description = expect_string(state, &k, v, span).unwrap_or_default(),
+                    "icon" => icon = expect_string(state, &k, v, span),
+                    "verbicon" => verbicon = expect_string(state, &k, v, span),
+                    _ => { others.insert(k, v); },
                 }
             },
             AspectStatement::Induce(key, chance) => {
-                if induces.is_none() {
-                    induces = Some((key, chance))
-                }
-                else {
-                    todo!("Failure! Cannot set key 'induce' multiple times")
+                match induce_span {
+                    Some(first) => duplicate_statement(state, "induce", span, first, "aspect"),
+                    None => {
+                        induces = Some((key, chance));
+                        induce_span = Some(span);
+                    }
                 }
             },
             AspectStatement::Xtrigger(xtrigger) => xtriggers.push(xtrigger),
         };
     }
 
-    Ok(Aspect{id, label, description, icon, verbicon, induces, decays_to, hidden, xtriggers, others})
-}
\ No newline at end of file
+    AspectDef{id, label, description, icon, verbicon, induces, decays_to, hidden, xtriggers, others}
+}
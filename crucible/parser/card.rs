@@ -11,31 +11,50 @@ use tracing::{event, Level};
 use super::*;
 use nom::{
     branch::*, bytes::complete::*, character::complete::*, combinator::*, error::*, multi::*,
-    sequence::*, IResult,
+    sequence::*,
 };
 
-pub fn parse(input: &str) -> IResult<&str, Component> {
-    let (remain, (hidden, _, id, title, desc, aspects, decay_lifetime, statements)) = tuple((
+#[derive(Debug, Clone)]
+pub struct CardDef {
+    pub id: Spanned<DefKey>,
+    pub label: String,
+    pub description: String,
+    pub icon: Option<String>,
+    pub verbicon: Option<String>,
+    pub induces: Option<(Spanned<DefKey>, Probability)>,
+    pub decays_to: Option<Spanned<DefKey>>,
+    pub hidden: bool,
+    pub aspects: Vec<(Spanned<DefKey>, u32)>,
+    pub lifetime: Option<u32>,
+    pub resaturate: bool,
+    pub unique: bool,
+    pub uniqueness_group: Option<Spanned<DefKey>>,
+    /// Pairs of the verb a slot appears in, and the slot itself.
+    pub slots: Vec<(Spanned<DefKey>, SlotDef)>,
+    pub xtriggers: Vec<XtriggerDef>,
+    pub others: HashMap<DefKey, json::Value>,
+}
+
+pub fn parse(input: Input) -> IResult<Component> {
+    let (remain, (hidden, _, (id, title, desc, aspects, decay_lifetime, statements))) = tuple((
         opt(ws(hidden)),
-        ws(tag_no_case("card")),
-        ws(defkey),
-        ws(string::parse),
-        opt(ws(string::parse)),
-        ws(card_aspects),
-        opt(ws(card_decays)),
-        delimited(
-            ws(tag("{")),
-            separated_list0(line_ending, ws(card_statement)),
-            ws(tag("}")),
-        ),
+        ws(keyword("card")),
+        cut(tuple((
+            ws(spanned(defkey)),
+            ws(string),
+            opt(ws(string)),
+            context("an aspect list", ws(card_aspects)),
+            opt(ws(card_decays)),
+            block(card_statement),
+        ))),
     ))(input)?;
 
-    let desc = desc.unwrap_or_else(|| "".to_owned());
     let (decays_to, lifetime) = decay_lifetime.unwrap_or((None, None));
 
     Ok((
         remain,
         Component::Card(Box::new(card_from_tokens(
+            input.extra,
             id,
             title,
             desc,
@@ -44,208 +63,175 @@ pub fn parse(input: &str) -> IResult<&str, Component> {
             lifetime,
             aspects,
             statements,
-        )?)),
+        ))),
     ))
 }
 
-fn card_decays(input: &str) -> IResult<&str, (Option<DefKey>, Option<u32>)> {
-    let (remain, (_, key, lifetime)) =
-        tuple((ws(tag("->")), opt(ws(defkey)), opt(ws(u32))))(input)?;
-    Ok((remain, (key, lifetime)))
+fn card_decays(input: Input) -> IResult<(Option<Spanned<DefKey>>, Option<u32>)> {
+    // Ids may be made of digits, so a number only stands alone as
+    // the lifetime if it isn't the start of an id. Any other lifetime
+    // expression has to start with `(` or `-` to be told apart.
+    fn lifetime_only(input: Input) -> IResult<(Option<Spanned<DefKey>>, Option<u32>)> {
+        let (remain, lifetime) = preceded(
+            peek(alt((
+                recognize(terminated(digit1, not(satisfy(is_defkey_char)))),
+                tag("("),
+                tag("-"),
+            ))),
+            unsigned,
+        )(input)?;
+        Ok((remain, (None, Some(lifetime))))
+    }
+    preceded(
+        ws(tag("->")),
+        alt((ws(lifetime_only), pair(opt(ws(spanned(defkey))), opt(ws(unsigned))))),
+    )(input)
 }
 enum CardStatement {
     Set(DefKey, json::Value),
-    Induce(DefKey, Probability),
-    Unique(Option<DefKey>),
-    Slot(DefKey, Slot),
-    Xtrigger(Xtrigger),
+    Induce(Spanned<DefKey>, Probability),
+    Unique(Option<Spanned<DefKey>>),
+    Slot(Spanned<DefKey>, SlotDef),
+    Xtrigger(XtriggerDef),
 }
 
-fn card_statement(input: &str) -> IResult<&str, CardStatement> {
-    fn set(input: &str) -> IResult<&str, CardStatement> {
+fn card_statement(input: Input) -> IResult<CardStatement> {
+    fn set(input: Input) -> IResult<CardStatement> {
         let (remain, (_, (key, val))) = pair(
-            ws(tag_no_case("set")),
-            separated_pair(ws(defkey), char('='), ws(json::parse)),
+            ws(keyword("set")),
+            cut(separated_pair(ws(defkey), context("`=`", char('=')), ws(json_value))),
         )(input)?;
 
         Ok((remain, CardStatement::Set(key, val)))
     }
 
-    fn induce(input: &str) -> IResult<&str, CardStatement> {
-        let (remain, (_, (key, chance))) =
-            pair(ws(tag_no_case("induce")), pair(ws(defkey), ws(chance)))(input)?;
+    fn induce(input: Input) -> IResult<CardStatement> {
+        let (remain, (_, (key, chance))) = pair(
+            ws(keyword("induce")),
+            cut(pair(ws(spanned(defkey)), opt(ws(chance)))),
+        )(input)?;
 
+        let chance = chance.unwrap_or_else(|| Probability::new(100).unwrap());
         Ok((remain, CardStatement::Induce(key, chance)))
     }
 
-    fn unique(input: &str) -> IResult<&str, CardStatement> {
-        let (remain, (_, uqgroup)) = pair(ws(tag_no_case("unique")), opt(ws(defkey)))(input)?;
+    fn unique(input: Input) -> IResult<CardStatement> {
+        let (remain, (_, uqgroup)) =
+            pair(ws(keyword("unique")), opt(ws(spanned(defkey))))(input)?;
 
         Ok((remain, CardStatement::Unique(uqgroup)))
     }
 
-    fn xtrigger(input: &str) -> IResult<&str, CardStatement> {
+    fn xtrigger(input: Input) -> IResult<CardStatement> {
         let (remain, xtrigger) = super::xtrigger(input)?;
         Ok((remain, CardStatement::Xtrigger(xtrigger)))
     }
 
-    fn card_slot(input: &str) -> IResult<&str, CardStatement> {
-        let (remain, (key, slot)) = separated_pair(defkey, ws(tag("->")), slot)(input)?;
+    fn card_slot(input: Input) -> IResult<CardStatement> {
+        let (remain, (key, slot)) =
+            separated_pair(spanned(defkey), ws(tag("->")), cut(slot))(input)?;
 
         Ok((remain, CardStatement::Slot(key, slot)))
     }
 
-    alt((ws(set), ws(induce), ws(unique), ws(card_slot), ws(xtrigger)))(input)
+    context(
+        "a card statement",
+        alt((ws(set), ws(induce), ws(unique), ws(card_slot), ws(xtrigger))),
+    )(input)
 }
 
-fn card_aspects(input: &str) -> IResult<&str, HashMap<DefKey, u32>> {
-    fn card_aspect(input: &str) -> IResult<&str, (DefKey, u32)> {
-        alt((
-            pair(ws(defkey), success::<_, _, _>(1)),
-            separated_pair(ws(defkey), ws(char(':')), ws(u32)),
-        ))(input)
+fn card_aspects(input: Input) -> IResult<Vec<(Spanned<DefKey>, u32)>> {
+    fn card_aspect(input: Input) -> IResult<(Spanned<DefKey>, u32)> {
+        let (remain, (key, amount)) = pair(
+            ws(spanned(defkey)),
+            opt(preceded(ws(char(':')), context("an amount", cut(ws(unsigned))))),
+        )(input)?;
+        Ok((remain, (key, amount.unwrap_or(1))))
     }
 
-    let (remain, aspects) = delimited(
+    delimited(
         ws(char('(')),
         separated_list0(ws(char(',')), ws(card_aspect)),
-        ws(char(')')),
-    )(input)?;
-
-    let mut map: HashMap<DefKey, u32> = HashMap::new();
-    for (k, v) in aspects {
-        match map.insert(k.clone(), v) {
-            None => (),
-            Some(_) => todo!(
-                "Duplicate aspect assignment: the aspect {} has already been declared on the card.",
-                k
-            ),
-        };
-    }
-
-    Ok((remain, map))
+        context("`,` or `)`", cut(list_end(')'))),
+    )(input)
 }
 
 #[allow(clippy::too_many_arguments)]
-fn card_from_tokens<I>(
-    id: DefKey,
+fn card_from_tokens(
+    state: &ParseState,
+    id: Spanned<DefKey>,
     title: String,
-    desc: String,
+    desc: Option<String>,
     hidden: bool,
-    decays_to: Option<DefKey>,
+    decays_to: Option<Spanned<DefKey>>,
     lifetime: Option<u32>,
-    aspects: HashMap<DefKey, u32>,
-    statements: Vec<CardStatement>,
-) -> Result<Card, nom::Err<nom::error::Error<I>>> {
+    aspect_list: Vec<(Spanned<DefKey>, u32)>,
+    statements: Vec<Spanned<CardStatement>>,
+) -> CardDef {
     // Initialize Defaults
     let id = id;
     let label = title;
-    let description = desc;
+    let has_description = desc.is_some();
+    let mut description = desc.unwrap_or_default();
     let mut resaturate = false;
     let mut icon: Option<String> = None;
     let mut verbicon: Option<String> = None;
-    let mut induces: Option<(DefKey, Probability)> = None;
-    let mut unique: Option<bool> = None;
-    let mut uniqueness_group: Option<DefKey> = None;
-    let mut slots: HashMap<DefKey, Vec<Slot>> = HashMap::new();
-    let mut xtriggers: Vec<Xtrigger> = Vec::new();
+    let mut induces: Option<(Spanned<DefKey>, Probability)> = None;
+    let mut unique: Option<Span> = None;
+    let mut uniqueness_group: Option<Spanned<DefKey>> = None;
+    let mut uniqueness_span: Option<Span> = None;
+    let mut induce_span: Option<Span> = None;
+    let mut slots: Vec<(Spanned<DefKey>, SlotDef)> = Vec::new();
+    let mut xtriggers: Vec<XtriggerDef> = Vec::new();
     let mut others: HashMap<DefKey, json::Value> = HashMap::new();
+    let mut assigned: HashMap<DefKey, Span> = HashMap::new();
 
-    for st in statements {
+    for Spanned { item: st, span } in statements {
         match st {
             CardStatement::Set(k, v) => {
                 match k.0.as_str() {
-                    "id" => todo!("Failure! id cannot be set outside of the aspect signature"),
-                    "label" => {
-                        todo!("Failure! label cannot be set outside of the aspect signature")
-                    }
-                    "description" => {
-                        todo!("Failure! Description cannot be set outside of the aspect signature")
-                    }
-                    "icon" => {
-                        if let Some(old) = icon {
-                            todo!("Failure! Key '{}' is already assigned with SET for this aspect: {:?}", k.0.as_str(), old)
-                        } else if let json::Value::Str(s) = v {
-                            icon = Some(s)
-                        } else {
-                            todo!(
-                                "Failure! Key '{}' must be of type 'string': {:?}",
-                                k.0.as_str(),
-                                v
-                            )
-                        }
-                    }
-                    "verbicon" => {
-                        if let Some(old) = verbicon {
-                            todo!("Failure! Key '{}' is already assigned with SET for this aspect: {:?}", k.0.as_str(), old)
-                        } else if let json::Value::Str(s) = v {
-                            verbicon = Some(s)
-                        } else {
-                            todo!(
-                                "Failure! Key '{}' must be of type 'string': {:?}",
-                                k.0.as_str(),
-                                v
-                            )
-                        }
-                    }
+                    "id" | "label" => reserved_key(state, &k, span, "card"),
+                    "description" if has_description => description_in_signature(state, &k, span, "card"),
+                    _ if !first_assignment(state, &mut assigned, &k, span, "card") => (),
+                    "description" => description = expect_string(state, &k, v, span).unwrap_or_default(),
+                    "icon" => icon = expect_string(state, &k, v, span),
+                    "verbicon" => verbicon = expect_string(state, &k, v, span),
                     "resaturate" => {
-                        if let Some(old) = verbicon {
-                            todo!("Failure! Key '{}' is already assigned with SET for this aspect: {:?}", k.0.as_str(), old)
-                        } else if let json::Value::Boolean(b) = v {
+                        if let Some(b) = expect_bool(state, &k, v, span) {
                             resaturate = b;
-                        } else {
-                            todo!(
-                                "Failure! Key '{}' must be of type 'string': {:?}",
-                                k.0.as_str(),
-                                v
-                            )
                         }
                     }
                     _ => {
-                        if let Some(old) = others.insert(k.clone(), v) {
-                            todo!("Failure! Key '{}' is already assigned with SET for this aspect: {:?}", k.0.as_str(), old)
-                        }
+                        others.insert(k, v);
                     }
                 }
             }
-            CardStatement::Unique(Some(uqgroup)) => {
-                if uniqueness_group.is_none() {
-                    uniqueness_group = Some(uqgroup)
-                } else {
-                    todo!("Failure! Cannot set key 'unique <Value>' multiple times")
-                }
-            }
-            CardStatement::Unique(None) => {
-                if unique.is_none() {
-                    unique = Some(true)
-                } else {
-                    todo!("Failure! Cannot set key 'unique' multiple times")
+            CardStatement::Unique(Some(uqgroup)) => match uniqueness_span {
+                Some(first) => duplicate_statement(state, "unique <group>", span, first, "card"),
+                None => {
+                    uniqueness_group = Some(uqgroup);
+                    uniqueness_span = Some(span);
                 }
-            }
-            CardStatement::Slot(verb, slotdef) => {
-                match slots.get_mut(&verb) {
-                    Some(entry) => {
-                        entry.push(slotdef);
-                    }
-                    None => {
-                        slots.insert(verb, vec![slotdef]);
-                    }
-                };
-            }
-            CardStatement::Induce(key, chance) => {
-                if induces.is_none() {
-                    induces = Some((key, chance))
-                } else {
-                    todo!("Failure! Cannot set key 'induce' multiple times")
+            },
+            CardStatement::Unique(None) => match unique {
+                Some(first) => duplicate_statement(state, "unique", span, first, "card"),
+                None => unique = Some(span),
+            },
+            CardStatement::Slot(verb, slotdef) => slots.push((verb, slotdef)),
+            CardStatement::Induce(key, chance) => match induce_span {
+                Some(first) => duplicate_statement(state, "induce", span, first, "card"),
+                None => {
+                    induces = Some((key, chance));
+                    induce_span = Some(span);
                 }
-            }
+            },
             CardStatement::Xtrigger(xtrigger) => xtriggers.push(xtrigger),
         };
     }
 
-    let unique = unique.unwrap_or(false);
+    let unique = unique.is_some();
 
-    Ok(Card {
+    CardDef {
         id,
         label,
         description,
@@ -254,12 +240,13 @@ fn card_from_tokens<I>(
         induces,
         decays_to,
         hidden,
-        aspects,
+        aspects: aspect_list,
         lifetime,
         resaturate,
         unique,
         uniqueness_group,
         slots,
         xtriggers,
-    })
+        others,
+    }
 }
@@ -0,0 +1,95 @@
+//! `#[cfg(...)]` attributes, which compile the namespace or
+//! component they are on only when the flags passed with
+//! `--cfg` satisfy a predicate:
+//!
+//! ```text
+//! #[cfg(debug)]
+//! card debug.tools "Debug Tools" "" () {}
+//!
+//! #[cfg(all(edition = "lite", not(companion)))]
+//! namespace lite { ... }
+//! ```
+//!
+//! `name` holds when `--cfg name` was passed, and `key = "value"`
+//! when `--cfg key=value` was. `all`, `any` and `not` combine
+//! other predicates. A unit with more than one `#[cfg]` is only
+//! compiled when all of them hold. The predicates are checked by
+//! [crate::cfg], after the whole mod has been parsed.
+
+use std::fmt;
+
+use super::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Predicate {
+    Flag(String),
+    Value(String, String),
+    All(Vec<Predicate>),
+    Any(Vec<Predicate>),
+    Not(Box<Predicate>),
+}
+
+impl fmt::Display for Predicate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let list = |f: &mut fmt::Formatter, name: &str, items: &[Predicate]| {
+            let items = items.iter().map(Predicate::to_string).collect::<Vec<_>>();
+            write!(f, "{}({})", name, items.join(", "))
+        };
+        match self {
+            Predicate::Flag(name) => write!(f, "{}", name),
+            Predicate::Value(key, value) => write!(f, "{} = {:?}", key, value),
+            Predicate::All(items) => list(f, "all", items),
+            Predicate::Any(items) => list(f, "any", items),
+            Predicate::Not(inner) => write!(f, "not({})", inner),
+        }
+    }
+}
+
+/// Parses a `#[cfg(predicate)]` attribute.
+pub fn attribute(input: Input) -> IResult<Spanned<Predicate>> {
+    preceded(
+        tuple((char('#'), ws(char('[')), ws(keyword("cfg")))),
+        cut(terminated(
+            delimited(
+                context("`(`", ws(char('('))),
+                ws(spanned(predicate)),
+                context("`)`", ws(char(')'))),
+            ),
+            context("`]`", char(']')),
+        )),
+    )(input)
+}
+
+fn predicate(input: Input) -> IResult<Predicate> {
+    fn list(input: Input) -> IResult<Vec<Predicate>> {
+        delimited(
+            ws(char('(')),
+            separated_list0(ws(char(',')), ws(predicate)),
+            context("`,` or `)`", list_end(')')),
+        )(input)
+    }
+
+    let (rest, name) = context("a predicate", expr::name)(input)?;
+    let (rest, assigned) = opt(ws(char('=')))(rest)?;
+    if assigned.is_some() {
+        let (rest, value) = cut(context("a string", ws(string)))(rest)?;
+        return Ok((rest, Predicate::Value(name, value)));
+    }
+    let (_, combined) = opt(peek(ws(char('('))))(rest)?;
+    if combined.is_none() {
+        return Ok((rest, Predicate::Flag(name)));
+    }
+    match name.as_str() {
+        "all" => cut(map(list, Predicate::All))(rest),
+        "any" => cut(map(list, Predicate::Any))(rest),
+        "not" => cut(map(
+            delimited(ws(char('(')), ws(predicate), context("`)`", ws(char(')')))),
+            |inner| Predicate::Not(Box::new(inner)),
+        ))(rest),
+        _ => Err(nom::Err::Failure(Error {
+            input,
+            kind: ErrorKind::Tag,
+            expected: Some("`all`, `any` or `not`"),
+        })),
+    }
+}
@@ -11,72 +11,174 @@ use tracing::{event, Level};
 use super::*;
 use nom::{
     branch::*, bytes::complete::*, character::complete::*, combinator::*, error::*, multi::*,
-    sequence::*, IResult,
+    sequence::*,
 };
 
-pub fn parse(input: &str) -> IResult<&str, Component> {
-    let (remain, (_, id, label, description, contents)) = tuple((
-        ws(tag_no_case("deck")),
-        ws(defkey),
-        opt(ws(string::parse)),
-        opt(ws(string::parse)),
-        delimited(
-            ws(char('{')),
-            separated_list0(line_ending, ws(deck_item)),
-            ws(char('}')),
-        ),
-    ))(input)?;
+#[derive(Debug, Clone)]
+pub struct DeckDef {
+    pub id: Spanned<DefKey>,
+    pub label: String,
+    pub description: String,
+    pub default: Option<Spanned<DefKey>>,
+    /// Each card, with its draw message and how many copies
+    /// of it are in the deck.
+    pub cards: Vec<(Spanned<DefKey>, Option<String>, u32)>,
+    pub is_portal_deck: bool,
+    pub others: HashMap<DefKey, json::Value>,
+}
+
+pub fn parse(input: Input) -> IResult<Component> {
+    let (remain, (_, (id, label, description, contents))) = pair(
+        ws(keyword("deck")),
+        cut(tuple((
+            ws(spanned(defkey)),
+            opt(ws(string)),
+            opt(ws(string)),
+            block(deck_statement),
+        ))),
+    )(input)?;
 
-    Ok((remain, Component::Deck(Box::new(deck_from_tokens(id, label, description, contents)?))))
+    Ok((remain, Component::Deck(Box::new(deck_from_tokens(input.extra, id, label, description, contents)))))
 }
 
-enum DeckItem {
-    Card(DefKey),
-    Description(DefKey, String),
-    Default(DefKey),
+struct DeckItem {
+    is_default: bool,
+    card: Spanned<DefKey>,
+    /// How many copies of the card are in the deck.
+    weight: u32,
+    desc: Option<String>,
 }
 
-// returns (is_default, card, desc)
-fn deck_item(input: &str) -> IResult<&str, (bool, DefKey, Option<String>)> {
-    let (remain, (is_default, id, desc)) = tuple((
-        opt(alt((
-            tag("!"), 
-            tag_no_case("default")
-        ))),
-        defkey,
-        opt(pair(multispace1, string::parse)),
+enum DeckStatement {
+    Set(DefKey, json::Value),
+    Card(DeckItem),
+}
+
+fn deck_statement(input: Input) -> IResult<DeckStatement> {
+    // A card may be named `set`, so this only commits
+    // to being an assignment once it reaches the `=`.
+    fn set(input: Input) -> IResult<DeckStatement> {
+        let (remain, (_, key, _, val)) = tuple((
+            ws(keyword("set")),
+            ws(defkey),
+            char('='),
+            cut(ws(json_value)),
+        ))(input)?;
+
+        Ok((remain, DeckStatement::Set(key, val)))
+    }
+
+    fn card(input: Input) -> IResult<DeckStatement> {
+        let (remain, item) = deck_item(input)?;
+        Ok((remain, DeckStatement::Card(item)))
+    }
+
+    alt((ws(set), ws(card)))(input)
+}
+
+fn deck_item(input: Input) -> IResult<DeckItem> {
+    let (remain, (is_default, card, weight, desc)) = tuple((
+        opt(ws(alt((
+            tag("!"),
+            keyword("default")
+        )))),
+        context("a card id", ws(spanned(defkey))),
+        opt(preceded(ws(char('*')), context("a number of copies", cut(ws(unsigned))))),
+        opt(ws(string)),
     ))(input)?;
 
     let is_default = is_default.is_some();
-    let desc = desc.map(|(_, d)| d);
-    Ok((remain, (is_default, id, desc)))
+    Ok((remain, DeckItem { is_default, card, weight: weight.unwrap_or(1), desc }))
+}
+
+/// The cards of a deck written inline in a recipe's `draw`
+/// statement, as in `draw { a, b * 2 } 1;`.
+pub struct InlineDeck {
+    items: Vec<Spanned<DeckStatement>>,
+    /// Whether the deck stays empty once every card has been drawn,
+    /// rather than being shuffled back together, as with `draw once`.
+    once: bool,
 }
 
-fn deck_from_tokens<I>(
-    id: DefKey,
+/// Parses the cards of an inline deck, and the `once` before them.
+pub fn inline(input: Input) -> IResult<InlineDeck> {
+    let (remain, (once, items)) = pair(
+        opt(ws(keyword("once"))),
+        delimited(
+            ws(char('{')),
+            separated_list0(ws(char(',')), ws(spanned(map(deck_item, DeckStatement::Card)))),
+            context("`,` or `}`", cut(list_end('}'))),
+        ),
+    )(input)?;
+    Ok((remain, InlineDeck { items, once: once.is_some() }))
+}
+
+impl InlineDeck {
+    /// The deck, with the id of the recipe it belongs to.
+    pub fn into_def(self, state: &ParseState, id: Spanned<DefKey>) -> DeckDef {
+        let mut deck = deck_from_tokens(state, id, None, None, self.items);
+        // Without a default card, a deck is shuffled back together
+        // unless it asks not to be.
+        if self.once && deck.default.is_none() {
+            deck.others.insert(DefKey("resetonexhaustion".to_owned()), json::Value::Boolean(false));
+        }
+        deck
+    }
+}
+
+fn deck_from_tokens(
+    state: &ParseState,
+    id: Spanned<DefKey>,
     label: Option<String>,
     description: Option<String>,
-    contents: Vec<(bool, DefKey, Option<String>)>,
-) -> Result<Deck, nom::Err<nom::error::Error<I>>> {
-    let mut default: Option<DefKey> = None;
-    let mut cards: Vec<(DefKey, Option<String>)> = Vec::new();
+    contents: Vec<Spanned<DeckStatement>>,
+) -> DeckDef {
+    let has_description = description.is_some();
+    let mut description = description.unwrap_or_default();
+    let mut default: Option<Spanned<DefKey>> = None;
+    let mut default_span: Option<Span> = None;
+    let mut cards: Vec<(Spanned<DefKey>, Option<String>, u32)> = Vec::new();
     let mut is_portal_deck = false;
+    let mut others: HashMap<DefKey, json::Value> = HashMap::new();
+    let mut assigned: HashMap<DefKey, Span> = HashMap::new();
 
-    for (is_default, card, desc) in contents {
+    for Spanned { item, span } in contents {
+        let DeckItem { is_default, card, weight, desc } = match item {
+            DeckStatement::Set(k, v) => {
+                match k.0.as_str() {
+                    "id" | "label" => reserved_key(state, &k, span, "deck"),
+                    "description" if has_description => description_in_signature(state, &k, span, "deck"),
+                    _ if !first_assignment(state, &mut assigned, &k, span, "deck") => (),
+                    "description" => description = expect_string(state, &k, v, span).unwrap_or_default(),
+                    _ => { others.insert(k, v); },
+                }
+                continue;
+            }
+            DeckStatement::Card(item) => item,
+        };
         if is_default {
-            if default.is_some() {
-                todo!("Cannot set more than one default card in a deck");
+            match default_span {
+                Some(first) => state.report(
+                    Diagnostic::error(
+                        Code::DuplicateDefault,
+                        span,
+                        "cannot set more than one default card in a deck",
+                    )
+                    .with_label(first, "first default set here"),
+                ),
+                None => {
+                    default = Some(card.clone());
+                    default_span = Some(span);
+                }
             }
-            default = Some(card.clone());
         }
         if desc.is_some() {
             is_portal_deck = true;
         }
-        cards.push((card.clone(), desc));
+        cards.push((card, desc, weight));
     }
-    
+
     let label = label.unwrap_or_default();
-    let description = description.unwrap_or_default();
     // If there is no Default then we must reset on exhaustion
-    Ok(Deck { id, label, description, default, cards, is_portal_deck })
+    DeckDef { id, label, description, default, cards, is_portal_deck, others }
 }
@@ -0,0 +1,28 @@
+#![allow(unused_imports)]
+
+use std::collections::HashMap;
+use mothlib::lantern::*;
+
+use nom::{
+    bytes::complete::*,
+    character::complete::*,
+    sequence::*,
+    combinator::*,
+};
+use super::*;
+
+#[derive(Debug, Clone)]
+pub struct EndingDef {
+    pub id: Spanned<DefKey>,
+    pub others: HashMap<DefKey, json::Value>,
+}
+
+pub fn parse(input: Input) -> IResult<Component> {
+    let (remain, (_, (id, statements))) = pair(
+        ws(keyword("ending")),
+        cut(pair(ws(spanned(defkey)), opt(block(set_statement)))),
+    )(input)?;
+
+    let others = assignments(input.extra, statements.unwrap_or_default(), &["id"], "ending");
+    Ok((remain, Component::Ending(Box::new(EndingDef{ id, others }))))
+}
@@ -0,0 +1,101 @@
+//! The error type produced by the Crucible parser.
+//!
+//! nom's default error only records the input at which a
+//! parser gave up. This one also keeps the description of
+//! what was expected there (taken from `context(..)`), and
+//! when two alternatives fail it keeps whichever one got
+//! further, which is almost always the more useful report.
+
+use nom::error::{ContextError, ErrorKind, FromExternalError, ParseError};
+
+use super::Input;
+use crate::diagnostic::{Code, Diagnostic, Span};
+
+#[derive(Debug)]
+pub struct Error<'a> {
+    pub input: Input<'a>,
+    pub kind: ErrorKind,
+    pub expected: Option<&'static str>,
+}
+
+impl<'a> Error<'a> {
+    pub fn offset(&self) -> usize {
+        self.input.location_offset()
+    }
+
+    /// Converts this error into a [Diagnostic] pointing
+    /// at the first character that could not be parsed.
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        let rest = self.input.fragment();
+        let found: String = match rest.chars().next() {
+            None => "end of file".to_owned(),
+            Some(c) if c.is_whitespace() => "whitespace".to_owned(),
+            Some(_) => {
+                let token: String = rest
+                    .chars()
+                    .take_while(|c| !c.is_whitespace())
+                    .take(24)
+                    .collect();
+                format!("`{}`", token)
+            }
+        };
+        let width = rest.chars().next().map(|c| c.len_utf8()).unwrap_or(0);
+        let span = Span::new(self.input.extra.file, self.offset(), self.offset() + width);
+        let message = match self.expected {
+            Some(expected) => format!("expected {}, found {}", expected, found),
+            None => format!("unexpected {}", found),
+        };
+        Diagnostic::error(Code::Syntax, span, message)
+    }
+}
+
+impl<'a> ParseError<Input<'a>> for Error<'a> {
+    fn from_error_kind(input: Input<'a>, kind: ErrorKind) -> Self {
+        Error {
+            input,
+            kind,
+            expected: None,
+        }
+    }
+
+    fn append(_input: Input<'a>, _kind: ErrorKind, other: Self) -> Self {
+        other
+    }
+
+    fn from_char(input: Input<'a>, _c: char) -> Self {
+        Error {
+            input,
+            kind: ErrorKind::Char,
+            expected: None,
+        }
+    }
+
+    fn or(self, other: Self) -> Self {
+        if other.offset() >= self.offset() {
+            other
+        } else {
+            self
+        }
+    }
+}
+
+impl<'a> ContextError<Input<'a>> for Error<'a> {
+    fn add_context(_input: Input<'a>, ctx: &'static str, mut other: Self) -> Self {
+        // Keep the innermost context, since it describes
+        // the construct that was actually being parsed.
+        if other.expected.is_none() {
+            other.expected = Some(ctx);
+        }
+        other
+    }
+}
+
+impl<'a, E> FromExternalError<Input<'a>, E> for Error<'a> {
+    fn from_external_error(input: Input<'a>, kind: ErrorKind, _e: E) -> Self {
+        Error {
+            input,
+            kind,
+            expected: None,
+        }
+    }
+}
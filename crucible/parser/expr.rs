@@ -0,0 +1,280 @@
+//! `const` declarations and integer expressions.
+//!
+//! Anywhere a number is expected, an expression may be written
+//! instead, such as `TIER * 2` or `BASE_WARMUP + 30`. Expressions
+//! are built from whole numbers, constants, `+`, `-`, `*`, `/` and
+//! parentheses, and are worked out as soon as they are parsed, so
+//! an expression that overflows or does not fit where it is used
+//! is reported at compile time.
+//!
+//! Constants are scoped to the namespace they are declared in, and
+//! are looked up like component references: first in the enclosing
+//! namespace, then in each namespace around it, and `.NAME` names a
+//! constant declared outside of any namespace. A constant must be
+//! declared before it is used, either earlier in the same file or
+//! in a file that it imports.
+
+use super::*;
+
+/// Every constant declared so far in a build, by full path.
+pub type Constants = HashMap<String, Spanned<i64>>;
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Number(i64),
+    Constant(String),
+    Negate(Box<Spanned<Expr>>),
+    Binary(Op, Box<Spanned<Expr>>, Box<Spanned<Expr>>),
+}
+
+/// Parses a `const NAME = expression;` declaration.
+pub fn parse(input: Input) -> IResult<Unit> {
+    let (remain, (text, (_, (id, _, expr, _)))) = consumed(pair(
+        ws(keyword("const")),
+        cut(tuple((
+            context("a constant name", ws(spanned(name))),
+            context("`=`", ws(char('='))),
+            context("a number", ws(expression)),
+            context("`;`", ws(char(';'))),
+        ))),
+    ))(input)?;
+
+    let state = input.extra;
+    let value = evaluate(state, &expr).unwrap_or_default();
+    state.declare_constant(&id, value);
+    Ok((
+        remain,
+        Unit::Const {
+            id: Spanned::new(DefKey(id.item), id.span),
+            value,
+            span: span_of(&text),
+        },
+    ))
+}
+
+/// Parses an expression whose value may be any whole number.
+pub fn integer(input: Input) -> IResult<i64> {
+    number(input, Some, "a whole number", 0)
+}
+
+/// Parses an expression whose value must be a valid `u32`.
+pub fn unsigned(input: Input) -> IResult<u32> {
+    number(input, |n| u32::try_from(n).ok(), "a whole number from 0 to 4294967295", 0)
+}
+
+/// Parses an expression whose value must be a valid `i32`.
+pub fn signed(input: Input) -> IResult<i32> {
+    number(
+        input,
+        |n| i32::try_from(n).ok(),
+        "a whole number from -2147483648 to 2147483647",
+        0,
+    )
+}
+
+/// Like [signed], but produces the negation of the expression,
+/// for operators such as `-=` which subtract what follows them.
+pub fn negated(input: Input) -> IResult<i32> {
+    number(
+        input,
+        |n| n.checked_neg().and_then(|n| i32::try_from(n).ok()),
+        "a whole number from -2147483647 to 2147483648",
+        0,
+    )
+}
+
+/// Parses an expression whose value must be a chance from 0 to 100.
+pub fn probability(input: Input) -> IResult<Probability> {
+    number(
+        input,
+        |n| u8::try_from(n).ok().and_then(|n| Probability::new(n).ok()),
+        "a chance from 0 to 100",
+        Probability::new(100).unwrap(),
+    )
+}
+
+/// Parses an expression and converts its value with `convert`. A
+/// value which can't be converted is reported as out of range,
+/// with `expected` describing the values that would have been
+/// accepted, and `fallback` is used in its place.
+fn number<'a, T>(
+    input: Input<'a>,
+    convert: fn(i64) -> Option<T>,
+    expected: &str,
+    fallback: T,
+) -> IResult<'a, T> {
+    let (remain, expr) = expression(input)?;
+    let state = input.extra;
+    let value = evaluate(state, &expr).and_then(|n| {
+        let value = convert(n);
+        if value.is_none() {
+            state.report(
+                Diagnostic::error(Code::NumberOutOfRange, expr.span, format!("`{}` is out of range here", n))
+                    .with_note(format!("expected {}", expected)),
+            );
+        }
+        value
+    });
+    Ok((remain, value.unwrap_or(fallback)))
+}
+
+/// The name of a constant or template: a letter or `_`, then
+/// any letters, digits and `_`.
+pub fn name(input: Input) -> IResult<String> {
+    let (remain, name) = recognize(pair(
+        satisfy(|c| c.is_ascii_alphabetic() || c == '_'),
+        take_while(|c: char| c.is_ascii_alphanumeric() || c == '_'),
+    ))(input)?;
+    Ok((remain, name.fragment().to_string()))
+}
+
+/// A reference to a constant or template, which may add a path in
+/// front of its name, separated by `.`s, and start with a `.` to
+/// name one declared outside of any namespace.
+pub fn path(input: Input) -> IResult<String> {
+    let (remain, path) = recognize(pair(opt(char('.')), separated_list1(char('.'), name)))(input)?;
+    Ok((remain, path.fragment().to_string()))
+}
+
+/// Recognizes an expression without working out its value.
+pub fn unevaluated(input: Input) -> IResult<Input> {
+    recognize(expression)(input)
+}
+
+fn expression(input: Input) -> IResult<Spanned<Expr>> {
+    // Neither operator may be the start of `+=`, `-=` or `->`.
+    fn op(input: Input) -> IResult<Op> {
+        alt((
+            value(Op::Add, terminated(char('+'), not(char('=')))),
+            value(Op::Sub, terminated(char('-'), not(one_of("=>")))),
+        ))(input)
+    }
+    binary(input, op, term)
+}
+
+fn term(input: Input) -> IResult<Spanned<Expr>> {
+    fn op(input: Input) -> IResult<Op> {
+        alt((value(Op::Mul, char('*')), value(Op::Div, char('/'))))(input)
+    }
+    binary(input, op, unary)
+}
+
+/// Parses a left-associative chain of `operand`s joined by `op`.
+fn binary<'a>(
+    input: Input<'a>,
+    op: fn(Input<'a>) -> IResult<'a, Op>,
+    operand: fn(Input<'a>) -> IResult<'a, Spanned<Expr>>,
+) -> IResult<'a, Spanned<Expr>> {
+    let (mut input, mut lhs) = operand(input)?;
+    loop {
+        match pair(ws(op), operand)(input) {
+            Ok((remain, (op, rhs))) => {
+                let span = lhs.span.to(rhs.span);
+                lhs = Spanned::new(Expr::Binary(op, Box::new(lhs), Box::new(rhs)), span);
+                input = remain;
+            }
+            Err(nom::Err::Error(_)) => return Ok((input, lhs)),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn unary(input: Input) -> IResult<Spanned<Expr>> {
+    alt((
+        spanned(map(preceded(pair(char('-'), space), unary), |e| Expr::Negate(Box::new(e)))),
+        atom,
+    ))(input)
+}
+
+fn atom(input: Input) -> IResult<Spanned<Expr>> {
+    // A number runs into an id if it is followed by anything that
+    // could continue one, except `-`, so that `4-1` is a subtraction,
+    // and `..`, so that `1..4` is a range.
+    fn literal(input: Input) -> IResult<Expr> {
+        map_res(
+            terminated(
+                digit1,
+                alt((peek(value((), tag(".."))), not(satisfy(|c| c != '-' && is_defkey_char(c))))),
+            ),
+            |digits: Input| digits.fragment().parse().map(Expr::Number),
+        )(input)
+    }
+    alt((
+        spanned(literal),
+        spanned(map(path, Expr::Constant)),
+        map(
+            spanned(delimited(
+                pair(char('('), space),
+                expression,
+                context("`)`", cut(pair(space, char(')')))),
+            )),
+            // Keep the parentheses in the span, for diagnostics.
+            |Spanned { item, span }| Spanned::new(item.item, span),
+        ),
+    ))(input)
+}
+
+/// Works out the value of `expr`. If it can't be worked out,
+/// the problem is reported and `None` is returned.
+fn evaluate(state: &ParseState, expr: &Spanned<Expr>) -> Option<i64> {
+    let overflow = || {
+        state.report(
+            Diagnostic::error(Code::NumberOutOfRange, expr.span, "this expression overflows")
+                .with_note("expressions are worked out with 64-bit whole numbers"),
+        );
+    };
+    match &expr.item {
+        Expr::Number(n) => Some(*n),
+        Expr::Constant(name) => {
+            let value = state.constant(name);
+            if value.is_none() {
+                state.report(
+                    Diagnostic::error(
+                        Code::UnresolvedConstant,
+                        expr.span,
+                        format!("cannot find constant `{}` in this scope", name),
+                    )
+                    .with_note("constants must be declared before they are used"),
+                );
+            }
+            value
+        }
+        Expr::Negate(inner) => {
+            let value = evaluate(state, inner)?.checked_neg();
+            if value.is_none() {
+                overflow();
+            }
+            value
+        }
+        Expr::Binary(op, lhs, rhs) => {
+            let (lhs, rhs) = (evaluate(state, lhs), evaluate(state, rhs));
+            let (lhs, rhs) = (lhs?, rhs?);
+            let value = match op {
+                Op::Add => lhs.checked_add(rhs),
+                Op::Sub => lhs.checked_sub(rhs),
+                Op::Mul => lhs.checked_mul(rhs),
+                Op::Div if rhs == 0 => {
+                    state.report(Diagnostic::error(
+                        Code::NumberOutOfRange,
+                        expr.span,
+                        "this expression divides by zero",
+                    ));
+                    return None;
+                }
+                Op::Div => lhs.checked_div(rhs),
+            };
+            if value.is_none() {
+                overflow();
+            }
+            value
+        }
+    }
+}
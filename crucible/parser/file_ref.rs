@@ -0,0 +1,99 @@
+//! `@"path"` file references.
+//!
+//! A file reference can be used anywhere a value is expected,
+//! and is replaced by the contents of the file it names. Files
+//! ending in `.json` are parsed as JSON, and any other file is
+//! inlined as a string. Paths are resolved relative to the
+//! directory of the file containing the reference.
+
+use std::path::{Path, PathBuf};
+
+use mothlib::lantern::json;
+use nom::{
+    character::complete::char,
+    combinator::cut,
+    error::{VerboseError, VerboseErrorKind},
+    sequence::preceded,
+};
+
+use super::*;
+use crate::diagnostic::SourceFile;
+
+pub fn parse(input: Input) -> IResult<json::Value> {
+    let (remain, Spanned { item: path, span }) =
+        spanned(preceded(char('@'), cut(string)))(input)?;
+
+    Ok((remain, load(input.extra, Path::new(&path), span)))
+}
+
+/// Reads the file at `path` and converts it into a value.
+/// Problems are reported as diagnostics at `span`, in which
+/// case the reference is replaced by `null`.
+fn load(state: &ParseState, path: &Path, span: Span) -> json::Value {
+    let resolved: PathBuf = match state.path.parent() {
+        Some(dir) => dir.join(path),
+        None => path.to_owned(),
+    };
+    state.track_input(resolved.clone());
+
+    let text = match std::fs::read_to_string(&resolved) {
+        Ok(text) => text,
+        Err(e) => {
+            state.report(Diagnostic::error(
+                Code::UnreadableFile,
+                span,
+                format!("could not read `{}`: {}", resolved.display(), e),
+            ));
+            return json::Value::Null;
+        }
+    };
+
+    let is_json = resolved
+        .extension()
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("json"));
+    if !is_json {
+        // Text files almost always end with a newline, which
+        // would otherwise show up at the end of the string.
+        let text = text
+            .strip_suffix('\n')
+            .map(|t| t.strip_suffix('\r').unwrap_or(t))
+            .unwrap_or(&text);
+        return json::Value::Str(text.to_owned());
+    }
+
+    match json::parse::<VerboseError<&str>>(&text) {
+        Ok((rest, value)) if rest.is_empty() => value,
+        Ok((rest, _)) => {
+            invalid_json(state, &resolved, &text, rest, "the end of the file", span);
+            json::Value::Null
+        }
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+            let expected = e
+                .errors
+                .iter()
+                .find_map(|(_, kind)| match kind {
+                    VerboseErrorKind::Context(ctx) => Some(*ctx),
+                    _ => None,
+                })
+                .unwrap_or("a value");
+            invalid_json(state, &resolved, &text, e.errors[0].0, expected, span);
+            json::Value::Null
+        }
+        Err(nom::Err::Incomplete(_)) => unreachable!("JSON is parsed with complete parsers"),
+    }
+}
+
+/// Reports that the JSON file at `path` stopped parsing at `rest`.
+/// The file is not part of the [SourceMap](crate::diagnostic::SourceMap),
+/// so the location is given in the message instead.
+fn invalid_json(state: &ParseState, path: &Path, text: &str, rest: &str, expected: &str, span: Span) {
+    let (line, col) = SourceFile::new(path.to_owned(), text.to_owned()).line_col(text.offset(rest));
+    state.report(
+        Diagnostic::error(
+            Code::InvalidJsonFile,
+            span,
+            format!("`{}` is not valid JSON", path.display()),
+        )
+        .with_note(format!("expected {} at {}:{}:{}", expected, path.display(), line, col)),
+    );
+}
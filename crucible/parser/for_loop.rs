@@ -0,0 +1,157 @@
+//! `for` loops, which produce the units in their body once for
+//! each value in a list or a range:
+//!
+//! ```text
+//! for p in [lantern, forge, edge, winter] {
+//!     card {p}.fragment "{p} Fragment" "" ({p}:2) {}
+//! }
+//!
+//! for tier in 1..=3 {
+//!     card fragment.{tier} "Fragment" "" (lantern:tier * 2) {}
+//! }
+//! ```
+//!
+//! A list may hold ids, strings and numbers. A bare name in a list
+//! is an id, so the value of a constant has to be written as an
+//! expression, such as `(TIER)`. A range `a..b` counts up from `a`
+//! to just below `b`, and `a..=b` counts up to `b` itself.
+//!
+//! As with template parameters, every `{name}` in ids, references
+//! and strings is replaced by the loop variable's value, and a
+//! number can also be used by name in expressions. Loops may be
+//! nested, and may be used inside template bodies.
+//!
+//! Each iteration parses the body afresh, so any constants and
+//! templates declared in the body only exist within that iteration.
+
+use super::template::stand_in;
+use super::*;
+
+/// The most values a range may have, so that a mistyped
+/// bound can't leave the compiler producing units forever.
+const MAX_RANGE: i64 = 10_000;
+
+impl ParseState {
+    /// A state for parsing an iteration of a loop in the text being
+    /// parsed, with `variable` bound to `value`. Whatever the
+    /// iteration declares is not seen by this state.
+    fn iteration(&self, variable: &str, value: Binding) -> ParseState {
+        let mut bindings = self.bindings.borrow().clone();
+        bindings.push(HashMap::from([(variable.to_owned(), value)]));
+        ParseState {
+            file: self.file,
+            inputs: RefCell::new(Vec::new()),
+            path: self.path.clone(),
+            text: self.text.clone(),
+            diagnostics: RefCell::new(Vec::new()),
+            definitions: self.definitions.clone(),
+            namespace: self.namespace.clone(),
+            bindings: RefCell::new(bindings),
+            expanding: self.expanding.clone(),
+            dropped: RefCell::new(Vec::new()),
+        }
+    }
+}
+
+/// Parses a `for name in values { units }` loop.
+pub fn parse(input: Input) -> IResult<Unit> {
+    let state = input.extra;
+    // A verb may be called `for`, so this isn't a loop until the `in`.
+    let (rest, (_, variable, _)) = tuple((
+        ws(keyword("for")),
+        ws(spanned(expr::name)),
+        ws(keyword("in")),
+    ))(input)?;
+    let (rest, (values, _)) = cut(pair(
+        context("a list or range", ws(alt((list, range)))),
+        context("`{`", preceded(space, char('{'))),
+    ))(rest)?;
+    let body = rest.location_offset();
+
+    // The body is checked once on its own, so that it is checked
+    // even if there are no values, and its syntax errors are only
+    // reported once.
+    let first = match values.first() {
+        Some((_, binding)) => binding.clone(),
+        None => Binding::Id(variable.item.clone()),
+    };
+    let (remain, _) = stand_in(rest, state.iteration(&variable.item, first))?;
+
+    let iterations = values
+        .into_iter()
+        .map(|(value, binding)| {
+            let child = state.iteration(&variable.item, binding);
+            let units = state.expand(child, body, |d| d.in_iteration(&variable.item, &value.item, value.span));
+            Iteration { value, units }
+        })
+        .collect();
+
+    let (_, text) = input.take_split(remain.location_offset() - input.location_offset());
+    Ok((
+        remain,
+        Unit::For {
+            variable: Spanned::new(DefKey(variable.item), variable.span),
+            iterations,
+            span: span_of(&text),
+        },
+    ))
+}
+
+/// Parses a `[a, b, c]` list of values.
+fn list(input: Input) -> IResult<Vec<(Spanned<String>, Binding)>> {
+    // A number is also a valid id, so ids that are only
+    // made of digits are left for `expr::integer` to parse.
+    fn id(input: Input) -> IResult<Binding> {
+        let number = |key: &DefKey| key.0.trim_start_matches('-').bytes().all(|b| b.is_ascii_digit());
+        map(
+            terminated(verify(defkey, move |key| !number(key)), peek(ws(one_of(",]")))),
+            |key| Binding::Id(key.0),
+        )(input)
+    }
+    fn value(input: Input) -> IResult<(Spanned<String>, Binding)> {
+        let (remain, binding) = ws(spanned(context(
+            "an id, a string or a number",
+            alt((
+                map(string, Binding::Str),
+                id,
+                map(expr::integer, Binding::Number),
+            )),
+        )))(input)?;
+        let text = Spanned::new(binding.item.text(), binding.span);
+        Ok((remain, (text, binding.item)))
+    }
+    delimited(
+        char('['),
+        separated_list0(ws(char(',')), value),
+        context("`,` or `]`", cut(list_end(']'))),
+    )(input)
+}
+
+/// Parses an `a..b` or `a..=b` range of numbers.
+fn range(input: Input) -> IResult<Vec<(Spanned<String>, Binding)>> {
+    let state = input.extra;
+    let (remain, range) = spanned(tuple((
+        expr::integer,
+        ws(alt((value(true, tag("..=")), value(false, tag(".."))))),
+        cut(context("a number", expr::integer)),
+    )))(input)?;
+    let (start, inclusive, end) = range.item;
+    let end = if inclusive { end.saturating_add(1) } else { end };
+
+    let count = end.saturating_sub(start).max(0);
+    if count > MAX_RANGE {
+        state.report(
+            Diagnostic::error(
+                Code::NumberOutOfRange,
+                range.span,
+                format!("this range has {} values, which is too many to loop over", count),
+            )
+            .with_note(format!("a range may have at most {} values", MAX_RANGE)),
+        );
+        return Ok((remain, Vec::new()));
+    }
+    let values = (start..end)
+        .map(|n| (Spanned::new(n.to_string(), range.span), Binding::Number(n)))
+        .collect();
+    Ok((remain, values))
+}
@@ -0,0 +1,140 @@
+//! `import "path.crucible";` directives.
+//!
+//! Imports are listed at the top of a file, after any global
+//! attributes. Each one names another Crucible file, relative
+//! to the importing file, whose units become part of the build.
+//! Every file is loaded at most once, no matter how many files
+//! import it, and import cycles are reported as errors.
+//!
+//! A file's imports are parsed before the rest of the file, so
+//! that the constants and templates they declare can be used in it.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use nom::{
+    character::complete::char,
+    combinator::cut,
+    error::context,
+    sequence::{pair, terminated},
+};
+
+use super::*;
+
+pub fn parse(input: Input) -> IResult<Spanned<String>> {
+    let (remain, (_, path)) = pair(
+        ws(keyword("import")),
+        cut(terminated(ws(spanned(string)), context("`;`", ws(char(';'))))),
+    )(input)?;
+    Ok((remain, path))
+}
+
+/// Loads files and everything they import, depth first, so that
+/// a file's imports are merged before the file itself.
+pub struct Loader<'s> {
+    sources: &'s mut SourceMap,
+    diagnostics: &'s mut Diagnostics,
+    /// Canonical paths of every file loaded so far.
+    loaded: HashSet<PathBuf>,
+    /// Canonical paths of the files currently being loaded,
+    /// each one imported by the file before it.
+    stack: Vec<PathBuf>,
+    /// Text to use in place of the contents of a file on disk,
+    /// keyed by canonical path.
+    overlay: &'s HashMap<PathBuf, String>,
+    /// The constants and templates declared by every file parsed so far.
+    definitions: Definitions,
+    output: Crucible,
+}
+
+impl<'s> Loader<'s> {
+    pub fn new(
+        sources: &'s mut SourceMap,
+        diagnostics: &'s mut Diagnostics,
+        overlay: &'s HashMap<PathBuf, String>,
+    ) -> Self {
+        Loader {
+            sources,
+            diagnostics,
+            loaded: HashSet::new(),
+            stack: Vec::new(),
+            overlay,
+            definitions: Definitions::default(),
+            output: Crucible::empty(),
+        }
+    }
+
+    /// Loads a file named directly by the user. Unlike an
+    /// imported file, failing to read it is a hard error.
+    pub fn load_root(&mut self, path: &Path) -> Result<()> {
+        // A file that only exists in the overlay, such as a new
+        // file which has not been saved yet, can't be canonicalized.
+        let canonical = match std::fs::canonicalize(path) {
+            Ok(canonical) => canonical,
+            Err(_) if self.overlay.contains_key(path) => path.to_owned(),
+            Err(e) => return Err(e.into()),
+        };
+        self.load(path, canonical)
+    }
+
+    pub fn finish(self) -> Crucible {
+        self.output
+    }
+
+    fn load_import(&mut self, importer: &Path, import: Spanned<String>) -> Result<()> {
+        let path = match importer.parent() {
+            Some(dir) => dir.join(&import.item),
+            None => PathBuf::from(&import.item),
+        };
+        let canonical = match std::fs::canonicalize(&path) {
+            Ok(canonical) => canonical,
+            Err(e) => {
+                self.diagnostics.push(Diagnostic::error(
+                    Code::UnreadableFile,
+                    import.span,
+                    format!("could not import `{}`: {}", path.display(), e),
+                ));
+                return Ok(());
+            }
+        };
+        if let Some(start) = self.stack.iter().position(|p| *p == canonical) {
+            let cycle: Vec<String> = self.stack[start..]
+                .iter()
+                .chain(std::iter::once(&canonical))
+                .map(|p| p.display().to_string())
+                .collect();
+            self.diagnostics.push(
+                Diagnostic::error(
+                    Code::ImportCycle,
+                    import.span,
+                    format!("importing `{}` creates a cycle", path.display()),
+                )
+                .with_note(format!("the import cycle is {}", cycle.join(" -> "))),
+            );
+            return Ok(());
+        }
+        self.load(&path, canonical)
+    }
+
+    fn load(&mut self, path: &Path, canonical: PathBuf) -> Result<()> {
+        if !self.loaded.insert(canonical.clone()) {
+            return Ok(());
+        }
+        let text = match self.overlay.get(&canonical) {
+            Some(text) => text.clone(),
+            None => std::fs::read_to_string(path)?,
+        };
+        let id = self.sources.add(path.to_owned(), text);
+
+        self.stack.push(canonical);
+        for import in Crucible::imports(id, self.sources.get(id)) {
+            self.load_import(path, import)?;
+        }
+        self.stack.pop();
+
+        let crucible =
+            Crucible::from_source(id, self.sources.get(id), &mut self.definitions, self.diagnostics);
+        self.output.merge(crucible)
+    }
+}
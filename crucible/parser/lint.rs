@@ -0,0 +1,69 @@
+//! `#[allow(...)]`, `#[warn(...)]` and `#[deny(...)]` attributes,
+//! which set the level of lints for the namespace or component
+//! they are on, or for the whole mod when written with `#!`:
+//!
+//! ```text
+//! #![deny(undrawn_deck)]
+//!
+//! #[allow(missing_art, unused_aspect)]
+//! namespace placeholders { ... }
+//! ```
+//!
+//! The names aren't checked here, as the lints are known
+//! to [crate::lint], which runs after the mod is resolved.
+
+use super::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LintLevel {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl LintLevel {
+    /// The attribute, and command line option, that sets this level.
+    pub fn name(self) -> &'static str {
+        match self {
+            LintLevel::Allow => "allow",
+            LintLevel::Warn => "warn",
+            LintLevel::Deny => "deny",
+        }
+    }
+}
+
+/// The lints named by one attribute, and the level it sets them to.
+#[derive(Debug, Clone)]
+pub struct LintAttr {
+    pub level: LintLevel,
+    pub lints: Vec<Spanned<String>>,
+}
+
+/// Parses a `#[level(lint, ...)]` attribute.
+pub fn attribute(input: Input) -> IResult<LintAttr> {
+    preceded(char('#'), body)(input)
+}
+
+/// Parses a `#![level(lint, ...)]` attribute.
+pub fn global_attribute(input: Input) -> IResult<LintAttr> {
+    preceded(tag("#!"), body)(input)
+}
+
+fn body(input: Input) -> IResult<LintAttr> {
+    let (rest, (_, level)) = pair(
+        ws(char('[')),
+        ws(alt((
+            value(LintLevel::Allow, keyword("allow")),
+            value(LintLevel::Warn, keyword("warn")),
+            value(LintLevel::Deny, keyword("deny")),
+        ))),
+    )(input)?;
+    // `allow` and the others may also be plain attribute keys,
+    // so this is only a lint attribute once the `(` is found.
+    let (rest, _) = ws(char('('))(rest)?;
+    let (rest, lints) = cut(terminated(
+        separated_list1(ws(char(',')), ws(spanned(context("a lint name", expr::name)))),
+        pair(context("`,` or `)`", list_end(')')), context("`]`", char(']'))),
+    ))(rest)?;
+    Ok((rest, LintAttr { level, lints }))
+}
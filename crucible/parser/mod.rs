@@ -1,23 +1,66 @@
-use anyhow::{bail, Result};
+use anyhow::Result;
+use either::Either;
 use mothlib::lantern::Attribute;
 use mothlib::lantern::*;
+use nom_locate::LocatedSpan;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::path::Path;
 use std::path::PathBuf;
-use tracing::{event, Level};
+use std::rc::Rc;
 
 use nom::{
     branch::*, bytes::complete::*, character::complete::*, combinator::*, error::*, multi::*,
-    sequence::*, IResult,
+    sequence::*, InputTake, Offset,
 };
 
+use crate::diagnostic::{Code, Diagnostic, Diagnostics, FileId, SourceFile, SourceMap, Span, Spanned};
+
+mod cfg;
+mod error;
+mod expr;
+mod file_ref;
+mod for_loop;
+mod import;
+mod lint;
+mod recover;
+mod requirement;
 mod string;
+mod template;
 
 mod aspect;
 mod card;
 mod deck;
+mod ending;
+mod legacy;
 mod recipe;
 mod verb;
 
+pub use cfg::Predicate;
+pub use error::Error;
+pub use lint::{LintAttr, LintLevel};
+
+use expr::{negated, probability, signed, unsigned, Constants};
+use template::{Binding, Templates};
+
+pub use aspect::AspectDef;
+pub use card::CardDef;
+pub use deck::DeckDef;
+pub use ending::EndingDef;
+pub use legacy::LegacyDef;
+pub use recipe::{BranchDef, ConditionDef, MutationDef, RecipeDef, SpawningDef};
+pub use requirement::{combine, RequirementDef, RequirementKind, Threshold, Written};
+pub use verb::VerbDef;
+
+/// The input type of every Crucible parser. It tracks
+/// the location of the remaining text within its file,
+/// and carries the [ParseState] of the file being parsed.
+pub type Input<'a> = LocatedSpan<&'a str, &'a ParseState>;
+
+/// The result type of every Crucible parser.
+pub type IResult<'a, O> = nom::IResult<Input<'a>, O, Error<'a>>;
+
+#[allow(unused_macros)]
 macro_rules! nomfail {
     ($input:expr) => {
         Err(nom::Err::Failure($input))
@@ -31,68 +74,333 @@ macro_rules! nomerr {
     };
 }
 
-pub fn parse(files: Vec<PathBuf>) -> Result<Crucible> {
-    let mut master = Crucible::empty();
-    let mut errored = false;
-    for file in files {
-        match Crucible::new(file.clone()) {
-            Ok(c) => match master.merge(c) {
-                Ok(_) => (),
-                Err(e) => {
-                    errored = true;
-                    event!(
-                        Level::ERROR,
-                        "Error merging \"{}\": {}",
-                        file.to_str()
-                            .unwrap_or_else(|| panic!("Error: Invalid Path: {:?}", file)),
-                        e
-                    );
+/// State shared by every parser working on the same file.
+///
+/// Semantic problems (such as assigning the same key twice)
+/// do not stop the parse. They are reported here instead,
+/// so that every problem in a file is found in a single pass.
+#[derive(Debug)]
+pub struct ParseState {
+    pub file: FileId,
+    /// The path of the file being parsed, which
+    /// file references are resolved relative to.
+    pub path: PathBuf,
+    /// The text of the file being parsed, kept
+    /// for the templates it declares.
+    text: Rc<str>,
+    diagnostics: RefCell<Vec<Diagnostic>>,
+    inputs: RefCell<Vec<PathBuf>>,
+    /// What has been declared so far, in this file and any before it.
+    definitions: RefCell<Definitions>,
+    /// The full path of the namespace being parsed.
+    namespace: RefCell<Vec<String>>,
+    /// The values of the template parameters and
+    /// loop variables in scope, innermost last.
+    bindings: RefCell<Vec<HashMap<String, Binding>>>,
+    /// The templates being instantiated, outermost first.
+    expanding: Vec<String>,
+    /// The full ids of the units which failed to parse.
+    dropped: RefCell<Vec<String>>,
+}
+
+/// The constants and templates declared by the files parsed so
+/// far, which the files parsed after them may use.
+#[derive(Debug, Clone, Default)]
+pub struct Definitions {
+    constants: Constants,
+    templates: Templates,
+}
+
+impl ParseState {
+    pub fn new(file: FileId, path: PathBuf, text: Rc<str>, definitions: Definitions) -> Self {
+        ParseState {
+            file,
+            inputs: RefCell::new(vec![path.clone()]),
+            path,
+            text,
+            diagnostics: RefCell::new(Vec::new()),
+            definitions: RefCell::new(definitions),
+            namespace: RefCell::new(Vec::new()),
+            bindings: RefCell::new(Vec::new()),
+            expanding: Vec::new(),
+            dropped: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// The value of the template parameter `name`, if one is in scope.
+    fn binding(&self, name: &str) -> Option<Binding> {
+        self.bindings.borrow().iter().rev().find_map(|b| b.get(name).cloned())
+    }
+
+    /// Replaces every `{name}` in `text` which names a template
+    /// parameter in scope with the parameter's value.
+    fn interpolate(&self, text: String) -> String {
+        if self.bindings.borrow().is_empty() || !text.contains('{') {
+            return text;
+        }
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text.as_str();
+        while let Some(open) = rest.find('{') {
+            out.push_str(&rest[..open]);
+            rest = &rest[open..];
+            let binding = rest[1..]
+                .split_once('}')
+                .and_then(|(name, _)| Some((name, self.binding(name)?)));
+            match binding {
+                Some((name, binding)) => {
+                    out.push_str(&binding.text());
+                    rest = &rest[name.len() + 2..];
+                }
+                None => {
+                    out.push('{');
+                    rest = &rest[1..];
                 }
-            },
-            Err(e) => {
-                errored = true;
-                event!(
-                    Level::ERROR,
-                    "Error parsing \"{}\": {}",
-                    file.to_str()
-                        .unwrap_or_else(|| panic!("Error: Invalid Path: {:?}", file)),
-                    e
-                );
             }
-        };
+        }
+        out.push_str(rest);
+        out
     }
-    if errored {
-        bail!("Encountered errors during Parsing")
+
+    /// Records that the output depends on the file at `path`.
+    pub fn track_input(&self, path: PathBuf) {
+        let mut inputs = self.inputs.borrow_mut();
+        if !inputs.contains(&path) {
+            inputs.push(path);
+        }
+    }
+
+    /// Records a diagnostic. The same problem can be found more
+    /// than once when parsers backtrack, so exact repeats are dropped.
+    /// Problems in different instantiations of a template, or different
+    /// iterations of a loop, are told apart by their labels.
+    pub fn report(&self, diagnostic: Diagnostic) {
+        let mut diagnostics = self.diagnostics.borrow_mut();
+        let repeated = diagnostics.iter().any(|d| d.repeats(&diagnostic));
+        if !repeated {
+            diagnostics.push(diagnostic);
+        }
+    }
+
+    /// Looks up the value of the constant `name`, searching from the
+    /// namespace being parsed outwards, as references are resolved.
+    pub fn constant(&self, name: &str) -> Option<i64> {
+        if let Some(Binding::Number(n)) = self.binding(name) {
+            return Some(n);
+        }
+        let definitions = self.definitions.borrow();
+        let constants = &definitions.constants;
+        if let Some(root) = name.strip_prefix('.') {
+            return constants.get(root).map(|c| c.item);
+        }
+        let namespace = self.namespace.borrow();
+        (0..=namespace.len()).rev().find_map(|depth| {
+            let path = namespace[..depth]
+                .iter()
+                .map(String::as_str)
+                .chain(std::iter::once(name))
+                .collect::<Vec<_>>()
+                .join(".");
+            constants.get(&path).map(|c| c.item)
+        })
+    }
+
+    /// Declares the constant `name` in the namespace being parsed.
+    pub fn declare_constant(&self, name: &Spanned<String>, value: i64) {
+        let path = self.declared_path(&name.item);
+        let constants = &mut self.definitions.borrow_mut().constants;
+        match constants.get(&path) {
+            Some(first) => self.report(
+                Diagnostic::error(
+                    Code::DuplicateDefinition,
+                    name.span,
+                    format!("constant `{}` is declared more than once", path),
+                )
+                .with_label(first.span, "first declared here"),
+            ),
+            None => {
+                constants.insert(path, Spanned::new(value, name.span));
+            }
+        }
+    }
+
+    /// Records that the unit declaring `id` in the namespace
+    /// being parsed was dropped because it failed to parse.
+    fn drop_unit(&self, id: &str) {
+        let path = self.declared_path(&self.interpolate(id.to_owned()));
+        self.dropped.borrow_mut().push(path);
+    }
+
+    /// The full path of something named `name`
+    /// declared in the namespace being parsed.
+    fn declared_path(&self, name: &str) -> String {
+        let mut path = self.namespace.borrow().clone();
+        path.push(name.to_owned());
+        path.join(".")
+    }
+
+    /// Consumes the state, returning the diagnostics reported, the
+    /// files read while parsing, everything declared so far and the
+    /// ids of the units dropped because they failed to parse.
+    pub fn finish(self) -> (Vec<Diagnostic>, Vec<PathBuf>, Definitions, Vec<String>) {
+        (
+            self.diagnostics.into_inner(),
+            self.inputs.into_inner(),
+            self.definitions.into_inner(),
+            self.dropped.into_inner(),
+        )
     }
-    Ok(master)
+}
+
+/// Parses every file in `files`, along with every file they
+/// import, and merges the results.
+///
+/// Problems in the source are added to `diagnostics` rather than
+/// returned, so that a single run reports all of them. An `Err`
+/// is only returned if a file could not be read at all.
+pub fn parse(
+    files: &[PathBuf],
+    sources: &mut SourceMap,
+    diagnostics: &mut Diagnostics,
+) -> Result<Crucible> {
+    parse_with(files, &HashMap::new(), sources, diagnostics)
+}
+
+/// Like [parse], but any file whose canonical path is a key of
+/// `overlay` is read from there instead of from the disk, such
+/// as a file with unsaved changes in an editor.
+pub fn parse_with(
+    files: &[PathBuf],
+    overlay: &HashMap<PathBuf, String>,
+    sources: &mut SourceMap,
+    diagnostics: &mut Diagnostics,
+) -> Result<Crucible> {
+    let mut loader = import::Loader::new(sources, diagnostics, overlay);
+    for file in files {
+        loader.load_root(file)?;
+    }
+    Ok(loader.finish())
 }
 
 #[derive(Debug)]
 pub struct Crucible {
     attributes: Vec<Attribute>,
+    /// The `#![allow]`, `#![warn]` and `#![deny]` attributes.
+    lints: Vec<LintAttr>,
     units: Vec<Unit>,
+    /// Every file the parsed source was read from, including
+    /// the targets of file references, in the order they were read.
+    inputs: Vec<PathBuf>,
+    /// The full ids of the units which failed to parse, and so
+    /// were left out, though the source declares them.
+    dropped: Vec<String>,
 }
 
 impl Crucible {
-    pub fn new(file: impl AsRef<Path>) -> Result<Self> {
-        let raw_data = std::fs::read_to_string(file)?;
-        let pdata = crucible(raw_data)?;
+    pub fn new(
+        file: impl AsRef<Path>,
+        sources: &mut SourceMap,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<Self> {
+        let raw_data = std::fs::read_to_string(file.as_ref())?;
+        let id = sources.add(file.as_ref().to_owned(), raw_data);
+        Ok(Self::from_source(id, sources.get(id), &mut Definitions::default(), diagnostics))
+    }
 
-        println!("{:#?}", pdata);
+    /// Parses a file that has already been registered in the
+    /// [SourceMap] as `file`. `definitions` holds what the file
+    /// can use, and gains whatever it declares.
+    pub fn from_source(
+        file: FileId,
+        source: &SourceFile,
+        definitions: &mut Definitions,
+        diagnostics: &mut Diagnostics,
+    ) -> Self {
+        let state = ParseState::new(
+            file,
+            source.path().to_owned(),
+            Rc::from(source.text()),
+            std::mem::take(definitions),
+        );
+        let parsed = match crucible(Input::new_extra(source.text(), &state)) {
+            Ok((_, c)) => Some(c),
+            Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+                state.report(e.to_diagnostic());
+                None
+            }
+            Err(nom::Err::Incomplete(_)) => unreachable!("Crucible only uses complete parsers"),
+        };
+        let (reported, inputs, declared, dropped) = state.finish();
+        *definitions = declared;
+        diagnostics.extend(reported);
+        Crucible {
+            inputs,
+            dropped,
+            ..parsed.unwrap_or_else(Crucible::empty)
+        }
+    }
 
-        todo!()
+    /// Finds the files named by `import` directives at the top of
+    /// `source`, without parsing the rest of it. Problems are left
+    /// for [Crucible::from_source] to report.
+    pub fn imports(file: FileId, source: &SourceFile) -> Vec<Spanned<String>> {
+        let state = ParseState::new(file, source.path().to_owned(), Rc::from(""), Definitions::default());
+        let mut header = preceded(global_attrs, many0(ws(import::parse)));
+        match header(Input::new_extra(source.text(), &state)) {
+            Ok((_, imports)) => imports,
+            Err(_) => Vec::new(),
+        }
     }
 
     pub fn empty() -> Self {
         Crucible {
             attributes: Vec::new(),
+            lints: Vec::new(),
             units: Vec::new(),
+            inputs: Vec::new(),
+            dropped: Vec::new(),
         }
     }
 
+    /// The files this Crucible was built from.
+    pub fn inputs(&self) -> &[PathBuf] {
+        &self.inputs
+    }
+
+    /// The full ids of the units left out because they failed to parse.
+    pub fn dropped(&self) -> &[String] {
+        &self.dropped
+    }
+
+    /// The `#![...]` attributes which apply to the whole mod.
+    pub fn attributes(&self) -> &[Attribute] {
+        &self.attributes
+    }
+
+    /// The `#![...]` lint attributes, which apply to the whole mod.
+    pub fn lints(&self) -> &[LintAttr] {
+        &self.lints
+    }
+
+    pub fn units(&self) -> &[Unit] {
+        &self.units
+    }
+
+    pub fn units_mut(&mut self) -> &mut Vec<Unit> {
+        &mut self.units
+    }
+
     // Takes another Crucible instance and merges it into this one.
-    pub fn merge(&mut self, _other: Crucible) -> Result<()> {
-        unimplemented!()
+    pub fn merge(&mut self, other: Crucible) -> Result<()> {
+        self.attributes.extend(other.attributes);
+        self.lints.extend(other.lints);
+        self.units.extend(other.units);
+        self.dropped.extend(other.dropped);
+        for input in other.inputs {
+            if !self.inputs.contains(&input) {
+                self.inputs.push(input);
+            }
+        }
+        Ok(())
     }
 
     // A version of `Crucible::merge(..)` that consumes self and another,
@@ -103,41 +411,88 @@ impl Crucible {
     }
 }
 
-fn crucible(input: String) -> IResult<String, Crucible> {
-    let c = context(
+fn crucible(input: Input) -> IResult<Crucible> {
+    // Imports are found and loaded by [Crucible::imports]
+    // before the file is parsed, so here they are skipped.
+    let (remainder, ((attributes, lints), _, units)) = context(
         "Crucible",
-        separated_pair(
-            separated_list0(multispace0, global_attr),
-            multispace0,
-            separated_list0(multispace0, unit),
-        ),
-    )(&input);
-
-    match c {
-        Ok((remainder, (attributes, units))) => {
-            if remainder.is_empty() {
-                Ok((remainder.to_owned(), Crucible { attributes, units }))
-            } else {
-                nomfail!(Error::new(input, ErrorKind::NonEmpty))
+        tuple((
+            global_attrs,
+            many0(ws(import::parse)),
+            |i| units(i, false),
+        )),
+    )(input)?;
+
+    Ok((
+        remainder,
+        Crucible {
+            attributes,
+            lints,
+            units,
+            inputs: Vec::new(),
+            dropped: Vec::new(),
+        },
+    ))
+}
+
+fn global_attr(input: Input) -> IResult<Attribute> {
+    preceded(tag("#!"), delimited(char('['), attr, cut(char(']'))))(input)
+}
+
+/// Parses the attributes at the top of a file,
+/// keeping lint attributes apart from the rest.
+fn global_attrs(input: Input) -> IResult<(Vec<Attribute>, Vec<LintAttr>)> {
+    fold_many0(
+        ws(alt((map(lint::global_attribute, Either::Right), map(global_attr, Either::Left)))),
+        || (Vec::new(), Vec::new()),
+        |(mut attrs, mut lints), attr| {
+            match attr {
+                Either::Left(attr) => attrs.push(attr),
+                Either::Right(lint) => lints.push(lint),
             }
-        }
-        Err(e) => Err(e.map(|e| Error::new(e.input.to_owned(), e.code))),
-    }
+            (attrs, lints)
+        },
+    )(input)
 }
 
-fn global_attr(input: &str) -> IResult<&str, Attribute> {
-    match preceded(tag("#!"), delimited(char('['), attr, char(']')))(input) {
-        Ok((r, a)) => Ok((r, a)),
-        Err(e) => Err(e),
-    }
+fn local_attr(input: Input) -> IResult<Attribute> {
+    preceded(char('#'), delimited(char('['), attr, cut(char(']'))))(input)
 }
 
-fn local_attr(input: &str) -> IResult<&str, Attribute> {
-    preceded(char('#'), delimited(char('['), attr, char(']')))(input)
+/// An attribute on a namespace or component.
+enum LocalAttr {
+    Plain(Attribute),
+    Cfg(Spanned<Predicate>),
+    Lint(LintAttr),
 }
 
-fn attr(input: &str) -> IResult<&str, Attribute> {
-    fn only_defkey(input: &str) -> IResult<&str, Attribute> {
+/// The plain attributes, `#[cfg]` predicates and
+/// lint attributes on a namespace or component.
+type LocalAttrs = (Vec<Attribute>, Vec<Spanned<Predicate>>, Vec<LintAttr>);
+
+/// Parses the attributes on a namespace or component, keeping
+/// `#[cfg]` predicates and lint attributes apart from the rest.
+fn local_attrs(input: Input) -> IResult<LocalAttrs> {
+    fold_many0(
+        ws(alt((
+            map(cfg::attribute, LocalAttr::Cfg),
+            map(lint::attribute, LocalAttr::Lint),
+            map(local_attr, LocalAttr::Plain),
+        ))),
+        || (Vec::new(), Vec::new(), Vec::new()),
+        |(mut attrs, mut cfg, mut lints), attr| {
+            match attr {
+                LocalAttr::Plain(attr) => attrs.push(attr),
+                LocalAttr::Cfg(predicate) => cfg.push(predicate),
+                LocalAttr::Lint(lint) => lints.push(lint),
+            }
+            (attrs, cfg, lints)
+        },
+    )(input)
+}
+
+fn attr(input: Input) -> IResult<Attribute> {
+    fn only_defkey(input: Input) -> IResult<Attribute> {
         let (s, k) = ws(defkey)(input)?;
         Ok((
             s,
@@ -147,8 +502,8 @@ fn attr(input: &str) -> IResult<&str, Attribute> {
             },
         ))
     }
-    fn defkey_value(input: &str) -> IResult<&str, Attribute> {
-        let (s, (k, v)) = separated_pair(ws(defkey), char('='), ws(value))(input)?;
+    fn defkey_value(input: Input) -> IResult<Attribute> {
+        let (s, (k, v)) = separated_pair(ws(defkey), char('='), ws(json_value))(input)?;
         Ok((
             s,
             Attribute {
@@ -157,79 +512,449 @@ fn attr(input: &str) -> IResult<&str, Attribute> {
             },
         ))
     }
-    alt((defkey_value, only_defkey))(input)
+    context("an attribute", alt((defkey_value, only_defkey)))(input)
 }
 
-fn defkey(input: &str) -> IResult<&str, DefKey> {
-    let (r, chrs) = take_while1(|b| {
-        matches!(b,
-            'a'..='z'
-          | 'A'..='Z'
-          | '0'..='9'
-          | '_'
-          | '-'
-          | '$'
-          | '.'
-        )
-    })(input)?;
-    Ok((r, DefKey(chrs.to_owned())))
+pub fn is_defkey_char(c: char) -> bool {
+    matches!(c,
+        'a'..='z'
+      | 'A'..='Z'
+      | '0'..='9'
+      | '_'
+      | '-'
+      | '$'
+      | '.'
+    )
+}
+
+fn defkey(input: Input) -> IResult<DefKey> {
+    let state = input.extra;
+    if state.bindings.borrow().is_empty() {
+        let (r, chrs) = context("an id", take_while1(is_defkey_char))(input)?;
+        return Ok((r, DefKey(chrs.fragment().to_string())));
+    }
+
+    // Inside a template, `{name}` stands for the value of a parameter.
+    let interpolation = map(
+        spanned(delimited(char('{'), expr::name, char('}'))),
+        |name| match state.binding(&name) {
+            Some(binding) => {
+                let text = binding.text();
+                if !text.chars().all(is_defkey_char) {
+                    state.report(
+                        Diagnostic::error(
+                            Code::MismatchedType,
+                            name.span,
+                            format!("`{}` can't be used in an id", text),
+                        )
+                        .with_note("ids may only contain letters, digits, `_`, `-`, `$` and `.`"),
+                    );
+                }
+                text
+            }
+            None => {
+                state.report(Diagnostic::error(
+                    Code::UnresolvedConstant,
+                    name.span,
+                    format!("cannot find parameter `{}` in this scope", name.item),
+                ));
+                String::new()
+            }
+        },
+    );
+    let (r, parts) = context(
+        "an id",
+        many1(alt((
+            map(take_while1(is_defkey_char), |chrs: Input| chrs.fragment().to_string()),
+            interpolation,
+        ))),
+    )(input)?;
+    Ok((r, DefKey(parts.concat())))
+}
+
+/// Recognizes a case-insensitive keyword, making sure it
+/// is not just the start of a longer identifier.
+fn keyword<'a>(kw: &'static str) -> impl FnMut(Input<'a>) -> IResult<'a, Input<'a>> {
+    terminated(tag_no_case(kw), not(satisfy(is_defkey_char)))
 }
 
-fn value(input: &str) -> IResult<&str, json::Value> {
-    json::parse(input)
+/// Wraps the output of `inner` with the span of text it consumed.
+fn spanned<'a, O, F>(inner: F) -> impl FnMut(Input<'a>) -> IResult<'a, Spanned<O>>
+where
+    F: FnMut(Input<'a>) -> IResult<'a, O>,
+{
+    map(consumed(inner), |(text, item): (Input, O)| {
+        Spanned::new(item, span_of(&text))
+    })
+}
+
+/// The span covered by a fragment of input.
+fn span_of(text: &Input) -> Span {
+    let start = text.location_offset();
+    Span::new(text.extra.file, start, start + text.fragment().len())
+}
+
+/// Runs a parser written for plain `&str` input (such as
+/// the string and JSON parsers) on an [Input]. If it fails,
+/// the innermost `context(..)` it recorded becomes what the
+/// resulting [Error] says was expected.
+fn plain<'a, O, F>(mut inner: F) -> impl FnMut(Input<'a>) -> IResult<'a, O>
+where
+    F: FnMut(&'a str) -> nom::IResult<&'a str, O, VerboseError<&'a str>>,
+{
+    move |input: Input<'a>| {
+        let text: &'a str = input.fragment();
+        match inner(text) {
+            Ok((rest, o)) => {
+                let (remain, _) = input.take_split(text.offset(rest));
+                Ok((remain, o))
+            }
+            // Some of these parsers are built from streaming combinators,
+            // which ask for more input when they run out. All of our
+            // input is already here, so that is just a failed match.
+            Err(nom::Err::Incomplete(_)) => Err(nom::Err::Error(Error::from_error_kind(
+                input,
+                ErrorKind::Eof,
+            ))),
+            Err(e) => Err(e.map(|e| {
+                let (at, kind) = e.errors[0].clone();
+                let (at, _) = input.take_split(text.offset(at));
+                let expected = e.errors.iter().find_map(|(_, kind)| match kind {
+                    VerboseErrorKind::Context(ctx) => Some(*ctx),
+                    _ => None,
+                });
+                let kind = match kind {
+                    VerboseErrorKind::Nom(kind) => kind,
+                    _ => ErrorKind::Fail,
+                };
+                Error { input: at, kind, expected }
+            })),
+        }
+    }
+}
+
+fn json_value(input: Input) -> IResult<json::Value> {
+    context("a value", alt((file_ref::parse, plain(json::parse))))(input)
+}
+
+fn string(input: Input) -> IResult<String> {
+    let (remain, text) = context("a string", plain(string::parse))(input)?;
+    Ok((remain, input.extra.interpolate(text)))
+}
+
+/// Parses a `{ ... }` block of statements, each terminated by a `;`.
+fn block<'a, O: 'a, F: 'a>(statement: F) -> impl FnMut(Input<'a>) -> IResult<'a, Vec<Spanned<O>>>
+where
+    F: FnMut(Input<'a>) -> IResult<'a, O>,
+{
+    delimited(
+        ws(char('{')),
+        many0(ws(terminated(
+            spanned(statement),
+            context("`;`", cut(ws(char(';')))),
+        ))),
+        context("a statement or `}`", cut(ws(char('}')))),
+    )
+}
+
+/// Parses the `close` that ends a comma-separated list. A `,` just
+/// before it is reported, and skipped so that parsing carries on.
+fn list_end<'a>(close: char) -> impl FnMut(Input<'a>) -> IResult<'a, char> {
+    move |input: Input<'a>| {
+        if let Ok((rest, (comma, _))) = pair(ws(spanned(char(','))), ws(char(close)))(input) {
+            input.extra.report(
+                Diagnostic::error(Code::Syntax, comma.span, "trailing `,` is not allowed")
+                    .with_note("remove the `,`"),
+            );
+            return Ok((rest, close));
+        }
+        ws(char(close))(input)
+    }
 }
 
 #[derive(Debug)]
 pub enum Unit {
     Namespace {
-        id: DefKey,
+        id: Spanned<DefKey>,
+        /// The text of any `///` doc comments before the namespace.
+        doc: Option<String>,
         attrs: Vec<Attribute>,
+        /// The `#[cfg]` predicates which must all hold for
+        /// the namespace to be compiled.
+        cfg: Vec<Spanned<Predicate>>,
+        /// The lint attributes, which apply to everything inside.
+        lints: Vec<LintAttr>,
         units: Vec<Unit>,
+        span: Span,
     },
     Component {
-        id: DefKey,
+        id: Spanned<DefKey>,
+        /// The text of any `///` doc comments before the component,
+        /// which becomes its `comment` in the output.
+        doc: Option<String>,
         attrs: Vec<Attribute>,
+        /// The `#[cfg]` predicates which must all hold for
+        /// the component to be compiled.
+        cfg: Vec<Spanned<Predicate>>,
+        lints: Vec<LintAttr>,
         component: Component,
-        inherits: Option<DefKey>,
+        inherits: Option<Spanned<DefKey>>,
+        span: Span,
+    },
+    /// A `use path as alias;` directive, which lets references in the
+    /// rest of the enclosing namespace (or file) write `alias` in place
+    /// of `path`.
+    Use {
+        path: Spanned<DefKey>,
+        alias: Spanned<DefKey>,
+        span: Span,
+    },
+    /// A `const NAME = value;` declaration. Constants are replaced by
+    /// their values as the file is parsed, so later passes skip these.
+    Const {
+        id: Spanned<DefKey>,
+        value: i64,
+        span: Span,
+    },
+    /// A `template` declaration. Each instantiation parses the body
+    /// afresh, so later passes skip these. `units` is the body as
+    /// parsed with stand-in arguments, for tools such as `fmt`.
+    Template {
+        id: Spanned<DefKey>,
+        units: Vec<Unit>,
+        span: Span,
+    },
+    /// The units produced by instantiating a template, which
+    /// belong to the namespace the instantiation is in.
+    Expansion {
+        template: Spanned<DefKey>,
+        units: Vec<Unit>,
+        span: Span,
+    },
+    /// A `for` loop, with the units produced by each of its
+    /// iterations, which belong to the namespace the loop is in.
+    For {
+        variable: Spanned<DefKey>,
+        iterations: Vec<Iteration>,
+        span: Span,
     },
 }
 
-fn unit(input: &str) -> IResult<&str, Unit> {
-    alt((namespace, component))(input)
+/// One iteration of a `for` loop.
+#[derive(Debug)]
+pub struct Iteration {
+    /// The value of the loop's variable, and where it was given.
+    pub value: Spanned<String>,
+    pub units: Vec<Unit>,
 }
 
-fn namespace(input: &str) -> IResult<&str, Unit> {
-    let (remain, (attrs, _, ns_id, _, units, _)) = tuple((
-        many0(ws(local_attr)),
-        ws(tag_no_case("namespace")),
-        ws(defkey),
-        ws(char('{')),
-        many0(ws(unit)),
-        ws(char('}')),
+fn unit(input: Input) -> IResult<Unit> {
+    context(
+        "a namespace or component",
+        // A doc comment before a unit that can't have one is a plain comment.
+        alt((
+            namespace,
+            preceded(space, use_decl),
+            preceded(space, expr::parse),
+            template::parse,
+            preceded(space, template::instantiate),
+            preceded(space, for_loop::parse),
+            component,
+        )),
+    )(input)
+}
+
+fn use_decl(input: Input) -> IResult<Unit> {
+    let (remain, (text, (_, (path, alias, _)))) = consumed(pair(
+        ws(keyword("use")),
+        cut(tuple((
+            ws(spanned(defkey)),
+            opt(preceded(ws(keyword("as")), cut(ws(spanned(defkey))))),
+            context("`as` or `;`", ws(char(';'))),
+        ))),
     ))(input)?;
+
+    // Without an explicit alias, the last part of the path is used,
+    // so `use a.b;` lets `b.c` stand for `a.b.c`.
+    let alias = alias.unwrap_or_else(|| {
+        let last = path.0.rsplit('.').next().unwrap_or_default();
+        Spanned::new(DefKey(last.to_owned()), path.span)
+    });
+    Ok((
+        remain,
+        Unit::Use {
+            path,
+            alias,
+            span: span_of(&text),
+        },
+    ))
+}
+
+/// Parses units until the end of the input or, if `nested` is set,
+/// until the `}` which closes the enclosing namespace.
+///
+/// A unit which fails to parse does not stop the others from being
+/// parsed. Its error is reported, and parsing resumes wherever the
+/// next unit seems to begin.
+fn units(mut input: Input, nested: bool) -> IResult<Vec<Unit>> {
+    let mut units = Vec::new();
+    loop {
+        let (rest, _) = space_before_docs(input)?;
+        // Doc comments with no unit after them are plain comments.
+        let (end, _) = space(rest)?;
+        if end.fragment().is_empty() || (nested && end.fragment().starts_with('}')) {
+            return Ok((end, units));
+        }
+        match unit(rest) {
+            Ok((remain, mut unit)) => {
+                let lifted = recipe::lifted(&mut unit);
+                units.push(unit);
+                units.extend(lifted);
+                input = remain;
+            }
+            Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+                rest.extra.report(e.to_diagnostic());
+                let error = e.offset() - rest.location_offset();
+                let skip = recover::skip_unit(rest.fragment(), error);
+                if let Some(id) = recover::declared_id(&rest.fragment()[..skip]) {
+                    rest.extra.drop_unit(id);
+                }
+                (input, _) = rest.take_split(skip);
+            }
+            Err(nom::Err::Incomplete(_)) => unreachable!("Crucible only uses complete parsers"),
+        }
+    }
+}
+
+fn namespace(input: Input) -> IResult<Unit> {
+    let (rest, (doc, (attrs, cfg, lints), _)) =
+        tuple((doc_comments, local_attrs, ws(keyword("namespace"))))(input)?;
+    let (rest, (ns_id, open)) =
+        cut(pair(ws(spanned(defkey)), context("`{`", ws(spanned(char('{'))))))(rest)?;
+
+    // Constants declared inside the namespace belong to it,
+    // so the state has to know which namespace is being parsed.
+    let state = input.extra;
+    state.namespace.borrow_mut().push(ns_id.0.clone());
+    let parsed = cut(|i| units(i, true))(rest);
+    state.namespace.borrow_mut().pop();
+    let (rest, units) = parsed?;
+
+    let (remain, close) = opt(ws(char('}')))(rest)?;
+    let (_, text) = input.take_split(remain.location_offset() - input.location_offset());
+    // `units` only stops early at a `}`, so a missing one
+    // means the namespace ran on to the end of the file.
+    if close.is_none() {
+        state.report(
+            Diagnostic::error(Code::Syntax, open.span, "this namespace is never closed")
+                .with_note("expected a `}` before the end of the file"),
+        );
+    }
     Ok((
         remain,
         Unit::Namespace {
             id: ns_id,
+            doc,
             attrs,
+            cfg,
+            lints,
             units,
+            span: span_of(&text),
         },
     ))
 }
 
-#[derive(Debug)]
+/// The kind of component a reference must name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expected {
+    /// An aspect, or a card, which counts as an aspect of itself.
+    Element,
+    Aspect,
+    Card,
+    Deck,
+    Recipe,
+    Verb,
+    Ending,
+}
+
+impl Expected {
+    /// Whether a component of `kind`, as given
+    /// by [Component::kind], may be named.
+    pub fn accepts(self, kind: &str) -> bool {
+        match self {
+            Expected::Element => kind == "aspect" || kind == "card",
+            Expected::Aspect => kind == "aspect",
+            Expected::Card => kind == "card",
+            Expected::Deck => kind == "deck",
+            Expected::Recipe => kind == "recipe",
+            Expected::Verb => kind == "verb",
+            Expected::Ending => kind == "ending",
+        }
+    }
+
+    /// What may be named, as in "expected an aspect or card".
+    pub fn describe(self) -> &'static str {
+        match self {
+            Expected::Element => "an aspect or card",
+            Expected::Aspect => "an aspect",
+            Expected::Card => "a card",
+            Expected::Deck => "a deck",
+            Expected::Recipe => "a recipe",
+            Expected::Verb => "a verb",
+            Expected::Ending => "an ending",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum Component {
-    Aspect(Box<Aspect>),
-    Card(Box<Card>),
-    Deck(Box<Deck>),
-    Recipe(Box<Recipe>),
-    Verb(Box<Verb>),
-    Legacy(Box<Legacy>),
-    Ending(Box<Ending>),
+    Aspect(Box<aspect::AspectDef>),
+    Card(Box<card::CardDef>),
+    Deck(Box<deck::DeckDef>),
+    Recipe(Box<recipe::RecipeDef>),
+    Verb(Box<verb::VerbDef>),
+    Legacy(Box<legacy::LegacyDef>),
+    Ending(Box<ending::EndingDef>),
 }
 
 impl Component {
-    pub fn id(&self) -> DefKey {
+    /// The keyword that declares this kind of component.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Component::Aspect(_) => "aspect",
+            Component::Card(_) => "card",
+            Component::Deck(_) => "deck",
+            Component::Recipe(_) => "recipe",
+            Component::Verb(_) => "verb",
+            Component::Legacy(_) => "legacy",
+            Component::Ending(_) => "ending",
+        }
+    }
+
+    /// The name shown for this component in game, if it has one.
+    pub fn label(&self) -> Option<&str> {
+        match self {
+            Component::Aspect(c) => Some(&c.label),
+            Component::Card(c) => Some(&c.label),
+            Component::Deck(c) => Some(&c.label),
+            Component::Recipe(c) => Some(&c.label),
+            Component::Verb(c) => Some(&c.label),
+            Component::Legacy(_) | Component::Ending(_) => None,
+        }
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        match self {
+            Component::Aspect(c) => Some(&c.description),
+            Component::Card(c) => Some(&c.description),
+            Component::Deck(c) => Some(&c.description),
+            Component::Recipe(c) => Some(&c.description),
+            Component::Verb(c) => Some(&c.description),
+            Component::Legacy(_) | Component::Ending(_) => None,
+        }
+    }
+
+    pub fn id(&self) -> Spanned<DefKey> {
         match self {
             Component::Aspect(c) => c.id.clone(),
             Component::Card(c) => c.id.clone(),
@@ -240,10 +965,59 @@ impl Component {
             Component::Ending(c) => c.id.clone(),
         }
     }
+
+    pub fn id_mut(&mut self) -> &mut Spanned<DefKey> {
+        match self {
+            Component::Aspect(c) => &mut c.id,
+            Component::Card(c) => &mut c.id,
+            Component::Deck(c) => &mut c.id,
+            Component::Recipe(c) => &mut c.id,
+            Component::Verb(c) => &mut c.id,
+            Component::Legacy(c) => &mut c.id,
+            Component::Ending(c) => &mut c.id,
+        }
+    }
+
+    /// Every id this component uses to refer to another
+    /// component, with the kind of component it must name.
+    pub fn references_mut(&mut self) -> Vec<(Expected, &mut Spanned<DefKey>)> {
+        use Expected::*;
+        let mut refs = Vec::new();
+        match self {
+            Component::Aspect(c) => {
+                refs.extend(c.induces.as_mut().map(|(key, _)| (Recipe, key)));
+                refs.extend(c.decays_to.as_mut().map(|key| (Element, key)));
+                c.xtriggers.iter_mut().for_each(|x| refs.extend(x.references_mut()));
+            }
+            Component::Card(c) => {
+                refs.extend(c.induces.as_mut().map(|(key, _)| (Recipe, key)));
+                refs.extend(c.decays_to.as_mut().map(|key| (Element, key)));
+                refs.extend(c.aspects.iter_mut().map(|(key, _)| (Element, key)));
+                refs.extend(c.uniqueness_group.as_mut().map(|key| (Aspect, key)));
+                for (verb, slot) in c.slots.iter_mut() {
+                    refs.push((Verb, verb));
+                    refs.extend(slot.references_mut());
+                }
+                c.xtriggers.iter_mut().for_each(|x| refs.extend(x.references_mut()));
+            }
+            Component::Deck(c) => {
+                refs.extend(c.default.as_mut().map(|key| (Card, key)));
+                refs.extend(c.cards.iter_mut().map(|(key, _, _)| (Card, key)));
+            }
+            Component::Recipe(c) => refs.extend(c.references_mut()),
+            Component::Verb(c) => {
+                if let Some(slot) = c.slot.as_mut() {
+                    refs.extend(slot.references_mut());
+                }
+            }
+            Component::Legacy(_) | Component::Ending(_) => (),
+        }
+        refs
+    }
 }
 
-fn component(input: &str) -> IResult<&str, Unit> {
-    fn component_inner(input: &str) -> IResult<&str, Component> {
+fn component(input: Input) -> IResult<Unit> {
+    fn component_inner(input: Input) -> IResult<Component> {
         alt((
             aspect::parse,
             card::parse,
@@ -254,58 +1028,226 @@ fn component(input: &str) -> IResult<&str, Unit> {
             ending::parse,
         ))(input)
     }
-    let (remain, (attrs, inherits, component_inner)) =
-        tuple((many0(ws(local_attr)), opt(ws(inherit)), ws(component_inner)))(input)?;
+    let (remain, (text, (doc, (attrs, cfg, lints), inherits, component_inner))) = consumed(tuple((
+        doc_comments,
+        local_attrs,
+        opt(ws(inherit)),
+        ws(component_inner),
+    )))(input)?;
     Ok((
         remain,
         Unit::Component {
             id: component_inner.id(),
+            doc,
             attrs,
+            cfg,
+            lints,
             component: component_inner,
             inherits,
+            span: span_of(&text),
         },
     ))
 }
 
-fn inherit(input: &str) -> IResult<&str, DefKey> {
-    let (remain, (_, key)) = pair(ws(tag_no_case("from")), ws(defkey))(input)?;
+fn inherit(input: Input) -> IResult<Spanned<DefKey>> {
+    let (remain, (_, key)) = pair(ws(keyword("from")), cut(ws(spanned(defkey))))(input)?;
     Ok((remain, key))
 }
 
-fn hidden(input: &str) -> IResult<&str, ()> {
-    let (remain, _) = alt((tag("hidden"), tag("?")))(input)?;
+fn hidden(input: Input) -> IResult<()> {
+    let (remain, _) = alt((keyword("hidden"), tag("?")))(input)?;
     Ok((remain, ()))
 }
 
-fn xtrigger(input: &str) -> IResult<&str, Xtrigger> {
+/// Records that `key` was assigned by a `set` statement at `span`.
+/// If it had already been assigned, reports a diagnostic pointing
+/// at both assignments and returns false.
+fn first_assignment(
+    state: &ParseState,
+    seen: &mut HashMap<DefKey, Span>,
+    key: &DefKey,
+    span: Span,
+    component: &str,
+) -> bool {
+    match seen.get(key) {
+        Some(first) => {
+            state.report(
+                Diagnostic::error(
+                    Code::DuplicateKey,
+                    span,
+                    format!("key `{}` is already assigned in this {}", key, component),
+                )
+                .with_label(*first, "first assigned here"),
+            );
+            false
+        }
+        None => {
+            seen.insert(key.clone(), span);
+            true
+        }
+    }
+}
+
+/// Reports a diagnostic for `set` statements that try to
+/// assign a key which is part of the component's signature.
+fn reserved_key(state: &ParseState, key: &DefKey, span: Span, component: &str) {
+    state.report(
+        Diagnostic::error(
+            Code::ReservedKey,
+            span,
+            format!("`{}` cannot be assigned with `set`", key),
+        )
+        .with_note(format!("`{}` is part of the {} signature", key, component)),
+    );
+}
+
+/// Reports a diagnostic for `set description` in a component whose
+/// signature already gave it a description. Unlike the other signature
+/// keys, the description may be left out of the signature and assigned
+/// with `set` instead, which is handy for long text kept in a file.
+fn description_in_signature(state: &ParseState, key: &DefKey, span: Span, component: &str) {
+    state.report(
+        Diagnostic::error(
+            Code::ReservedKey,
+            span,
+            format!("`{}` is already given in the {} signature", key, component),
+        )
+        .with_note("leave the description out of the signature to assign it with `set`"),
+    );
+}
+
+/// Reports a diagnostic for a statement that may only appear once
+/// in a component but was repeated.
+fn duplicate_statement(state: &ParseState, statement: &str, span: Span, first: Span, component: &str) {
+    state.report(
+        Diagnostic::error(
+            Code::DuplicateStatement,
+            span,
+            format!("`{}` may only appear once in this {}", statement, component),
+        )
+        .with_label(first, "first used here"),
+    );
+}
+
+/// Parses a `set key = value` statement, for components
+/// whose only statements are assignments.
+fn set_statement(input: Input) -> IResult<(DefKey, json::Value)> {
+    let (remain, (_, pair)) = pair(
+        ws(keyword("set")),
+        cut(separated_pair(ws(defkey), context("`=`", char('=')), ws(json_value))),
+    )(input)?;
+    Ok((remain, pair))
+}
+
+/// Collects the keys assigned by [set_statement]s, reporting
+/// any that are `reserved` by the component's signature.
+fn assignments(
+    state: &ParseState,
+    statements: Vec<Spanned<(DefKey, json::Value)>>,
+    reserved: &[&str],
+    component: &str,
+) -> HashMap<DefKey, json::Value> {
+    let mut others = HashMap::new();
+    let mut assigned: HashMap<DefKey, Span> = HashMap::new();
+    for Spanned { item: (k, v), span } in statements {
+        if reserved.contains(&k.0.as_str()) {
+            reserved_key(state, &k, span, component);
+        } else if first_assignment(state, &mut assigned, &k, span, component) {
+            others.insert(k, v);
+        }
+    }
+    others
+}
+
+fn expect_string(state: &ParseState, key: &DefKey, value: json::Value, span: Span) -> Option<String> {
+    match value {
+        json::Value::Str(s) => Some(s),
+        _ => {
+            state.report(Diagnostic::error(
+                Code::MismatchedType,
+                span,
+                format!("key `{}` must be of type `string`", key),
+            ));
+            None
+        }
+    }
+}
+
+fn expect_bool(state: &ParseState, key: &DefKey, value: json::Value, span: Span) -> Option<bool> {
+    match value {
+        json::Value::Boolean(b) => Some(b),
+        _ => {
+            state.report(Diagnostic::error(
+                Code::MismatchedType,
+                span,
+                format!("key `{}` must be of type `boolean`", key),
+            ));
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum XtriggerDef {
+    Transform {
+        catalyst: Spanned<DefKey>,
+        transforms_to: Spanned<DefKey>,
+        amount: u32,
+        chance: Probability,
+    },
+    Spawn {
+        catalyst: Spanned<DefKey>,
+        creates: Spanned<DefKey>,
+        amount: u32,
+        chance: Probability,
+    },
+    Mutate {
+        catalyst: Spanned<DefKey>,
+        adds_to_catalyst: Spanned<DefKey>,
+        amount: i32,
+        chance: Probability,
+    },
+}
+
+impl XtriggerDef {
+    pub fn references_mut(&mut self) -> [(Expected, &mut Spanned<DefKey>); 2] {
+        use Expected::*;
+        match self {
+            XtriggerDef::Transform { catalyst, transforms_to, .. } => [(Element, catalyst), (Element, transforms_to)],
+            XtriggerDef::Spawn { catalyst, creates, .. } => [(Element, catalyst), (Element, creates)],
+            XtriggerDef::Mutate { catalyst, adds_to_catalyst, .. } => [(Element, catalyst), (Aspect, adds_to_catalyst)],
+        }
+    }
+}
+
+fn xtrigger(input: Input) -> IResult<XtriggerDef> {
     enum XtriggerKind {
         Transform {
-            target: DefKey,
+            target: Spanned<DefKey>,
             amount: u32,
             chance: Probability,
         },
         Spawn {
-            target: DefKey,
+            target: Spanned<DefKey>,
             amount: u32,
             chance: Probability,
         },
         Mutate {
-            target: DefKey,
+            target: Spanned<DefKey>,
             amount: i32,
             chance: Probability,
         },
     }
-    fn spawn(input: &str) -> IResult<&str, XtriggerKind> {
-        let (remain, (_, _, target, _, amount, _, chance, _)) = tuple((
-            ws(tag_no_case("spawn")),
-            multispace0,
-            defkey,
-            char(':'),
-            u32,
-            multispace0,
-            opt(chance),
-            multispace0,
-        ))(input)?;
+    fn spawn(input: Input) -> IResult<XtriggerKind> {
+        let (remain, (_, (target, _, amount, chance))) = pair(
+            ws(keyword("spawn")),
+            cut(tuple((
+                ws(spanned(defkey)),
+                context("`:`", ws(char(':'))),
+                context("an amount", ws(unsigned)),
+                opt(ws(chance)),
+            ))),
+        )(input)?;
 
         let chance = chance.unwrap_or_else(|| Probability::new(100).unwrap());
         Ok((
@@ -317,17 +1259,16 @@ fn xtrigger(input: &str) -> IResult<&str, Xtrigger> {
             },
         ))
     }
-    fn mutate(input: &str) -> IResult<&str, XtriggerKind> {
-        let (remain, (_, _, target, _, amount, _, chance, _)) = tuple((
-            ws(tag_no_case("mutate")),
-            multispace0,
-            defkey,
-            char(':'),
-            i32,
-            multispace0,
-            opt(chance),
-            multispace0,
-        ))(input)?;
+    fn mutate(input: Input) -> IResult<XtriggerKind> {
+        let (remain, (_, (target, _, amount, chance))) = pair(
+            ws(keyword("mutate")),
+            cut(tuple((
+                ws(spanned(defkey)),
+                context("`:`", ws(char(':'))),
+                context("an amount", ws(signed)),
+                opt(ws(chance)),
+            ))),
+        )(input)?;
 
         let chance = chance.unwrap_or_else(|| Probability::new(100).unwrap());
         Ok((
@@ -339,15 +1280,12 @@ fn xtrigger(input: &str) -> IResult<&str, Xtrigger> {
             },
         ))
     }
-    fn transform(input: &str) -> IResult<&str, XtriggerKind> {
-        let (remain, (_, target, _, amount, _, chance, _)) = tuple((
-            multispace0,
-            defkey,
-            char(':'),
-            u32,
-            multispace0,
-            opt(chance),
-            multispace0,
+    fn transform(input: Input) -> IResult<XtriggerKind> {
+        let (remain, (target, _, amount, chance)) = tuple((
+            ws(spanned(defkey)),
+            ws(char(':')),
+            context("an amount", cut(ws(unsigned))),
+            opt(ws(chance)),
         ))(input)?;
 
         let chance = chance.unwrap_or_else(|| Probability::new(100).unwrap());
@@ -360,8 +1298,8 @@ fn xtrigger(input: &str) -> IResult<&str, Xtrigger> {
             },
         ))
     }
-    fn basic(input: &str) -> IResult<&str, XtriggerKind> {
-        let (remain, (target, chance)) = tuple((ws(defkey), opt(ws(chance))))(input)?;
+    fn basic(input: Input) -> IResult<XtriggerKind> {
+        let (remain, (target, chance)) = tuple((ws(spanned(defkey)), opt(ws(chance))))(input)?;
 
         let chance = chance.unwrap_or_else(|| Probability::new(100).unwrap());
         Ok((
@@ -374,19 +1312,24 @@ fn xtrigger(input: &str) -> IResult<&str, Xtrigger> {
         ))
     }
 
-    let (remain, (_, catalyst, _, trigger_inner)) = tuple((
-        ws(tag("xtrigger")),
-        ws(defkey),
-        ws(tag("->")),
-        alt((ws(spawn), ws(mutate), ws(transform), ws(basic))),
-    ))(input)?;
+    let (remain, (_, (catalyst, _, trigger_inner))) = pair(
+        ws(keyword("xtrigger")),
+        cut(tuple((
+            ws(spanned(defkey)),
+            context("`->`", ws(tag("->"))),
+            context(
+                "an xtrigger effect",
+                alt((ws(spawn), ws(mutate), ws(transform), ws(basic))),
+            ),
+        ))),
+    )(input)?;
 
     let trigger = match trigger_inner {
         XtriggerKind::Transform {
             target,
             amount,
             chance,
-        } => Xtrigger::Transform {
+        } => XtriggerDef::Transform {
             catalyst,
             transforms_to: target,
             amount,
@@ -396,7 +1339,7 @@ fn xtrigger(input: &str) -> IResult<&str, Xtrigger> {
             target,
             amount,
             chance,
-        } => Xtrigger::Spawn {
+        } => XtriggerDef::Spawn {
             catalyst,
             creates: target,
             amount,
@@ -406,7 +1349,7 @@ fn xtrigger(input: &str) -> IResult<&str, Xtrigger> {
             target,
             amount,
             chance,
-        } => Xtrigger::Mutate {
+        } => XtriggerDef::Mutate {
             catalyst,
             adds_to_catalyst: target,
             amount,
@@ -417,67 +1360,85 @@ fn xtrigger(input: &str) -> IResult<&str, Xtrigger> {
     Ok((remain, trigger))
 }
 
-/// Parses a single SlotDef. Does not parse predicates, such
-/// as the verbs in a card's slot def.
-fn slot(input: &str) -> IResult<&str, Slot> {
-    // returns (isConsume, isGreedy)
-    pub fn slotkind(input: &str) -> IResult<&str, (Option<()>, Option<()>)> {
-        let (remain, (is_consume, is_greedy)) = alt((
-            permutation((ws(tag("!")), ws(tag("?")))),
-            permutation((ws(tag_no_case("consume")), ws(tag_no_case("greedy")))),
-            pair(success::<_, _, _>(""), ws(tag("!"))),
-            pair(ws(tag("?")), success::<_, _, _>("")),
-            pair(success::<_, _, _>(""), ws(tag_no_case("consume"))),
-            pair(ws(tag_no_case("greedy")), success::<_, _, _>("")),
-        ))(input)?;
+#[derive(Debug, Clone)]
+pub struct SlotDef {
+    pub id: Spanned<DefKey>,
+    pub label: String,
+    pub description: String,
+    pub consumes: bool,
+    pub greedy: bool,
+    pub requirements: Vec<SlotFilterDef>,
+}
 
-        let is_consume = match is_consume.to_lowercase().as_str() {
-            "!" | "consume" => Some(()),
-            _ => None,
-        };
+#[derive(Debug, Clone)]
+pub enum SlotFilterDef {
+    Accept { element: Spanned<DefKey>, amount: u32 },
+    Forbid { element: Spanned<DefKey>, amount: u32 },
+}
 
-        let is_greedy = match is_greedy.to_lowercase().as_str() {
-            "?" | "greedy" => Some(()),
-            _ => None,
-        };
+impl SlotDef {
+    /// The elements named by this slot's filters. The slot's
+    /// own id is a declaration, not a reference.
+    pub fn references_mut(&mut self) -> impl Iterator<Item = (Expected, &mut Spanned<DefKey>)> {
+        self.requirements.iter_mut().map(|filter| match filter {
+            SlotFilterDef::Accept { element, .. } | SlotFilterDef::Forbid { element, .. } => (Expected::Element, element),
+        })
+    }
+}
 
-        Ok((remain, (is_consume, is_greedy)))
+/// Parses a single SlotDef. Does not parse predicates, such
+/// as the verbs in a card's slot def.
+fn slot(input: Input) -> IResult<SlotDef> {
+    // returns (isConsume, isGreedy)
+    fn slotkind(input: Input) -> IResult<(bool, bool)> {
+        fn consume(input: Input) -> IResult<Input> {
+            alt((tag("!"), keyword("consume")))(input)
+        }
+        fn greedy(input: Input) -> IResult<Input> {
+            alt((tag("?"), keyword("greedy")))(input)
+        }
+        alt((
+            map(permutation((ws(consume), ws(greedy))), |_| (true, true)),
+            map(ws(consume), |_| (true, false)),
+            map(ws(greedy), |_| (false, true)),
+        ))(input)
     }
-    pub fn slotfilter(input: &str) -> IResult<&str, SlotFilter> {
-        let (remain, (forbid, element, _, amount)) =
-            tuple((opt(char('!')), defkey, char(':'), u32))(input)?;
+    fn slotfilter(input: Input) -> IResult<SlotFilterDef> {
+        let (remain, (forbid, element, amount)) = tuple((
+            opt(ws(char('!'))),
+            ws(spanned(defkey)),
+            opt(preceded(ws(char(':')), context("an amount", cut(ws(unsigned))))),
+        ))(input)?;
 
+        let amount = amount.unwrap_or(1);
         let filter = match forbid {
-            Some(_) => SlotFilter::Accept { element, amount },
-            None => SlotFilter::Forbid { element, amount },
+            Some(_) => SlotFilterDef::Forbid { element, amount },
+            None => SlotFilterDef::Accept { element, amount },
         };
         Ok((remain, filter))
     }
 
-    let (remain, (kind, _, id, label, description, requirements)) = tuple((
+    let (remain, (kind, _, (id, label, description, requirements))) = tuple((
         opt(ws(slotkind)),
-        ws(tag_no_case("slot")),
-        ws(defkey),
-        ws(string::parse),
-        ws(string::parse),
-        opt(delimited(
-            ws(char('(')),
-            separated_list0(char(','), slotfilter),
-            ws(char(')')),
-        )),
+        ws(keyword("slot")),
+        cut(tuple((
+            ws(spanned(defkey)),
+            ws(string),
+            ws(string),
+            opt(delimited(
+                ws(char('(')),
+                separated_list0(ws(char(',')), slotfilter),
+                context("`,` or `)`", list_end(')')),
+            )),
+        ))),
     ))(input)?;
 
-    let mut consumes = false;
-    let mut greedy = false;
-    if let Some((is_consume, is_greedy)) = kind {
-        consumes = is_consume.is_some();
-        greedy = is_greedy.is_some();
-    }
+    let (consumes, greedy) = kind.unwrap_or((false, false));
     let requirements = requirements.unwrap_or_default();
 
     Ok((
         remain,
-        Slot {
+        SlotDef {
             id,
             label,
             description,
@@ -488,41 +1449,85 @@ fn slot(input: &str) -> IResult<&str, Slot> {
     ))
 }
 
-fn chance(input: &str) -> IResult<&str, Probability> {
-    let (remain, (num, _)) = pair(verify(u8, |num| matches!(num, 0..=100)), opt(char('%')))(input)?;
-    // The verify() combinator above makes sure the value
-    // is OK, so we can just unwrap here
-    Ok((
-        remain,
-        Probability::new(num).expect("Parser failed to uphold Probability invariant!"),
-    ))
+fn chance(input: Input) -> IResult<Probability> {
+    let (remain, (chance, _)) = pair(
+        context("a chance between 0 and 100", probability),
+        opt(preceded(space, char('%'))),
+    )(input)?;
+    Ok((remain, chance))
 }
 
 /// A combinator that takes a parser `inner` and produces a parser that also consumes both leading and
-/// trailing whitespace, returning the output of `inner`. It also consumed and discards comments of any kind.
-fn ws<'a, F: 'a, O, E: ParseError<&'a str>>(
-    inner: F,
-) -> impl FnMut(&'a str) -> IResult<&'a str, O, E>
+/// trailing whitespace, returning the output of `inner`. It also consumes and discards comments.
+fn ws<'a, F: 'a, O>(inner: F) -> impl FnMut(Input<'a>) -> IResult<'a, O>
 where
-    F: Fn(&'a str) -> IResult<&'a str, O, E>,
+    F: FnMut(Input<'a>) -> IResult<'a, O>,
 {
-    fn space_or_comment(input: &str) -> IResult<&str, ()> {
-        fn multispace_0_wrapper(input: &str) -> IResult<&str, ()> {
-            let (remain, _) = multispace0(input)?;
-            Ok((remain, ()))
-        }
-        alt((comment, block_comment, multispace_0_wrapper))(input)
+    // A doc comment after `inner` is left for whatever follows it.
+    delimited(space, inner, space_before_docs)
+}
+
+/// Skips any amount of whitespace and comments.
+///
+/// `//` comments run to the end of the line, and `/* */` comments
+/// may span several lines but do not nest. A line starting with
+/// exactly three slashes is a doc comment rather than a plain one,
+/// but only where something follows that it can document, so it
+/// is skipped like any other comment here.
+fn space(input: Input) -> IResult<()> {
+    let comment = recognize(pair(tag("//"), not_line_ending));
+    let (remain, _) = many0_count(alt((multispace1, comment, block_comment)))(input)?;
+    Ok((remain, ()))
+}
+
+/// Skips whitespace and comments like [space], but stops at
+/// a doc comment, leaving it in place for [doc_comments].
+fn space_before_docs(input: Input) -> IResult<()> {
+    let (remain, _) = many0_count(alt((multispace1, line_comment, block_comment)))(input)?;
+    Ok((remain, ()))
+}
+
+fn line_comment(input: Input) -> IResult<Input> {
+    fn doc_marker(input: Input) -> IResult<Input> {
+        terminated(tag("/"), not(char('/')))(input)
     }
-    delimited(multispace0, inner, multispace0)
+    recognize(tuple((tag("//"), not(doc_marker), not_line_ending)))(input)
 }
 
-fn comment<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, (), E> {
-    let (remainder, (_slashes, _comment)) = pair(tag("//"), is_not("\n\r"))(i)?;
-    Ok((remainder, ()))
+fn block_comment(input: Input) -> IResult<Input> {
+    let (body, _) = tag("/*")(input)?;
+    match body.fragment().find("*/") {
+        Some(end) => {
+            let (remain, _) = body.take_split(end + 2);
+            let (_, comment) = input.take_split(input.fragment().len() - remain.fragment().len());
+            Ok((remain, comment))
+        }
+        None => {
+            let (eof, _) = body.take_split(body.fragment().len());
+            nomfail!(Error {
+                input: eof,
+                kind: ErrorKind::TakeUntil,
+                expected: Some("`*/` to close the comment"),
+            })
+        }
+    }
 }
 
-fn block_comment<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, (), E> {
-    let (remainder, (_open, _comment, _close)) =
-        tuple((tag("(*"), take_until("*)"), tag("*)")))(i)?;
-    Ok((remainder, ()))
+/// Parses a run of `///` doc comment lines, joining their text
+/// with newlines. Returns `None` if there are none.
+fn doc_comments(input: Input) -> IResult<Option<String>> {
+    fn doc_comment(input: Input) -> IResult<String> {
+        let (remain, text) =
+            preceded(pair(tag("///"), not(char('/'))), not_line_ending)(input)?;
+        let text = text.fragment();
+        let text = text.strip_prefix(' ').unwrap_or(text).trim_end();
+        Ok((remain, text.to_owned()))
+    }
+    let (remain, lines) = many0(delimited(space_before_docs, doc_comment, space_before_docs))(input)?;
+    let doc = if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join("\n"))
+    };
+    Ok((remain, doc))
 }
@@ -1,5 +1,6 @@
 #![allow(unused_imports)]
 
+use std::collections::HashMap;
 use std::path::Path;
 use std::path::PathBuf;
 use mothlib::lantern::Attribute;
@@ -8,7 +9,6 @@ use tracing::{event, Level};
 use anyhow::{bail, Result};
 
 use nom::{
-    IResult,
     bytes::complete::*,
     character::complete::*,
     multi::*,
@@ -17,8 +17,1139 @@ use nom::{
     combinator::*,
     error::*,
 };
+use super::requirement::{requirements, Written};
 use super::*;
 
-pub fn parse(input: &str) -> IResult<&str, Component> {
-    todo!()
-}
\ No newline at end of file
+#[derive(Debug, Clone)]
+pub struct RecipeDef {
+    pub id: Spanned<DefKey>,
+    pub verb: Spanned<DefKey>,
+    pub label: String,
+    pub description: String,
+    pub end_description: String,
+    pub burn: Option<String>,
+    pub portal: Option<String>,
+    /// The requirements as written, as every way they can be met.
+    pub written: Vec<Vec<Written>>,
+    /// The requirements [crate::combine] made of `written`.
+    pub requirements: Vec<RequirementDef>,
+    /// The other ways the requirements can be met, if they hold
+    /// an `or`. The recipe is split into a variant for each.
+    pub alternatives: Vec<Vec<RequirementDef>>,
+    pub max_executions: u32,
+    pub warmup: u32,
+    pub craftable: bool,
+    pub hint_only: bool,
+    /// Whether this is a later stage of another recipe, which is
+    /// only ever reached from the stage before it.
+    pub stage: bool,
+    pub slot: Option<SlotDef>,
+    pub effects: Vec<(Spanned<DefKey>, ValueOperation)>,
+    pub purge: Vec<(Spanned<DefKey>, u32)>,
+    pub draws: Vec<(Spanned<DefKey>, i32)>,
+    /// The deck written inline in a `draw` statement, which
+    /// belongs to this recipe alone, and how many cards are
+    /// drawn from it.
+    pub internal_deck: Option<(DeckDef, i32)>,
+    pub mutations: Vec<MutationDef>,
+    pub halt: Vec<(Spanned<DefKey>, u32)>,
+    pub delete: Vec<(Spanned<DefKey>, u32)>,
+    pub ending: Option<Spanned<DefKey>>,
+    pub style: WarmupStyle,
+    pub branches: Vec<BranchDef>,
+    pub others: HashMap<DefKey, json::Value>,
+    /// The recipes and temporary verbs written inline in this
+    /// one's branches, and its later stages, which [lifted] moves
+    /// out to sit beside it once it has been parsed.
+    pub lifted: Vec<Lifted>,
+}
+
+/// A recipe or verb written as part of a recipe.
+#[derive(Debug, Clone)]
+pub struct Lifted {
+    pub component: Component,
+    /// The recipe named by a stage's `from`.
+    pub inherits: Option<Spanned<DefKey>>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConditionDef {
+    pub chance: Option<Probability>,
+    /// The requirements as written, as every way they can be met.
+    pub written: Vec<Vec<Written>>,
+    /// The requirements [crate::combine] made of `written`.
+    pub requirements: Vec<RequirementDef>,
+    /// The other ways the requirements can be met, if they hold
+    /// an `or`. The branch is split into one for each.
+    pub alternatives: Vec<Vec<RequirementDef>>,
+}
+
+impl ConditionDef {
+    /// A condition which always holds.
+    fn always(chance: Option<Probability>) -> Self {
+        ConditionDef { chance, written: Vec::new(), requirements: Vec::new(), alternatives: Vec::new() }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SpawningDef {
+    Spawn,
+    Expel(Vec<(Spanned<DefKey>, u32)>),
+}
+
+#[derive(Debug, Clone)]
+pub enum BranchDef {
+    Link {
+        target: Spanned<DefKey>,
+        condition: ConditionDef,
+    },
+    Goto {
+        target: Spanned<DefKey>,
+        condition: ConditionDef,
+        action: Option<SpawningDef>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct MutationDef {
+    pub id: Spanned<DefKey>,
+    pub aspect: Spanned<DefKey>,
+    pub operation: ValueOperation,
+}
+
+impl RecipeDef {
+    /// Every id this recipe uses to refer to another
+    /// component, with the kind of component it must name.
+    pub fn references_mut(&mut self) -> Vec<(Expected, &mut Spanned<DefKey>)> {
+        use Expected::*;
+        let mut refs = vec![(Verb, &mut self.verb)];
+        self.written.iter_mut().flatten().for_each(|w| refs.extend(w.references_mut()));
+        if let Some(slot) = self.slot.as_mut() {
+            refs.extend(slot.references_mut());
+        }
+        refs.extend(self.effects.iter_mut().map(|(key, _)| (Element, key)));
+        refs.extend(self.purge.iter_mut().map(|(key, _)| (Element, key)));
+        refs.extend(self.draws.iter_mut().map(|(key, _)| (Deck, key)));
+        if let Some((deck, _)) = self.internal_deck.as_mut() {
+            refs.extend(deck.default.as_mut().map(|key| (Card, key)));
+            refs.extend(deck.cards.iter_mut().map(|(key, _, _)| (Card, key)));
+        }
+        for mutation in self.mutations.iter_mut() {
+            refs.push((Element, &mut mutation.id));
+            refs.push((Aspect, &mut mutation.aspect));
+        }
+        refs.extend(self.halt.iter_mut().map(|(key, _)| (Verb, key)));
+        refs.extend(self.delete.iter_mut().map(|(key, _)| (Verb, key)));
+        refs.extend(self.ending.as_mut().map(|key| (Ending, key)));
+        for branch in self.branches.iter_mut() {
+            let (target, condition, action) = match branch {
+                BranchDef::Link { target, condition } => (target, condition, None),
+                BranchDef::Goto { target, condition, action } => (target, condition, action.as_mut()),
+            };
+            refs.push((Recipe, target));
+            condition.written.iter_mut().flatten().for_each(|w| refs.extend(w.references_mut()));
+            if let Some(SpawningDef::Expel(elements)) = action {
+                refs.extend(elements.iter_mut().map(|(key, _)| (Element, key)));
+            }
+        }
+        refs
+    }
+}
+
+pub fn parse(input: Input) -> IResult<Component> {
+    let (remain, ((craftable, hint_only), verb, _, (id, written, max_executions, statements, stages))) = tuple((
+        map(opt(ws(recipe_kind)), Option::unwrap_or_default),
+        ws(spanned(defkey)),
+        ws(keyword("recipe")),
+        cut(tuple((
+            ws(spanned(defkey)),
+            context("a requirement list", ws(requirements)),
+            opt(ws(max_executions)),
+            block(recipe_statement),
+            // A `from` that isn't followed by a stage belongs to the next component.
+            many0(pair(opt(ws(inherit)), ws(spanned(block(recipe_statement))))),
+        ))),
+    ))(input)?;
+
+    let state = input.extra;
+    let header = RecipeHeader { id, verb, craftable, hint_only, written, max_executions };
+    let mut recipe = recipe_from_tokens(state, header, statements);
+
+    // Each stage after the first is a recipe of its own, which
+    // the one before links to once it has done everything else.
+    let mut later: Vec<(RecipeDef, Option<Spanned<DefKey>>, Span)> = Vec::new();
+    for (n, (inherits, stage)) in stages.into_iter().enumerate() {
+        let header = RecipeHeader {
+            id: Spanned::new(DefKey(format!("{}.__stage{}", recipe.id, n + 2)), stage.span),
+            verb: recipe.verb.clone(),
+            craftable: false,
+            hint_only: false,
+            written: Vec::new(),
+            max_executions: None,
+        };
+        let mut next = recipe_from_tokens(state, header, stage.item);
+        next.stage = true;
+        let previous = later.last_mut().map_or(&mut recipe, |(stage, _, _)| stage);
+        let target = next.id.clone();
+        previous.branches.push(BranchDef::Link { target, condition: ConditionDef::always(None) });
+        later.push((next, inherits, stage.span));
+    }
+    for (mut stage, inherits, span) in later {
+        let nested = std::mem::take(&mut stage.lifted);
+        recipe.lifted.push(Lifted { component: Component::Recipe(Box::new(stage)), inherits, span });
+        recipe.lifted.extend(nested);
+    }
+    Ok((remain, Component::Recipe(Box::new(recipe))))
+}
+
+// returns (isCraftable, isHintOnly)
+fn recipe_kind(input: Input) -> IResult<(bool, bool)> {
+    fn craft(input: Input) -> IResult<Input> {
+        alt((tag("!"), keyword("craft")))(input)
+    }
+    fn hint(input: Input) -> IResult<Input> {
+        alt((tag("?"), keyword("hint")))(input)
+    }
+    alt((
+        map(permutation((ws(craft), ws(hint))), |_| (true, true)),
+        map(ws(craft), |_| (true, false)),
+        map(ws(hint), |_| (false, true)),
+    ))(input)
+}
+
+fn max_executions(input: Input) -> IResult<u32> {
+    let (remain, (_, max)) = pair(ws(keyword("max")), context("a number", cut(ws(unsigned))))(input)?;
+    Ok((remain, max))
+}
+
+enum RecipeStatement {
+    Set(DefKey, json::Value),
+    Warmup(u32),
+    Apply(Option<Spanned<DefKey>>, Spanned<DefKey>, ValueOperation),
+    Draw(Spanned<DefKey>, i32),
+    DrawInline(deck::InlineDeck, i32),
+    Signal(WarmupStyle),
+    Purge(Spanned<DefKey>, u32),
+    Burn(String),
+    Portal(String),
+    Ending(Spanned<DefKey>),
+    Halt(Option<Spanned<DefKey>>, u32),
+    Delete(Option<Spanned<DefKey>>, u32),
+    Branch(BranchDef, Option<InlineRecipe>),
+    Roll(Vec<Spanned<(u32, BranchDef, Option<InlineRecipe>)>>),
+    Slot(SlotDef),
+}
+
+fn recipe_statement(input: Input) -> IResult<RecipeStatement> {
+    fn set(input: Input) -> IResult<RecipeStatement> {
+        let (remain, (_, (key, val))) = pair(
+            ws(keyword("set")),
+            cut(separated_pair(ws(defkey), context("`=`", char('=')), ws(json_value))),
+        )(input)?;
+
+        Ok((remain, RecipeStatement::Set(key, val)))
+    }
+
+    fn warmup(input: Input) -> IResult<RecipeStatement> {
+        let (remain, (_, time)) =
+            pair(ws(keyword("warmup")), context("a number", cut(ws(unsigned))))(input)?;
+        Ok((remain, RecipeStatement::Warmup(time)))
+    }
+
+    fn apply(input: Input) -> IResult<RecipeStatement> {
+        fn operation(input: Input) -> IResult<ValueOperation> {
+            alt((
+                preceded(ws(tag("+=")), context("an amount", cut(map(ws(signed), ValueOperation::Add)))),
+                preceded(ws(tag("-=")), context("an amount", cut(map(ws(negated), ValueOperation::Add)))),
+                preceded(ws(char('=')), context("an amount", cut(map(ws(unsigned), ValueOperation::Set)))),
+            ))(input)
+        }
+        let (remain, (_, (first, second, operation))) = pair(
+            ws(keyword("apply")),
+            cut(tuple((
+                ws(spanned(defkey)),
+                // Ids may contain `-`, so `-=` must not be read as one.
+                opt(preceded(not(ws(tag("-="))), ws(spanned(defkey)))),
+                context("`+=`, `-=` or `=`", operation),
+            ))),
+        )(input)?;
+
+        let statement = match second {
+            Some(aspect) => RecipeStatement::Apply(Some(first), aspect, operation),
+            None => RecipeStatement::Apply(None, first, operation),
+        };
+        Ok((remain, statement))
+    }
+
+    fn draw(input: Input) -> IResult<RecipeStatement> {
+        let (remain, (_, (deck, amount))) = pair(
+            ws(keyword("draw")),
+            cut(pair(
+                alt((map(ws(deck::inline), Either::Right), map(ws(spanned(defkey)), Either::Left))),
+                opt(ws(signed)),
+            )),
+        )(input)?;
+        let amount = amount.unwrap_or(1);
+        let statement = match deck {
+            Either::Left(deck) => RecipeStatement::Draw(deck, amount),
+            Either::Right(deck) => RecipeStatement::DrawInline(deck, amount),
+        };
+        Ok((remain, statement))
+    }
+
+    fn signal(input: Input) -> IResult<RecipeStatement> {
+        let style = alt((
+            map(keyword("none"), |_| WarmupStyle::None),
+            map(keyword("grand"), |_| WarmupStyle::Grand),
+            map(keyword("melancholy"), |_| WarmupStyle::Mellancholy),
+            map(keyword("pale"), |_| WarmupStyle::Pale),
+            map(keyword("vile"), |_| WarmupStyle::Vile),
+            map(keyword("important"), |_| WarmupStyle::Important),
+        ));
+        let (remain, (_, style)) =
+            pair(ws(keyword("signal")), context("a warmup style", cut(ws(style))))(input)?;
+        Ok((remain, RecipeStatement::Signal(style)))
+    }
+
+    fn purge(input: Input) -> IResult<RecipeStatement> {
+        let (remain, (_, (element, amount))) = pair(
+            ws(keyword("purge")),
+            cut(pair(ws(spanned(defkey)), opt(ws(unsigned)))),
+        )(input)?;
+        Ok((remain, RecipeStatement::Purge(element, amount.unwrap_or(1))))
+    }
+
+    fn burn(input: Input) -> IResult<RecipeStatement> {
+        let (remain, (_, image)) = pair(ws(keyword("burn")), cut(ws(defkey)))(input)?;
+        Ok((remain, RecipeStatement::Burn(image.0)))
+    }
+
+    fn portal(input: Input) -> IResult<RecipeStatement> {
+        let (remain, (_, door)) = pair(ws(keyword("portal")), cut(ws(defkey)))(input)?;
+        Ok((remain, RecipeStatement::Portal(door.0)))
+    }
+
+    fn ending(input: Input) -> IResult<RecipeStatement> {
+        let (remain, (_, ending)) =
+            pair(ws(keyword("ending")), cut(ws(spanned(defkey))))(input)?;
+        Ok((remain, RecipeStatement::Ending(ending)))
+    }
+
+    fn halt(input: Input) -> IResult<RecipeStatement> {
+        let (remain, (_, (verb, amount))) = pair(
+            ws(keyword("halt")),
+            cut(pair(opt(ws(spanned(defkey))), opt(ws(unsigned)))),
+        )(input)?;
+        Ok((remain, RecipeStatement::Halt(verb, amount.unwrap_or(1))))
+    }
+
+    fn delete(input: Input) -> IResult<RecipeStatement> {
+        let (remain, (_, (verb, amount))) = pair(
+            ws(keyword("delete")),
+            cut(pair(opt(ws(spanned(defkey))), opt(ws(unsigned)))),
+        )(input)?;
+        Ok((remain, RecipeStatement::Delete(verb, amount.unwrap_or(1))))
+    }
+
+    fn branch(input: Input) -> IResult<RecipeStatement> {
+        let (remain, (branch, inline)) = self::branch(input)?;
+        Ok((remain, RecipeStatement::Branch(branch, inline)))
+    }
+
+    fn roll(input: Input) -> IResult<RecipeStatement> {
+        fn outcome(input: Input) -> IResult<(u32, BranchDef, Option<InlineRecipe>)> {
+            let (remain, (weight, (_, (branch, inline)))) = pair(
+                ws(context("a weight", unsigned)),
+                cut(pair(context("`:`", ws(char(':'))), context("`link` or `goto`", ws(rolled_branch)))),
+            )(input)?;
+            Ok((remain, (weight, branch, inline)))
+        }
+        let (remain, (_, outcomes)) = pair(ws(keyword("roll")), cut(block(outcome)))(input)?;
+        Ok((remain, RecipeStatement::Roll(outcomes)))
+    }
+
+    fn recipe_slot(input: Input) -> IResult<RecipeStatement> {
+        let (remain, slot) = slot(input)?;
+        Ok((remain, RecipeStatement::Slot(slot)))
+    }
+
+    context(
+        "a recipe statement",
+        alt((
+            ws(set),
+            ws(warmup),
+            ws(apply),
+            ws(draw),
+            ws(signal),
+            ws(purge),
+            ws(burn),
+            ws(portal),
+            ws(ending),
+            ws(halt),
+            ws(delete),
+            ws(branch),
+            ws(roll),
+            ws(recipe_slot),
+        )),
+    )(input)
+}
+
+/// A recipe written in place of the target of a branch, which
+/// is lifted out into a recipe of its own once it is parsed.
+struct InlineRecipe {
+    /// The verb it runs in, if not the same as its parent's.
+    verb: Option<InlineVerb>,
+    statements: Vec<Spanned<RecipeStatement>>,
+    span: Span,
+}
+
+impl InlineRecipe {
+    /// The inline recipe with this body, and the target for the
+    /// branch leading to it, which is left empty until the recipe
+    /// is given an id.
+    fn new(verb: Option<InlineVerb>, body: Spanned<Vec<Spanned<RecipeStatement>>>) -> (Spanned<DefKey>, Self) {
+        let target = Spanned::new(DefKey(String::new()), body.span);
+        (target, InlineRecipe { verb, statements: body.item, span: body.span })
+    }
+}
+
+/// A `link` or `goto` statement, with the recipe written inline
+/// in place of its target, if there is one.
+type BranchStatement = (BranchDef, Option<InlineRecipe>);
+
+fn branch(input: Input) -> IResult<BranchStatement> {
+    fn link(input: Input) -> IResult<BranchStatement> {
+        fn named(input: Input) -> IResult<BranchStatement> {
+            let (remain, (target, condition)) = pair(ws(spanned(defkey)), opt(ws(condition)))(input)?;
+            let condition = condition.unwrap_or_else(|| ConditionDef::always(None));
+            Ok((remain, (BranchDef::Link { target, condition }, None)))
+        }
+        fn inline(input: Input) -> IResult<BranchStatement> {
+            let (remain, (verb, condition, body)) =
+                tuple((opt(ws(inline_verb)), opt(ws(inline_condition)), inline_body))(input)?;
+            let condition = condition.unwrap_or_else(|| ConditionDef::always(None));
+            let (target, inline) = InlineRecipe::new(verb, body);
+            Ok((remain, (BranchDef::Link { target, condition }, Some(inline))))
+        }
+        preceded(ws(keyword("link")), cut(alt((inline, named))))(input)
+    }
+    fn goto(input: Input) -> IResult<BranchStatement> {
+        fn named(input: Input) -> IResult<BranchStatement> {
+            let (remain, (target, condition, action)) =
+                tuple((ws(spanned(defkey)), opt(ws(condition)), opt(ws(spawning))))(input)?;
+            let condition = condition.unwrap_or_else(|| ConditionDef::always(None));
+            Ok((remain, (BranchDef::Goto { target, condition, action }, None)))
+        }
+        fn inline(input: Input) -> IResult<BranchStatement> {
+            let (remain, (verb, condition, action, body)) =
+                tuple((opt(ws(inline_verb)), opt(ws(inline_condition)), opt(ws(spawning)), inline_body))(input)?;
+            let condition = condition.unwrap_or_else(|| ConditionDef::always(None));
+            let (target, inline) = InlineRecipe::new(verb, body);
+            Ok((remain, (BranchDef::Goto { target, condition, action }, Some(inline))))
+        }
+        preceded(ws(keyword("goto")), cut(alt((inline, named))))(input)
+    }
+
+    alt((link, goto))(input)
+}
+
+/// Parses a branch in a `roll` block, which can't have
+/// a condition of its own, as its chance is worked out
+/// from the weights.
+fn rolled_branch(input: Input) -> IResult<BranchStatement> {
+    fn link(input: Input) -> IResult<BranchStatement> {
+        let (remain, (_, target)) = pair(
+            ws(keyword("link")),
+            cut(alt((
+                map(pair(opt(ws(inline_verb)), inline_body), Either::Right),
+                map(ws(spanned(defkey)), Either::Left),
+            ))),
+        )(input)?;
+        let condition = ConditionDef::always(None);
+        Ok((remain, match target {
+            Either::Left(target) => (BranchDef::Link { target, condition }, None),
+            Either::Right((verb, body)) => {
+                let (target, inline) = InlineRecipe::new(verb, body);
+                (BranchDef::Link { target, condition }, Some(inline))
+            }
+        }))
+    }
+    fn goto(input: Input) -> IResult<BranchStatement> {
+        let (remain, (_, target)) = pair(
+            ws(keyword("goto")),
+            cut(alt((
+                map(tuple((opt(ws(inline_verb)), opt(ws(spawning)), inline_body)), Either::Right),
+                map(pair(ws(spanned(defkey)), opt(ws(spawning))), Either::Left),
+            ))),
+        )(input)?;
+        let condition = ConditionDef::always(None);
+        Ok((remain, match target {
+            Either::Left((target, action)) => (BranchDef::Goto { target, condition, action }, None),
+            Either::Right((verb, action, body)) => {
+                let (target, inline) = InlineRecipe::new(verb, body);
+                (BranchDef::Goto { target, condition, action }, Some(inline))
+            }
+        }))
+    }
+
+    alt((link, goto))(input)
+}
+
+/// The verb an inline recipe runs in: either one declared
+/// elsewhere, or a temporary one written in place.
+type InlineVerb = Either<Spanned<DefKey>, VerbDef>;
+
+/// Parses the `in verb` of an inline recipe which runs in
+/// a different verb to its parent. A spawned recipe may
+/// instead run in a temporary verb of its own, written as
+/// `in verb "label" "description" (slot)`.
+fn inline_verb(input: Input) -> IResult<InlineVerb> {
+    preceded(
+        ws(keyword("in")),
+        cut(alt((
+            map(ws(verb::temporary), Either::Right),
+            map(ws(spanned(defkey)), Either::Left),
+        ))),
+    )(input)
+}
+
+/// Parses the condition of a branch to an inline recipe. Where
+/// the target would be, only `if` or a number can start one, as
+/// a name there is the target of a branch which isn't inline.
+fn inline_condition(input: Input) -> IResult<ConditionDef> {
+    preceded(peek(alt((keyword("if"), digit1))), condition)(input)
+}
+
+/// Parses the body of an inline recipe.
+fn inline_body(input: Input) -> IResult<Spanned<Vec<Spanned<RecipeStatement>>>> {
+    // Only a `{` makes this an inline recipe, so that a branch
+    // naming its target can be tried once this doesn't match.
+    preceded(peek(char('{')), cut(spanned(block(recipe_statement))))(input)
+}
+
+fn condition(input: Input) -> IResult<ConditionDef> {
+    fn conditional(input: Input) -> IResult<ConditionDef> {
+        let (remain, (_, (chance, written))) = pair(
+            ws(keyword("if")),
+            cut(pair(
+                // A `(` starts the requirements rather than the chance.
+                opt(ws(preceded(not(char('(')), chance))),
+                context("a requirement list", ws(requirements)),
+            )),
+        )(input)?;
+        Ok((remain, ConditionDef { chance, written, requirements: Vec::new(), alternatives: Vec::new() }))
+    }
+    fn chance_only(input: Input) -> IResult<ConditionDef> {
+        let (remain, chance) = ws(chance)(input)?;
+        Ok((remain, ConditionDef::always(Some(chance))))
+    }
+
+    alt((conditional, chance_only))(input)
+}
+
+fn spawning(input: Input) -> IResult<SpawningDef> {
+    fn expelled(input: Input) -> IResult<(Spanned<DefKey>, u32)> {
+        let (remain, (key, amount)) = pair(
+            ws(spanned(defkey)),
+            opt(preceded(ws(char(':')), context("an amount", cut(ws(unsigned))))),
+        )(input)?;
+        Ok((remain, (key, amount.unwrap_or(1))))
+    }
+    fn expel(input: Input) -> IResult<SpawningDef> {
+        let (remain, (_, elements)) = pair(
+            ws(keyword("expel")),
+            cut(opt(delimited(
+                ws(char('{')),
+                separated_list0(ws(char(',')), expelled),
+                context("`,` or `}`", list_end('}')),
+            ))),
+        )(input)?;
+        Ok((remain, SpawningDef::Expel(elements.unwrap_or_default())))
+    }
+
+    preceded(
+        ws(tag("->")),
+        context("`spawn` or `expel`", cut(alt((
+            map(ws(keyword("spawn")), |_| SpawningDef::Spawn),
+            ws(expel),
+        )))),
+    )(input)
+}
+
+struct RecipeHeader {
+    id: Spanned<DefKey>,
+    verb: Spanned<DefKey>,
+    craftable: bool,
+    hint_only: bool,
+    written: Vec<Vec<Written>>,
+    max_executions: Option<u32>,
+}
+
+fn recipe_from_tokens(state: &ParseState, header: RecipeHeader, statements: Vec<Spanned<RecipeStatement>>) -> RecipeDef {
+    let RecipeHeader { id, verb, craftable, hint_only, written, max_executions } = header;
+    // Initialize Defaults
+    let mut label = String::new();
+    let mut description = String::new();
+    let mut end_description = String::new();
+    let mut burn: Option<(String, Span)> = None;
+    let mut portal: Option<(String, Span)> = None;
+    let mut warmup: Option<(u32, Span)> = None;
+    let mut slot: Option<(SlotDef, Span)> = None;
+    let mut effects: Vec<(Spanned<DefKey>, ValueOperation)> = Vec::new();
+    let mut purge: Vec<(Spanned<DefKey>, u32)> = Vec::new();
+    let mut draws: Vec<(Spanned<DefKey>, i32)> = Vec::new();
+    let mut internal_deck: Option<((DeckDef, i32), Span)> = None;
+    let mut mutations: Vec<MutationDef> = Vec::new();
+    let mut halt: Vec<(Spanned<DefKey>, u32)> = Vec::new();
+    let mut delete: Vec<(Spanned<DefKey>, u32)> = Vec::new();
+    let mut ending: Option<Spanned<DefKey>> = None;
+    let mut style: Option<(WarmupStyle, Span)> = None;
+    let mut branches: Vec<BranchDef> = Vec::new();
+    let mut others: HashMap<DefKey, json::Value> = HashMap::new();
+    let mut lifter =
+        Lifter { parent: id.item.clone(), verb: verb.clone(), links: 0, gotos: 0, verbs: 0, lifted: Vec::new() };
+    let mut assigned: HashMap<DefKey, Span> = HashMap::new();
+    // The first `draw`, `purge`, etc. statement for each target,
+    // so that repeating one can point back at the first.
+    let mut targeted: HashMap<(&'static str, DefKey), Span> = HashMap::new();
+    let mut first_target = |statement: &'static str, key: &Spanned<DefKey>, span: Span| -> bool {
+        match targeted.get(&(statement, key.item.clone())) {
+            Some(first) => {
+                duplicate_statement(state, &format!("{} {}", statement, key), span, *first, "recipe");
+                false
+            }
+            None => {
+                targeted.insert((statement, key.item.clone()), span);
+                true
+            }
+        }
+    };
+
+    for Spanned { item: st, span } in statements {
+        match st {
+            RecipeStatement::Set(k, v) => {
+                match k.0.as_str() {
+                    "id" | "verb" => reserved_key(state, &k, span, "recipe"),
+                    _ if !first_assignment(state, &mut assigned, &k, span, "recipe") => (),
+                    "label" => label = expect_string(state, &k, v, span).unwrap_or_default(),
+                    "description" => description = expect_string(state, &k, v, span).unwrap_or_default(),
+                    "end_description" => end_description = expect_string(state, &k, v, span).unwrap_or_default(),
+                    _ => { others.insert(k, v); },
+                }
+            },
+            RecipeStatement::Warmup(time) => match warmup {
+                Some((_, first)) => duplicate_statement(state, "warmup", span, first, "recipe"),
+                None => warmup = Some((time, span)),
+            },
+            RecipeStatement::Apply(Some(target), aspect, operation) => {
+                mutations.push(MutationDef { id: target, aspect, operation });
+            },
+            RecipeStatement::Apply(None, element, operation) => {
+                if first_target("apply", &element, span) {
+                    effects.push((element, operation));
+                }
+            },
+            RecipeStatement::Draw(deck, amount) => {
+                if first_target("draw", &deck, span) {
+                    draws.push((deck, amount));
+                }
+            },
+            RecipeStatement::DrawInline(deck, amount) => match internal_deck {
+                Some((_, first)) => duplicate_statement(state, "draw { .. }", span, first, "recipe"),
+                None => internal_deck = Some(((deck.into_def(state, id.clone()), amount), span)),
+            },
+            RecipeStatement::Signal(signal) => match style {
+                Some((_, first)) => duplicate_statement(state, "signal", span, first, "recipe"),
+                None => style = Some((signal, span)),
+            },
+            RecipeStatement::Purge(element, amount) => {
+                if first_target("purge", &element, span) {
+                    purge.push((element, amount));
+                }
+            },
+            RecipeStatement::Burn(image) => match burn {
+                Some((_, first)) => duplicate_statement(state, "burn", span, first, "recipe"),
+                None => burn = Some((image, span)),
+            },
+            RecipeStatement::Portal(door) => match portal {
+                Some((_, first)) => duplicate_statement(state, "portal", span, first, "recipe"),
+                None => portal = Some((door, span)),
+            },
+            RecipeStatement::Ending(key) => match &ending {
+                Some(first) => duplicate_statement(state, "ending", span, first.span, "recipe"),
+                None => ending = Some(key),
+            },
+            // Without a verb, `halt` and `delete` act on the recipe's own verb.
+            RecipeStatement::Halt(target, amount) => {
+                let target = target.unwrap_or_else(|| verb.clone());
+                if first_target("halt", &target, span) {
+                    halt.push((target, amount));
+                }
+            },
+            RecipeStatement::Delete(target, amount) => {
+                let target = target.unwrap_or_else(|| verb.clone());
+                if first_target("delete", &target, span) {
+                    delete.push((target, amount));
+                }
+            },
+            RecipeStatement::Branch(mut branch, inline) => {
+                if let Some(inline) = inline {
+                    lifter.lift(state, &mut branch, inline);
+                }
+                branches.push(branch);
+            },
+            RecipeStatement::Roll(outcomes) => {
+                let outcomes = outcomes
+                    .into_iter()
+                    .map(|Spanned { item: (weight, mut branch, inline), span }| {
+                        if let Some(inline) = inline {
+                            lifter.lift(state, &mut branch, inline);
+                        }
+                        Spanned::new((weight, branch), span)
+                    })
+                    .collect();
+                branches.extend(roll(state, outcomes, span));
+            },
+            RecipeStatement::Slot(slotdef) => match slot {
+                Some((_, first)) => duplicate_statement(state, "slot", span, first, "recipe"),
+                None => slot = Some((slotdef, span)),
+            },
+        };
+    }
+
+    RecipeDef {
+        id,
+        verb,
+        label,
+        description,
+        end_description,
+        burn: burn.map(|(image, _)| image),
+        portal: portal.map(|(door, _)| door),
+        written,
+        requirements: Vec::new(),
+        alternatives: Vec::new(),
+        max_executions: max_executions.unwrap_or(0),
+        warmup: warmup.map_or(0, |(time, _)| time),
+        craftable,
+        hint_only,
+        stage: false,
+        slot: slot.map(|(slot, _)| slot),
+        effects,
+        purge,
+        draws,
+        internal_deck: internal_deck.map(|(deck, _)| deck),
+        mutations,
+        halt,
+        delete,
+        ending,
+        style: style.map_or(WarmupStyle::None, |(style, _)| style),
+        branches,
+        others,
+        lifted: lifter.lifted,
+    }
+}
+
+/// Gives the recipes and temporary verbs written inline in a
+/// recipe's branches ids of their own, such as `parent.__link1`,
+/// numbered in the order they are written so that they are the
+/// same each time.
+struct Lifter {
+    parent: DefKey,
+    verb: Spanned<DefKey>,
+    links: u32,
+    gotos: u32,
+    verbs: u32,
+    lifted: Vec<Lifted>,
+}
+
+impl Lifter {
+    /// Parses `inline` into a recipe of its own, pointing `branch` at it.
+    fn lift(&mut self, state: &ParseState, branch: &mut BranchDef, inline: InlineRecipe) {
+        let spawns = matches!(branch, BranchDef::Goto { action: Some(_), .. });
+        let (target, count, kind) = match branch {
+            BranchDef::Link { target, .. } => (target, &mut self.links, "link"),
+            BranchDef::Goto { target, .. } => (target, &mut self.gotos, "goto"),
+        };
+        *count += 1;
+        target.item = DefKey(format!("{}.__{}{}", self.parent, kind, count));
+        let verb = match inline.verb {
+            None => self.verb.clone(),
+            Some(Either::Left(verb)) => verb,
+            Some(Either::Right(mut verb)) => {
+                // Only a spawned token runs in a verb of its own; a
+                // recipe reached any other way stays in its parent's.
+                if !spawns {
+                    let message = "a temporary verb is only used by a recipe that is spawned";
+                    state.report(
+                        Diagnostic::error(Code::UnspawnedTemporaryVerb, verb.id.span, message)
+                            .with_note("only a `goto` with `-> spawn` or `-> expel` starts a token of its own"),
+                    );
+                }
+                self.verbs += 1;
+                verb.id.item = DefKey(format!("{}.__verb{}", self.parent, self.verbs));
+                let id = verb.id.clone();
+                let span = verb.id.span;
+                self.lifted.push(Lifted { component: Component::Verb(Box::new(verb)), inherits: None, span });
+                id
+            }
+        };
+        let header = RecipeHeader {
+            id: target.clone(),
+            verb,
+            craftable: false,
+            hint_only: false,
+            written: Vec::new(),
+            max_executions: None,
+        };
+        let mut recipe = recipe_from_tokens(state, header, inline.statements);
+        // Recipes inline in this one come out after it.
+        let nested = std::mem::take(&mut recipe.lifted);
+        self.lifted.push(Lifted { component: Component::Recipe(Box::new(recipe)), inherits: None, span: inline.span });
+        self.lifted.extend(nested);
+    }
+}
+
+/// Takes the recipes and temporary verbs written inline in `unit`,
+/// and its later stages, if it is a recipe, as units of their own
+/// to go beside it. They share its `#[cfg]` and lint attributes.
+pub fn lifted(unit: &mut Unit) -> Vec<Unit> {
+    let (recipe, cfg, lints) = match unit {
+        Unit::Component { component: Component::Recipe(recipe), cfg, lints, .. } => (recipe, cfg, lints),
+        _ => return Vec::new(),
+    };
+    std::mem::take(&mut recipe.lifted)
+        .into_iter()
+        .map(|Lifted { component, inherits, span }| Unit::Component {
+            id: component.id(),
+            doc: None,
+            attrs: Vec::new(),
+            cfg: cfg.clone(),
+            lints: lints.clone(),
+            component,
+            inherits,
+            span,
+        })
+        .collect()
+}
+
+/// The most outcomes a `roll` may have for other orders of
+/// them to be tried, to suggest one when the written one
+/// can't be rolled exactly.
+const MAX_REORDERED: usize = 8;
+
+/// Turns the weighted outcomes of a `roll` into branches.
+///
+/// The game tries branches in order, rolling each one's chance
+/// only if those before it weren't taken, so each chance is its
+/// weight out of the weights of itself and those after it. The
+/// outcomes are kept in the order they were written, as which
+/// is tried first matters once their targets have requirements,
+/// so chances that aren't whole percentages are rounded.
+fn roll(state: &ParseState, outcomes: Vec<Spanned<(u32, BranchDef)>>, span: Span) -> Vec<BranchDef> {
+    let is_link = |outcome: &Spanned<(u32, BranchDef)>| matches!(outcome.item.1, BranchDef::Link { .. });
+    if let Some(first) = outcomes.first() {
+        if let Some(mixed) = outcomes.iter().find(|o| is_link(o) != is_link(first)) {
+            let (this, that) = if is_link(first) { ("goto", "link") } else { ("link", "goto") };
+            state.report(
+                Diagnostic::error(Code::MixedRoll, mixed.span, format!("a `roll` of `{}`s can't also `{}`", that, this))
+                    .with_label(first.span, format!("this makes it a `roll` of `{}`s", that))
+                    .with_note("`goto`s are rolled when the recipe starts, and `link`s when it ends"),
+            );
+            return Vec::new();
+        }
+    }
+
+    let weights: Vec<u64> = outcomes.iter().map(|o| u64::from(o.item.0)).collect();
+    let chances = chances(&weights);
+    let written: Vec<usize> = (0..weights.len()).collect();
+    if !is_exact(&weights, &written) {
+        let total: u64 = weights.iter().sum();
+        let percent = |p: f64| format!("{}%", (p * 10000.0).round() / 100.0);
+        let stated: Vec<String> = weights.iter().map(|&w| percent(w as f64 / total as f64)).collect();
+        let rolled: Vec<String> = taken(&chances).into_iter().map(percent).collect();
+        let mut diagnostic =
+            Diagnostic::warning(Code::InexactRoll, span, "these weights can't be rolled exactly with whole percentages")
+                .with_note(format!(
+                    "the outcomes are taken {} of the time, rather than {}",
+                    rolled.join(", "),
+                    stated.join(", "),
+                ));
+        let mut order = Vec::new();
+        if weights.len() <= MAX_REORDERED && find_exact(&weights, &mut order) {
+            let reordered: Vec<String> = order.iter().map(|&i| weights[i].to_string()).collect();
+            diagnostic = diagnostic.with_note(format!(
+                "written with the weights in the order {}, they can be rolled exactly",
+                reordered.join(", "),
+            ));
+        }
+        state.report(diagnostic);
+    }
+
+    outcomes
+        .into_iter()
+        .zip(chances)
+        .map(|(outcome, chance)| {
+            let mut branch = outcome.item.1;
+            let condition = match &mut branch {
+                BranchDef::Link { condition, .. } | BranchDef::Goto { condition, .. } => condition,
+            };
+            condition.chance = Probability::new(chance).ok();
+            branch
+        })
+        .collect()
+}
+
+/// The chance of each outcome with these weights, tried in order,
+/// as the nearest whole percentage.
+fn chances(weights: &[u64]) -> Vec<u8> {
+    let mut remaining: u64 = weights.iter().sum();
+    weights
+        .iter()
+        .map(|&weight| {
+            // Once only weights of 0 are left, none of them are ever taken.
+            let chance = match remaining {
+                0 => 0,
+                _ => (weight * 200 + remaining) / (remaining * 2),
+            };
+            remaining -= weight;
+            chance as u8
+        })
+        .collect()
+}
+
+/// How often each outcome is taken, if each is
+/// tried in order with these chances.
+fn taken(chances: &[u8]) -> Vec<f64> {
+    let mut left = 1.0;
+    chances
+        .iter()
+        .map(|&chance| {
+            let taken = left * f64::from(chance) / 100.0;
+            left -= taken;
+            taken
+        })
+        .collect()
+}
+
+/// Whether every chance is a whole percentage when the
+/// outcomes with these weights are tried in this order.
+fn is_exact(weights: &[u64], order: &[usize]) -> bool {
+    let mut remaining: u64 = order.iter().map(|&i| weights[i]).sum();
+    order.iter().all(|&i| {
+        let fits = remaining == 0 || weights[i] * 100 % remaining == 0;
+        remaining -= weights[i];
+        fits
+    })
+}
+
+/// Searches for an order, starting with `order`, in which
+/// every chance is a whole percentage, leaving it in `order`.
+fn find_exact(weights: &[u64], order: &mut Vec<usize>) -> bool {
+    if order.len() == weights.len() {
+        return true;
+    }
+    let remaining: u64 = (0..weights.len()).filter(|i| !order.contains(i)).map(|i| weights[i]).sum();
+    for i in 0..weights.len() {
+        if order.contains(&i) || (remaining != 0 && weights[i] * 100 % remaining != 0) {
+            continue;
+        }
+        order.push(i);
+        if find_exact(weights, order) {
+            return true;
+        }
+        order.pop();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses `text` as a recipe, returning it, with whatever
+    /// is to be lifted out of it, and whatever was reported.
+    fn parsed(text: &str) -> (RecipeDef, Vec<Diagnostic>) {
+        let state = ParseState::new(0, PathBuf::from("test.crucible"), Rc::from(text), Definitions::default());
+        let recipe = match parse(Input::new_extra(text, &state)) {
+            Ok((_, Component::Recipe(recipe))) => *recipe,
+            _ => panic!("not a recipe: {}", text),
+        };
+        let (diagnostics, _, _, _) = state.finish();
+        (recipe, diagnostics)
+    }
+
+    /// The recipe `text`, which must not report anything.
+    fn recipe(text: &str) -> RecipeDef {
+        let (recipe, diagnostics) = parsed(text);
+        let codes: Vec<Code> = diagnostics.into_iter().map(|d| d.code).collect();
+        assert_eq!(codes, [], "reported for {}", text);
+        recipe
+    }
+
+    fn lifted_ids(recipe: &RecipeDef) -> Vec<String> {
+        recipe.lifted.iter().map(|lifted| lifted.component.id().item.0).collect()
+    }
+
+    fn targets(recipe: &RecipeDef) -> Vec<String> {
+        let target = |branch: &BranchDef| match branch {
+            BranchDef::Link { target, .. } | BranchDef::Goto { target, .. } => target.item.0.clone(),
+        };
+        recipe.branches.iter().map(target).collect()
+    }
+
+    fn lifted_recipe(recipe: &RecipeDef, id: &str) -> RecipeDef {
+        let found = recipe.lifted.iter().find_map(|lifted| match &lifted.component {
+            Component::Recipe(lifted) if lifted.id.item.0 == id => Some(lifted.as_ref().clone()),
+            _ => None,
+        });
+        found.unwrap_or_else(|| panic!("`{}` was not lifted", id))
+    }
+
+    #[test]
+    fn inline_recipes_are_numbered_in_order() {
+        let parent = recipe(
+            ".dream recipe r() {
+                link { warmup 1; };
+                goto -> spawn { warmup 2; };
+                link {
+                    warmup 3;
+                    link { warmup 4; };
+                };
+                goto in verb \"Vigil\" \"\" -> spawn { warmup 5; };
+            }",
+        );
+        assert_eq!(targets(&parent), ["r.__link1", "r.__goto1", "r.__link2", "r.__goto2"]);
+        // Recipes inline in an inline recipe are numbered within it,
+        // and come out after it.
+        assert_eq!(
+            lifted_ids(&parent),
+            ["r.__link1", "r.__goto1", "r.__link2", "r.__link2.__link1", "r.__verb1", "r.__goto2"]
+        );
+        assert_eq!(targets(&lifted_recipe(&parent, "r.__link2")), ["r.__link2.__link1"]);
+        assert_eq!(lifted_recipe(&parent, "r.__link1").verb.item.0, ".dream");
+        assert_eq!(lifted_recipe(&parent, "r.__goto2").verb.item.0, "r.__verb1");
+    }
+
+    #[test]
+    fn temporary_verbs_are_only_for_spawned_recipes() {
+        let (parent, diagnostics) = parsed(".dream recipe r() { link in verb \"Vigil\" \"\" { warmup 5; }; }");
+        let codes: Vec<Code> = diagnostics.into_iter().map(|d| d.code).collect();
+        assert_eq!(codes, [Code::UnspawnedTemporaryVerb]);
+        assert_eq!(lifted_ids(&parent), ["r.__verb1", "r.__link1"]);
+    }
+
+    #[test]
+    fn stages_link_on_to_the_next() {
+        let first = recipe(".dream recipe r(a:1) { warmup 1; link other; } { warmup 2; } from p { warmup 3; }");
+        assert_eq!(lifted_ids(&first), ["r.__stage2", "r.__stage3"]);
+        // The next stage comes after everything else the stage does.
+        assert_eq!(targets(&first), ["other", "r.__stage2"]);
+        assert!(!first.stage);
+
+        let second = lifted_recipe(&first, "r.__stage2");
+        assert_eq!(targets(&second), ["r.__stage3"]);
+        let third = lifted_recipe(&first, "r.__stage3");
+        assert_eq!(targets(&third), Vec::<String>::new());
+        for stage in [&second, &third] {
+            assert!(stage.stage && !stage.craftable && stage.written.is_empty());
+            assert_eq!(stage.verb.item.0, ".dream");
+        }
+        let inherits: Vec<Option<String>> =
+            first.lifted.iter().map(|lifted| lifted.inherits.as_ref().map(|p| p.item.0.clone())).collect();
+        assert_eq!(inherits, [None, Some("p".to_owned())]);
+    }
+
+    #[test]
+    fn inline_decks() {
+        let reset = DefKey("resetonexhaustion".to_owned());
+        let drawn = |text: &str| recipe(text).internal_deck.expect("an inline deck");
+
+        let (deck, amount) = drawn(".dream recipe r() { draw { !a * 3, b } 2; }");
+        assert_eq!(amount, 2);
+        assert_eq!(deck.id.item.0, "r");
+        assert_eq!(deck.default.map(|card| card.item.0), Some("a".to_owned()));
+        let cards: Vec<(String, u32)> = deck.cards.iter().map(|(card, _, n)| (card.item.0.clone(), *n)).collect();
+        assert_eq!(cards, [("a".to_owned(), 3), ("b".to_owned(), 1)]);
+        assert!(!deck.others.contains_key(&reset));
+
+        // A deck drawn `once` isn't shuffled back together,
+        // unless it has a default card to draw instead.
+        let (deck, _) = drawn(".dream recipe r() { draw once { a, b } 1; }");
+        assert!(matches!(deck.others.get(&reset), Some(json::Value::Boolean(false))));
+        let (deck, _) = drawn(".dream recipe r() { draw once { !a, b } 1; }");
+        assert!(!deck.others.contains_key(&reset));
+    }
+
+    #[test]
+    fn a_roll_may_not_mix_links_and_gotos() {
+        let (parent, diagnostics) = parsed(".dream recipe r() { roll { 50: link a; 50: goto b; }; }");
+        let codes: Vec<Code> = diagnostics.iter().map(|d| d.code).collect();
+        assert_eq!(codes, [Code::MixedRoll]);
+        assert_eq!(diagnostics[0].message, "a `roll` of `link`s can't also `goto`");
+        assert_eq!(targets(&parent), Vec::<String>::new());
+    }
+
+    #[test]
+    fn inexact_rolls_suggest_an_order() {
+        let (parent, diagnostics) = parsed(".dream recipe r() { roll { 30: link a; 50: link b; 20: link c; }; }");
+        let codes: Vec<Code> = diagnostics.iter().map(|d| d.code).collect();
+        assert_eq!(codes, [Code::InexactRoll]);
+        assert_eq!(
+            diagnostics[0].notes,
+            [
+                "the outcomes are taken 30%, 49.7%, 20.3% of the time, rather than 30%, 50%, 20%",
+                "written with the weights in the order 50, 30, 20, they can be rolled exactly",
+            ]
+        );
+        // The outcomes are still tried in the order written.
+        assert_eq!(targets(&parent), ["a", "b", "c"]);
+
+        // No order of thirds is exact, so none is suggested.
+        let (_, diagnostics) = parsed(".dream recipe r() { roll { 1: link a; 1: link b; 1: link c; }; }");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].notes.len(), 1);
+
+        let exact = recipe(".dream recipe r() { roll { 50: link a; 30: link b; 20: link c; }; }");
+        assert_eq!(targets(&exact), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn chances_are_out_of_the_weights_left() {
+        assert_eq!(chances(&[50, 30, 20]), [50, 60, 100]);
+        assert_eq!(chances(&[1, 1, 1, 1]), [25, 33, 50, 100]);
+        assert_eq!(chances(&[7]), [100]);
+        // Weights of 0 are never taken, even when they are all that's left.
+        assert_eq!(chances(&[0, 3, 0]), [0, 100, 0]);
+        assert_eq!(chances(&[0, 0]), [0, 0]);
+    }
+
+    #[test]
+    fn chances_are_rounded_to_the_nearest_percent() {
+        // 50 out of 70 is 71.4%, and 1 out of 3 is 33.3%.
+        assert_eq!(chances(&[30, 50, 20]), [30, 71, 100]);
+        assert_eq!(chances(&[1, 2]), [33, 100]);
+        // 1 out of 8 is 12.5%, which rounds up.
+        assert_eq!(chances(&[1, 7]), [13, 100]);
+    }
+
+    #[test]
+    fn how_often_each_is_taken() {
+        let taken = taken(&[30, 71, 100]);
+        let expected = [0.3, 0.497, 0.203];
+        assert!(taken.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-9), "{:?}", taken);
+    }
+
+    #[test]
+    fn exact_orders() {
+        assert!(is_exact(&[50, 30, 20], &[0, 1, 2]));
+        assert!(!is_exact(&[30, 50, 20], &[0, 1, 2]));
+        assert!(is_exact(&[30, 50, 20], &[1, 0, 2]));
+        assert!(is_exact(&[0, 5], &[1, 0]));
+        let mut order = Vec::new();
+        assert!(find_exact(&[30, 50, 20], &mut order));
+        assert!(is_exact(&[30, 50, 20], &order));
+        // A third each is never a whole percentage, in any order.
+        assert!(!find_exact(&[1, 1, 1], &mut Vec::new()));
+    }
+}
@@ -0,0 +1,159 @@
+//! Error recovery at `Unit` boundaries.
+//!
+//! When a namespace or component fails to parse, the parser
+//! reports the error and then uses [skip_unit] to find the
+//! place where the next unit is most likely to begin, so that
+//! the rest of the file can still be checked in the same pass.
+//! It also uses [declared_id] to remember what the unit would have
+//! declared, so that references to it aren't reported as well.
+
+use super::is_defkey_char;
+
+/// The words that may begin a unit. Finding one of these outside
+/// of any braces is a good sign that a new unit starts there.
+const UNIT_KEYWORDS: &[&str] = &[
+    "namespace", "use", "const", "template", "for", "aspect", "card", "deck", "recipe", "verb",
+    "legacy", "ending", "hidden", "from",
+];
+
+/// Given the text of a unit that failed to parse, and the offset
+/// within it at which parsing failed, returns how many bytes to
+/// skip before trying to parse the next unit.
+///
+/// Scanning stops at whichever comes first:
+/// * the `}` which balances the first `{` of the unit,
+/// * a `}` which would close the enclosing namespace,
+/// * a unit keyword, `#[`, template instantiation or doc comment outside of
+///   any braces, past the error. A recipe starts at its verb and markers,
+///   before the keyword `recipe`,
+/// * the end of the text.
+///
+/// Braces inside of string literals and comments are ignored. The returned
+/// count is never zero, so that the caller always makes progress.
+pub fn skip_unit(text: &str, error: usize) -> usize {
+    let bytes = text.as_bytes();
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                let doc = text[i..].starts_with("///") && !text[i..].starts_with("////");
+                if doc && depth == 0 && i > error {
+                    return i;
+                }
+                i += text[i..].find('\n').unwrap_or(text.len() - i);
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i += text[i + 2..].find("*/").map_or(text.len() - i, |end| end + 3);
+            }
+            b'{' => depth += 1,
+            b'}' if depth == 0 => return i.max(1),
+            b'}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            _ if depth == 0 && i > error && text.is_char_boundary(i) && starts_unit(text, i) => {
+                let start = header_start(text, i);
+                return if start > 0 { start } else { i };
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+    text.len().max(1)
+}
+
+/// Whether a unit keyword (or an attribute, or a `name!(`
+/// instantiation of a template) begins at byte `at`.
+fn starts_unit(text: &str, at: usize) -> bool {
+    let rest = &text[at..];
+    if rest.starts_with("#[") {
+        return true;
+    }
+    if text[..at].chars().next_back().map_or(false, is_defkey_char) {
+        return false;
+    }
+    let name = rest.find(|c| !is_defkey_char(c)).unwrap_or(rest.len());
+    if name > 0 && rest[name..].starts_with('!') && rest[name + 1..].trim_start().starts_with('(') {
+        return true;
+    }
+    UNIT_KEYWORDS.iter().any(|kw| is_keyword(rest, kw))
+}
+
+/// Whether `text` begins with the keyword `kw`.
+fn is_keyword(text: &str, kw: &str) -> bool {
+    text.len() >= kw.len()
+        && text.is_char_boundary(kw.len())
+        && text[..kw.len()].eq_ignore_ascii_case(kw)
+        && !text[kw.len()..].chars().next().map_or(false, is_defkey_char)
+}
+
+/// Where the unit whose keyword is at byte `at` begins. That is
+/// the keyword itself, but for a recipe, whose header starts with
+/// its verb, as in `craft .dream recipe`.
+fn header_start(text: &str, at: usize) -> usize {
+    if !is_keyword(&text[at..], "recipe") {
+        return at;
+    }
+    let before = text[..at].trim_end();
+    let verb = before.trim_end_matches(is_defkey_char);
+    if verb.len() == before.len() {
+        return at;
+    }
+    let mut start = verb;
+    loop {
+        let rest = start.trim_end();
+        let marker = ["!", "?", "craft", "hint"].iter().find_map(|m| {
+            let word = rest.strip_suffix(m)?;
+            let whole = m.len() == 1 || !word.ends_with(is_defkey_char);
+            whole.then_some(word)
+        });
+        match marker {
+            Some(word) => start = word,
+            None => return start.len(),
+        }
+    }
+}
+
+/// The id declared by the unit which begins `text`, as far as it can
+/// be made out from the words before its first bracket or string.
+///
+/// Used on the text of a unit that failed to parse, so that it can be
+/// told apart from one that was never declared.
+pub fn declared_id(text: &str) -> Option<&str> {
+    const COMPONENTS: &[&str] = &["aspect", "card", "deck", "recipe", "verb", "legacy", "ending"];
+    let mut words = Vec::new();
+    let mut rest = text;
+    loop {
+        rest = rest.trim_start();
+        if rest.starts_with("//") {
+            rest = rest.find('\n').map_or("", |end| &rest[end..]);
+        } else if rest.starts_with("/*") {
+            rest = rest.find("*/").map_or("", |end| &rest[end + 2..]);
+        } else if rest.starts_with("#[") {
+            rest = rest.find(']').map_or("", |end| &rest[end + 1..]);
+        } else if rest.starts_with(['!', '?']) {
+            rest = &rest[1..];
+        } else {
+            let len = rest.find(|c| !is_defkey_char(c)).unwrap_or(rest.len());
+            if len == 0 {
+                break;
+            }
+            words.push(&rest[..len]);
+            rest = &rest[len..];
+        }
+    }
+    let component = |word: &&str| COMPONENTS.iter().any(|kw| word.eq_ignore_ascii_case(kw));
+    // The verb of a recipe may itself be named like a keyword,
+    // so the id follows the last of the keywords in a row.
+    let mut words = words.into_iter().skip_while(|word| !component(word)).skip_while(component);
+    words.next()
+}
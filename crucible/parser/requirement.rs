@@ -0,0 +1,709 @@
+//! Requirement lists, as in a recipe's header or a branch's `if`.
+//!
+//! The game keeps a single amount for each element a recipe requires,
+//! where a positive amount means "at least" and a negative one means
+//! "fewer than", or the id of another element the amount must be at
+//! least as high as. Comparisons can be written instead:
+//!
+//! ```text
+//! dream recipe study(lantern >= 5, moth < 3, edge == 0, heart >= winter) {}
+//! ```
+//!
+//! `element:n` is the game's own encoding, and a bare `element` is
+//! `element >= 1`. Every requirement on the same element, of the same
+//! kind, is combined into one. Requirements which can never all hold,
+//! or which would need more than one amount to check, are errors.
+//! As an element can be named in more than one way, through a `use`
+//! alias or a relative id, lists are only [combine]d once they have
+//! been resolved, by [crate::combine].
+//!
+//! The game needs all of a recipe's requirements to hold, but a list
+//! may also join requirements with `or`, and group them in parentheses:
+//!
+//! ```text
+//! dream recipe study(lantern >= 5 or (moth >= 3, edge == 0)) {}
+//! ```
+//!
+//! `or` binds tighter than `,`, so `a or b, c` means `(a or b), c`.
+//! Such a list is split into every combination of requirements which
+//! meets it, and [crate::lower] gives the recipe or branch one variant
+//! for each, in the order they were written. A combination which can
+//! never hold is left out with a warning, and is only an error if no
+//! combination can hold at all.
+//!
+//! Only one of the ways should ever be taken, as a branch split into
+//! several must not fire more than once, or roll its chance again for
+//! each. So each way also requires that none before it holds, and
+//! `lantern >= 5 or moth >= 3` is split into `lantern >= 5`, and
+//! `moth >= 3, lantern < 5`. A comparison with another element can't
+//! be turned around like this, so it has to be in the last way.
+
+use super::*;
+use crate::diagnostic::Severity;
+
+/// The most ways a requirement list may be met, so that a
+/// list of many `or`s can't split a recipe into thousands.
+const MAX_ALTERNATIVES: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequirementKind {
+    Basic,
+    Table,
+    Extant,
+}
+
+impl RequirementKind {
+    fn prefix(self) -> &'static str {
+        match self {
+            RequirementKind::Basic => "",
+            RequirementKind::Table => "table ",
+            RequirementKind::Extant => "extant ",
+        }
+    }
+}
+
+/// What a requirement compares its element's amount with.
+#[derive(Debug, Clone)]
+pub enum Threshold {
+    /// At least `n` if positive, or fewer than `-n` if negative.
+    Amount(i32),
+    /// At least as many as another element.
+    Element(Spanned<DefKey>),
+}
+
+/// A single requirement, such as `lantern >= 5` or `table moth:-1`.
+#[derive(Debug, Clone)]
+pub struct RequirementDef {
+    pub kind: RequirementKind,
+    pub element: Spanned<DefKey>,
+    pub threshold: Threshold,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    AtLeast,
+    Above,
+    AtMost,
+    Below,
+    Equal,
+}
+
+/// What one requirement, as written, allows of its element.
+#[derive(Debug, Clone)]
+enum Bound {
+    /// From `min` up to just below `below`, if there is a limit.
+    Range { min: i64, below: Option<i64> },
+    Element(Spanned<DefKey>),
+}
+
+/// A requirement as written, before it is combined with
+/// any others on the same element.
+#[derive(Debug, Clone)]
+pub struct Written {
+    kind: RequirementKind,
+    element: Spanned<DefKey>,
+    bound: Bound,
+    /// The amount given with `:`, which is kept as it is
+    /// if nothing else constrains the element.
+    raw: Option<i32>,
+    span: Span,
+}
+
+impl Written {
+    /// The kind of requirement and the element it is on. Those
+    /// of a child replace any of its parent's with the same key.
+    pub fn key(&self) -> (RequirementKind, DefKey) {
+        (self.kind, self.element.item.clone())
+    }
+
+    /// The element required, and the one it is compared with, if any.
+    pub fn references_mut(&mut self) -> Vec<(Expected, &mut Spanned<DefKey>)> {
+        let mut refs = vec![(Expected::Element, &mut self.element)];
+        if let Bound::Element(other) = &mut self.bound {
+            refs.push((Expected::Element, other));
+        }
+        refs
+    }
+}
+
+/// Parses a parenthesized, comma separated list of requirements,
+/// as every way it can be met. Each has yet to be [combine]d, as
+/// only once they are resolved is it known which requirements are
+/// on the same element.
+pub fn requirements(input: Input) -> IResult<Vec<Vec<Written>>> {
+    list(input)
+}
+
+/// Combines the requirements of each way of meeting a list into
+/// those the game checks, reporting any problems to `diagnostics`.
+/// The first way is returned apart from the others, which only
+/// exist if the list held an `or`.
+pub fn combine(ways: &[Vec<Written>], diagnostics: &mut Diagnostics) -> (Vec<RequirementDef>, Vec<Vec<RequirementDef>>) {
+    let ways: Vec<_> = ways.iter().cloned().map(combine_way).collect();
+    // A way which can never hold is only an error if it is the only
+    // kind there is. Anything else that was found is an error either way.
+    let possible = ways.iter().any(Result::is_ok);
+    // The same problem is found in every way that shares the
+    // requirements responsible for it, but is only reported once.
+    let mut problems: Vec<&Diagnostic> = Vec::new();
+    for problem in ways.iter().filter_map(|way| way.as_ref().err()).flatten() {
+        if !problems.iter().any(|p| p.repeats(problem)) {
+            problems.push(problem);
+        }
+    }
+    for problem in problems {
+        match problem.code {
+            Code::UnsatisfiableRequirement if possible => diagnostics.push(
+                Diagnostic {
+                    severity: Severity::Warning,
+                    code: Code::UnsatisfiableAlternative,
+                    ..problem.clone()
+                }
+                .with_note("this way of meeting the requirements is left out"),
+            ),
+            _ => diagnostics.push(problem.clone()),
+        }
+    }
+    let ways = ways.into_iter().filter_map(Result::ok).collect();
+    let mut ways = exclusive(ways, diagnostics).into_iter();
+    let first = ways.next().unwrap_or_default();
+    (first, ways.collect())
+}
+
+/// Adds to each way of meeting a list that the ways before it don't
+/// hold, splitting it further if it has to. Ways which then can never
+/// hold are left out, as they were covered by those before them.
+fn exclusive(ways: Vec<Vec<RequirementDef>>, diagnostics: &mut Diagnostics) -> Vec<Vec<RequirementDef>> {
+    let why = "only one way of meeting an `or` is taken, so each requires that those before it don't hold";
+    let mut exclusive: Vec<Vec<RequirementDef>> = Vec::new();
+    for (n, way) in ways.iter().enumerate() {
+        let mut split = vec![way.clone()];
+        for earlier in &ways[..n] {
+            // The ways to not meet `earlier`, each leaving out the
+            // ones before it: not `a`, or `a` and not `b`, and so on.
+            let mut unmet = Vec::new();
+            for (i, requirement) in earlier.iter().enumerate() {
+                let opposite = match requirement.threshold {
+                    // Always met, so there's no way to not meet it.
+                    Threshold::Amount(0) => continue,
+                    Threshold::Amount(n) => Threshold::Amount(n.saturating_neg()),
+                    Threshold::Element(_) => {
+                        diagnostics.push(
+                            Diagnostic::error(
+                                Code::UnsupportedRequirement,
+                                requirement.element.span,
+                                format!(
+                                    "`{}` is compared with another element, so the ways after this can't leave it out",
+                                    requirement.element.0,
+                                ),
+                            )
+                            .with_note(why)
+                            .with_note("the game can't check that an element is fewer than another; write this way last"),
+                        );
+                        return Vec::new();
+                    }
+                };
+                let mut unmet_here = earlier[..i].to_vec();
+                unmet_here.push(RequirementDef { threshold: opposite, ..requirement.clone() });
+                unmet.push(unmet_here);
+            }
+            let mut next = Vec::new();
+            for way in split {
+                if let Conjoined::Never = conjoin(&way, earlier) {
+                    // The two can never both hold already.
+                    next.push(way);
+                    continue;
+                }
+                for unmet in &unmet {
+                    match conjoin(&way, unmet) {
+                        Conjoined::Holds(way) => next.push(way),
+                        Conjoined::Never => (),
+                        Conjoined::Unchecked(span, what) => {
+                            diagnostics.push(
+                                Diagnostic::error(
+                                    Code::UnsupportedRequirement,
+                                    span,
+                                    format!("the game can't check that {}, which this way needs to leave out others", what),
+                                )
+                                .with_note(why)
+                                .with_note("try writing the ways in another order"),
+                            );
+                            return Vec::new();
+                        }
+                    }
+                }
+            }
+            split = next;
+            if exclusive.len() + split.len() > MAX_ALTERNATIVES {
+                let span = way.first().map_or_else(Span::default, |r| r.element.span);
+                diagnostics.push(
+                    Diagnostic::error(
+                        Code::UnsupportedRequirement,
+                        span,
+                        "these requirements can be met in too many ways once each leaves out those before it",
+                    )
+                    .with_note(format!("a requirement list may be met in at most {} ways", MAX_ALTERNATIVES)),
+                );
+                return Vec::new();
+            }
+        }
+        exclusive.extend(split);
+    }
+    exclusive
+}
+
+/// Two sets of requirements, required together.
+enum Conjoined {
+    Holds(Vec<RequirementDef>),
+    /// They can never both hold.
+    Never,
+    /// They would need a requirement the game can't check,
+    /// which is described, on the element at the span.
+    Unchecked(Span, String),
+}
+
+fn conjoin(way: &[RequirementDef], more: &[RequirementDef]) -> Conjoined {
+    // The range an amount allows, as `min` up to just below `below`.
+    let range = |n: i32| match n {
+        n if n < 0 => (0, Some(-(n as i64))),
+        n => (n as i64, None),
+    };
+    let mut combined = way.to_vec();
+    for requirement in more {
+        let same = |r: &&mut RequirementDef| r.kind == requirement.kind && r.element.0 == requirement.element.0;
+        let existing = match combined.iter_mut().find(same) {
+            Some(existing) => existing,
+            None => {
+                combined.push(requirement.clone());
+                continue;
+            }
+        };
+        let name = format!("`{}{}`", requirement.kind.prefix(), requirement.element.0);
+        let span = existing.element.span;
+        let (a, b) = match (&existing.threshold, &requirement.threshold) {
+            (Threshold::Amount(a), Threshold::Amount(b)) => (range(*a), range(*b)),
+            (Threshold::Element(a), Threshold::Element(b)) if a.0 == b.0 => continue,
+            _ => {
+                let what = format!("{} is both compared with another element and an amount", name);
+                return Conjoined::Unchecked(span, what);
+            }
+        };
+        let min = a.0.max(b.0);
+        let below = match (a.1, b.1) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        existing.threshold = match below {
+            Some(below) if below <= min => return Conjoined::Never,
+            Some(below) if min > 0 => {
+                return Conjoined::Unchecked(span, format!("{} is {}", name, describe(min, Some(below))))
+            }
+            Some(below) => Threshold::Amount(-(below as i32)),
+            None if min > 0 => Threshold::Amount(min as i32),
+            None => continue,
+        };
+    }
+    Conjoined::Holds(combined)
+}
+
+/// Parses a parenthesized list, as every way it can be met.
+fn list(input: Input) -> IResult<Vec<Vec<Written>>> {
+    let (remain, items) = spanned(delimited(
+        ws(char('(')),
+        separated_list0(ws(char(',')), ws(either)),
+        context("`,`, `or` or `)`", cut(list_end(')'))),
+    ))(input)?;
+    // Every way of meeting all of the items, taking one way of each.
+    // The count is checked before each step, as a list of many `or`s
+    // could otherwise take too long to even build.
+    let mut ways = vec![Vec::new()];
+    for item in items.item {
+        if ways.len() * item.len() > MAX_ALTERNATIVES {
+            input.extra.report(
+                Diagnostic::error(
+                    Code::UnsupportedRequirement,
+                    items.span,
+                    "these requirements can be met in too many ways to split into",
+                )
+                .with_note(format!("a requirement list may be met in at most {} ways", MAX_ALTERNATIVES)),
+            );
+            return Ok((remain, Vec::new()));
+        }
+        ways = ways
+            .iter()
+            .flat_map(|way| item.iter().map(move |other| [way.clone(), other.clone()].concat()))
+            .collect();
+    }
+    Ok((remain, ways))
+}
+
+/// Parses requirements joined by `or`, as every way they can be met.
+fn either(input: Input) -> IResult<Vec<Vec<Written>>> {
+    let item = |i| alt((list, map(requirement, |written| vec![vec![written]])))(i);
+    map(
+        separated_list1(ws(keyword("or")), ws(item)),
+        |items| items.into_iter().flatten().collect(),
+    )(input)
+}
+
+fn requirement(input: Input) -> IResult<Written> {
+    fn kind(input: Input) -> IResult<RequirementKind> {
+        let qualifier = alt((
+            value(RequirementKind::Table, keyword("table")),
+            value(RequirementKind::Extant, keyword("extant")),
+        ));
+        map(opt(ws(qualifier)), |kind| kind.unwrap_or(RequirementKind::Basic))(input)
+    }
+    fn op(input: Input) -> IResult<Op> {
+        alt((
+            value(Op::AtLeast, tag(">=")),
+            value(Op::AtMost, tag("<=")),
+            value(Op::Equal, tag("==")),
+            value(Op::Above, char('>')),
+            value(Op::Below, char('<')),
+        ))(input)
+    }
+    // An id on its own names another element, unless it is a
+    // constant, and ids made only of digits are left as numbers.
+    fn element(input: Input) -> IResult<Spanned<DefKey>> {
+        let state = input.extra;
+        let named = move |key: &Spanned<DefKey>| {
+            !key.0.bytes().all(|b| b.is_ascii_digit()) && state.constant(&key.0).is_none()
+        };
+        let end = alt((value((), one_of(",)")), value((), keyword("or"))));
+        terminated(verify(spanned(defkey), named), peek(ws(end)))(input)
+    }
+
+    fn operand(input: Input) -> IResult<Either<i64, Spanned<DefKey>>> {
+        context(
+            "a number or an element",
+            alt((map(element, Either::Right), map(expr::integer, Either::Left))),
+        )(input)
+    }
+
+    let (remain, (written, (kind, element, rest))) = consumed(tuple((
+        kind,
+        ws(spanned(defkey)),
+        opt(alt((
+            map(preceded(ws(char(':')), context("an amount", cut(ws(signed)))), Either::Left),
+            map(pair(ws(op), cut(ws(operand))), Either::Right),
+        ))),
+    )))(input)?;
+    let span = span_of(&written);
+    let range = |min: i64, below: Option<i64>| Bound::Range { min: min.max(0), below };
+
+    let (element, bound, raw) = match rest {
+        None => (element, range(1, None), None),
+        Some(Either::Left(n)) if n < 0 => (element, range(0, Some(-(n as i64))), Some(n)),
+        Some(Either::Left(n)) => (element, range(n as i64, None), Some(n)),
+        Some(Either::Right((op, Either::Left(n)))) => {
+            let bound = match op {
+                Op::AtLeast => range(n, None),
+                Op::Above => range(n.saturating_add(1), None),
+                Op::AtMost => range(0, Some(n.saturating_add(1))),
+                Op::Below => range(0, Some(n)),
+                Op::Equal => range(n, Some(n.saturating_add(1))),
+            };
+            (element, bound, None)
+        }
+        Some(Either::Right((Op::AtLeast, Either::Right(other)))) => (element, Bound::Element(other), None),
+        // `a <= b` is `b >= a`, which the game can check.
+        Some(Either::Right((Op::AtMost, Either::Right(other)))) => (other, Bound::Element(element), None),
+        Some(Either::Right((_, Either::Right(other)))) => {
+            input.extra.report(
+                Diagnostic::error(
+                    Code::UnsupportedRequirement,
+                    span,
+                    format!("`{}` can't be compared with `{}` this way", element.0, other.0),
+                )
+                .with_note("the game can only check that an element is at least as many as another, with `>=` or `<=`"),
+            );
+            (element, Bound::Element(other), None)
+        }
+    };
+    Ok((remain, Written { kind, element, bound, raw, span }))
+}
+
+/// Combines the requirements on each element into one, returning
+/// the problems found with those which can't hold together or can't
+/// be checked by the game instead, if there are any.
+fn combine_way(written: Vec<Written>) -> Result<Vec<RequirementDef>, Vec<Diagnostic>> {
+    let mut groups: Vec<Vec<Written>> = Vec::new();
+    for requirement in written {
+        let same = |group: &&mut Vec<Written>| {
+            group[0].kind == requirement.kind && group[0].element.0 == requirement.element.0
+        };
+        match groups.iter_mut().find(same) {
+            Some(group) => group.push(requirement),
+            None => groups.push(vec![requirement]),
+        }
+    }
+    let mut problems = Vec::new();
+    let mut combined = Vec::new();
+    for group in groups {
+        match combine_group(group) {
+            Ok(requirement) => combined.extend(requirement),
+            Err(problem) => problems.push(problem),
+        }
+    }
+    if problems.is_empty() {
+        Ok(combined)
+    } else {
+        Err(problems)
+    }
+}
+
+/// Combines the requirements on one element, returning nothing if
+/// none is needed, or the problem if they can't be met or checked.
+fn combine_group(group: Vec<Written>) -> Result<Option<RequirementDef>, Diagnostic> {
+    let first = &group[0];
+    let (kind, element) = (first.kind, first.element.clone());
+    let name = format!("{}{}", kind.prefix(), element.0);
+    let requirement = |threshold| Ok(Some(RequirementDef { kind, element: element.clone(), threshold }));
+
+    if let [Written { raw: Some(n), .. }] = group.as_slice() {
+        return requirement(Threshold::Amount(*n));
+    }
+    if let Some(compared) = group.iter().find(|w| matches!(w.bound, Bound::Element(_))) {
+        let other = group.iter().find(|w| w.span != compared.span);
+        if let Some(other) = other {
+            return Err(Diagnostic::error(
+                Code::UnsupportedRequirement,
+                other.span,
+                format!("`{}` is compared with another element, so it can't be required again", name),
+            )
+            .with_label(compared.span, "compared here")
+            .with_note("the game keeps a single requirement for each element"));
+        }
+        return match &compared.bound {
+            Bound::Element(other) => requirement(Threshold::Element(other.clone())),
+            Bound::Range { .. } => unreachable!("found as a comparison with an element"),
+        };
+    }
+
+    // The tightest limits, and the requirements which set them.
+    let (mut min, mut below) = ((0, None), (None, None));
+    for written in &group {
+        if let Bound::Range { min: low, below: high } = written.bound {
+            if low > min.0 {
+                min = (low, Some(written.span));
+            }
+            if let Some(high) = high.filter(|&high| below.0.map_or(true, |b| high < b)) {
+                below = (Some(high), Some(written.span));
+            }
+        }
+    }
+    let (min, min_span) = min;
+    let (below, below_span) = below;
+
+    match below {
+        Some(below) if below <= min => {
+            // Point at whichever limit came last,
+            // and at the other one, if it was written.
+            let (at, other) = match (min_span, below_span) {
+                (Some(a), Some(b)) if a.start > b.start => (a, Some(b)),
+                (a, Some(b)) => (b, a.filter(|&a| a != b)),
+                (_, None) => unreachable!("there is a limit"),
+            };
+            let mut diagnostic = Diagnostic::error(
+                Code::UnsatisfiableRequirement,
+                at,
+                format!("`{}` can never be {}", name, describe(min, Some(below))),
+            );
+            if let Some(other) = other {
+                let limit = if Some(other) == min_span { describe(min, None) } else { describe(0, Some(below)) };
+                diagnostic = diagnostic.with_label(other, format!("`{}` must be {} here", name, limit));
+            }
+            Err(diagnostic)
+        }
+        Some(below) if min > 0 => {
+            let mut diagnostic = Diagnostic::error(
+                Code::UnsupportedRequirement,
+                below_span.or(min_span).expect("there is a limit"),
+                format!("the game can't check that `{}` is {}", name, describe(min, Some(below))),
+            )
+            .with_note("the game keeps one amount for each element, which is either a minimum or a maximum");
+            if let Some(min_span) = min_span.filter(|&span| Some(span) != below_span) {
+                diagnostic = diagnostic.with_label(min_span, format!("`{}` must be {} here", name, describe(min, None)));
+            }
+            Err(diagnostic)
+        }
+        Some(below) => amount(-below, below_span, requirement),
+        None if min > 0 => amount(min, min_span, requirement),
+        // Nothing is required of the element.
+        None => Ok(None),
+    }
+}
+
+/// Describes the amounts from `min` up to just below `below`.
+fn describe(min: i64, below: Option<i64>) -> String {
+    match (min, below) {
+        (min, Some(below)) if below == min + 1 => format!("exactly {}", min),
+        (0, Some(below)) => format!("fewer than {}", below),
+        (min, Some(below)) => format!("at least {} and fewer than {}", min, below),
+        (min, None) => format!("at least {}", min),
+    }
+}
+
+/// The requirement for the game's encoded amount `n`,
+/// if it fits, or the problem to report at `span` if not.
+fn amount(
+    n: i64,
+    span: Option<Span>,
+    requirement: impl Fn(Threshold) -> Result<Option<RequirementDef>, Diagnostic>,
+) -> Result<Option<RequirementDef>, Diagnostic> {
+    match i32::try_from(n) {
+        Ok(n) => requirement(Threshold::Amount(n)),
+        Err(_) => Err(Diagnostic::error(
+            Code::NumberOutOfRange,
+            span.expect("a limit was written"),
+            format!("`{}` is out of range here", n.abs()),
+        )
+        .with_note("requirement amounts must be below 2147483648")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses and combines `text` as a requirement list, returning
+    /// every way it can be met, written as the game's amounts, and
+    /// the codes of whatever was reported.
+    fn parse(text: &str) -> (Vec<Vec<String>>, Vec<Code>) {
+        let state = ParseState::new(0, PathBuf::from("test.crucible"), Rc::from(text), Definitions::default());
+        let (_, written) = requirements(Input::new_extra(text, &state)).expect("a requirement list");
+        let mut combined = Diagnostics::new();
+        let (first, others) = combine(&written, &mut combined);
+        let show = |r: &RequirementDef| match &r.threshold {
+            Threshold::Amount(n) => format!("{}{}:{}", r.kind.prefix(), r.element.0, n),
+            Threshold::Element(other) => format!("{}{}>={}", r.kind.prefix(), r.element.0, other.0),
+        };
+        let ways = std::iter::once(first).chain(others).map(|way| way.iter().map(show).collect()).collect();
+        let (diagnostics, _, _, _) = state.finish();
+        (ways, diagnostics.into_iter().chain(combined).map(|d| d.code).collect())
+    }
+
+    /// The only way `text` can be met, which must not report anything.
+    fn met(text: &str) -> Vec<String> {
+        let (mut ways, codes) = parse(text);
+        assert_eq!(codes, [], "reported for {}", text);
+        assert_eq!(ways.len(), 1, "ways of meeting {}", text);
+        ways.remove(0)
+    }
+
+    fn reported(text: &str) -> Vec<Code> {
+        parse(text).1
+    }
+
+    #[test]
+    fn amounts_are_kept_as_written() {
+        assert_eq!(met("(lantern)"), ["lantern:1"]);
+        assert_eq!(met("(lantern:5)"), ["lantern:5"]);
+        assert_eq!(met("(lantern:-2)"), ["lantern:-2"]);
+        assert_eq!(met("(lantern:0)"), ["lantern:0"]);
+        assert_eq!(met("(table lantern:2, extant moth:-1)"), ["table lantern:2", "extant moth:-1"]);
+    }
+
+    #[test]
+    fn comparisons_become_amounts() {
+        assert_eq!(met("(lantern >= 5)"), ["lantern:5"]);
+        assert_eq!(met("(lantern > 4)"), ["lantern:5"]);
+        assert_eq!(met("(moth < 3)"), ["moth:-3"]);
+        assert_eq!(met("(moth <= 2)"), ["moth:-3"]);
+        assert_eq!(met("(edge == 0)"), ["edge:-1"]);
+        assert_eq!(met("(edge <= 0)"), ["edge:-1"]);
+    }
+
+    #[test]
+    fn comparisons_with_elements() {
+        assert_eq!(met("(heart >= winter)"), ["heart>=winter"]);
+        assert_eq!(met("(heart <= winter)"), ["winter>=heart"]);
+        assert_eq!(reported("(heart < winter)"), [Code::UnsupportedRequirement]);
+        assert_eq!(reported("(heart == winter)"), [Code::UnsupportedRequirement]);
+        assert_eq!(reported("(heart >= winter, heart >= 2)"), [Code::UnsupportedRequirement]);
+    }
+
+    #[test]
+    fn requirements_on_one_element_are_combined() {
+        assert_eq!(met("(lantern >= 2, lantern >= 5)"), ["lantern:5"]);
+        assert_eq!(met("(moth < 5, moth < 3, lantern)"), ["moth:-3", "lantern:1"]);
+        // Only requirements of the same kind are combined.
+        assert_eq!(met("(table lantern >= 1, lantern < 2)"), ["table lantern:1", "lantern:-2"]);
+        // A bound that allows anything needs nothing.
+        assert_eq!(met("(lantern >= 0, moth)"), ["moth:1"]);
+    }
+
+    #[test]
+    fn impossible_requirements() {
+        assert_eq!(reported("(lantern >= 5, lantern < 3)"), [Code::UnsatisfiableRequirement]);
+        assert_eq!(reported("(lantern > 2, lantern <= 2)"), [Code::UnsatisfiableRequirement]);
+        assert_eq!(reported("(lantern < 0)"), [Code::UnsatisfiableRequirement]);
+    }
+
+    #[test]
+    fn or_splits_into_every_way() {
+        let (ways, codes) = parse("(lantern or moth, edge)");
+        assert_eq!(ways, [vec!["lantern:1", "edge:1"], vec!["moth:1", "edge:1", "lantern:-1"]]);
+        assert_eq!(codes, []);
+    }
+
+    #[test]
+    fn each_way_leaves_out_those_before_it() {
+        let (ways, codes) = parse("(lantern:2 or moth:2)");
+        assert_eq!(ways, [vec!["lantern:2"], vec!["moth:2", "lantern:-2"]]);
+        assert_eq!(codes, []);
+        // Not meeting `lantern, moth` is either not `lantern`, or `lantern` but not `moth`.
+        let (ways, _) = parse("((lantern, moth) or edge < 2)");
+        assert_eq!(
+            ways,
+            [vec!["lantern:1", "moth:1"], vec!["edge:-2", "lantern:-1"], vec!["edge:-2", "lantern:1", "moth:-1"]],
+        );
+        // Ways which can't both hold are left as they are.
+        let (ways, _) = parse("(lantern < 3 or lantern >= 3)");
+        assert_eq!(ways, [["lantern:-3"], ["lantern:3"]]);
+        // And a way covered by those before it is left out.
+        let (ways, codes) = parse("(lantern >= 2 or lantern >= 5)");
+        assert_eq!(ways, [["lantern:2"]]);
+        assert_eq!(codes, []);
+    }
+
+    #[test]
+    fn ways_that_cant_be_kept_apart() {
+        // The second way would need `lantern` to be from 2 up to 5.
+        assert_eq!(reported("(lantern >= 5 or lantern >= 2)"), [Code::UnsupportedRequirement]);
+        assert_eq!(reported("(heart >= winter or moth)"), [Code::UnsupportedRequirement]);
+        let (ways, codes) = parse("(moth or heart >= winter)");
+        assert_eq!(ways, [vec!["moth:1"], vec!["heart>=winter", "moth:-1"]]);
+        assert_eq!(codes, []);
+    }
+
+    #[test]
+    fn problems_shared_by_ways_are_reported_once() {
+        assert_eq!(reported("(lantern >= 5, lantern < 3, moth or edge)"), [Code::UnsatisfiableRequirement]);
+    }
+
+    #[test]
+    fn impossible_ways_are_left_out() {
+        let (ways, codes) = parse("(lantern >= 5, (lantern < 3 or moth > 1))");
+        assert_eq!(ways, [["lantern:5", "moth:2"]]);
+        assert_eq!(codes, [Code::UnsatisfiableAlternative]);
+        let (_, codes) = parse("(lantern >= 5, (lantern < 3 or lantern < 2))");
+        assert_eq!(codes, [Code::UnsatisfiableRequirement, Code::UnsatisfiableRequirement]);
+    }
+
+    #[test]
+    fn ranges_the_game_cant_check() {
+        assert_eq!(reported("(lantern >= 2, lantern < 5)"), [Code::UnsupportedRequirement]);
+        assert_eq!(reported("(lantern == 3)"), [Code::UnsupportedRequirement]);
+        assert_eq!(reported("(lantern >= 3000000000)"), [Code::NumberOutOfRange]);
+    }
+
+    #[test]
+    fn too_many_ways() {
+        let groups: Vec<_> = (0..24).map(|n| format!("a{} or b{}", n, n)).collect();
+        assert_eq!(reported(&format!("({})", groups.join(", "))), [Code::UnsupportedRequirement]);
+        let options: Vec<_> = (0..65).map(|n| format!("a{}", n)).collect();
+        assert_eq!(reported(&format!("({})", options.join(" or "))), [Code::UnsupportedRequirement]);
+    }
+}
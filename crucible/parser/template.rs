@@ -0,0 +1,374 @@
+//! `template` declarations and their instantiations.
+//!
+//! A template is a named group of units with typed parameters,
+//! for content that comes in families of near-identical parts:
+//!
+//! ```text
+//! template fragment(principle: id, tier: number, label: string) {
+//!     card {principle}.fragment.{tier} "{label} Fragment" "" ({principle}: tier * 2) {}
+//! }
+//!
+//! fragment!(lantern, 2, "Lantern");
+//! ```
+//!
+//! Each instantiation parses the template's body again, with every
+//! `{name}` in ids, references and strings replaced by the argument
+//! given for that parameter. Number parameters can also be used by
+//! name in expressions. The components this produces belong to the
+//! namespace the instantiation is in, and are kept together in a
+//! [Unit::Expansion], so that problems found in them can point at
+//! the instantiation as well as at the template's body.
+//!
+//! Templates are scoped and looked up like constants, and likewise
+//! must be declared before they are used. The constants and templates
+//! used in a template's body are looked up from where the template is
+//! declared, and any declared inside its body only exist within each
+//! of its instantiations.
+
+use std::rc::Rc;
+
+use super::*;
+
+/// Every template declared so far in a build, by full path.
+pub type Templates = HashMap<String, Template>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamKind {
+    Id,
+    Str,
+    Number,
+}
+
+/// The value given for a template parameter.
+#[derive(Debug, Clone)]
+pub enum Binding {
+    Id(String),
+    Str(String),
+    Number(i64),
+}
+
+impl Binding {
+    /// The text which `{name}` stands for.
+    pub fn text(&self) -> String {
+        match self {
+            Binding::Id(text) | Binding::Str(text) => text.clone(),
+            Binding::Number(n) => n.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Template {
+    name: Spanned<String>,
+    params: Vec<(Spanned<String>, ParamKind)>,
+    /// The namespace the template was declared in.
+    namespace: Vec<String>,
+    /// The file the template was declared in, and its text.
+    path: PathBuf,
+    text: Rc<str>,
+    /// The offset of the template's body, just past its `{`.
+    body: usize,
+}
+
+impl ParseState {
+    /// A state for parsing the body of `template`, with `bindings`
+    /// as its arguments. Whatever the body declares is not seen
+    /// by this state.
+    pub(super) fn nested(&self, template: &Template, bindings: HashMap<String, Binding>) -> ParseState {
+        let mut expanding = self.expanding.clone();
+        expanding.push(template.name.item.clone());
+        ParseState {
+            file: template.name.span.file,
+            inputs: RefCell::new(Vec::new()),
+            path: template.path.clone(),
+            text: template.text.clone(),
+            diagnostics: RefCell::new(Vec::new()),
+            definitions: self.definitions.clone(),
+            namespace: RefCell::new(template.namespace.clone()),
+            bindings: RefCell::new(vec![bindings]),
+            expanding,
+            dropped: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Parses the units of the block at `offset` in the text of `child`,
+    /// which was checked by [stand_in] beforehand, with `child` as the
+    /// state. Problems found are passed through `relabel` and reported
+    /// to this state, apart from the syntax errors [stand_in] reported.
+    pub(super) fn expand(&self, child: ParseState, offset: usize, relabel: impl Fn(Diagnostic) -> Diagnostic) -> Vec<Unit> {
+        let text = child.text.clone();
+        let (body, _) = Input::new_extra(&text, &child).take_split(offset);
+        let units = match units(body, true) {
+            Ok((_, units)) => units,
+            // The block parsed when it was checked, so the
+            // same text can't fail to parse now.
+            Err(_) => Vec::new(),
+        };
+        let (reported, inputs, _, dropped) = child.finish();
+        for diagnostic in reported {
+            if diagnostic.code != Code::Syntax {
+                self.report(relabel(diagnostic));
+            }
+        }
+        self.dropped.borrow_mut().extend(dropped);
+        for input in inputs {
+            self.track_input(input);
+        }
+        units
+    }
+
+    /// Looks up the template `name` like [ParseState::constant].
+    pub(super) fn template(&self, name: &str) -> Option<Template> {
+        let definitions = self.definitions.borrow();
+        let templates = &definitions.templates;
+        if let Some(root) = name.strip_prefix('.') {
+            return templates.get(root).cloned();
+        }
+        let namespace = self.namespace.borrow();
+        (0..=namespace.len()).rev().find_map(|depth| {
+            let path = namespace[..depth]
+                .iter()
+                .map(String::as_str)
+                .chain(std::iter::once(name))
+                .collect::<Vec<_>>()
+                .join(".");
+            templates.get(&path).cloned()
+        })
+    }
+
+    /// Declares `template` in the namespace being parsed.
+    fn declare_template(&self, template: Template) {
+        let path = self.declared_path(&template.name.item);
+        let templates = &mut self.definitions.borrow_mut().templates;
+        match templates.get(&path) {
+            Some(first) => self.report(
+                Diagnostic::error(
+                    Code::DuplicateDefinition,
+                    template.name.span,
+                    format!("template `{}` is declared more than once", path),
+                )
+                .with_label(first.name.span, "first declared here"),
+            ),
+            None => {
+                templates.insert(path, template);
+            }
+        }
+    }
+}
+
+/// Parses a `template name(params) { units }` declaration.
+pub fn parse(input: Input) -> IResult<Unit> {
+    fn kind(input: Input) -> IResult<ParamKind> {
+        context(
+            "`id`, `string` or `number`",
+            alt((
+                value(ParamKind::Id, keyword("id")),
+                value(ParamKind::Str, keyword("string")),
+                value(ParamKind::Number, keyword("number")),
+            )),
+        )(input)
+    }
+    fn param(input: Input) -> IResult<(Spanned<String>, ParamKind)> {
+        separated_pair(
+            ws(spanned(expr::name)),
+            context("`:`", ws(char(':'))),
+            ws(kind),
+        )(input)
+    }
+
+    let state = input.extra;
+    // Doc comments describe the template itself, so they are not
+    // passed on to the components it produces.
+    let (rest, (_, (name, params, open))) = pair(
+        preceded(doc_comments, ws(keyword("template"))),
+        cut(tuple((
+            context("a template name", ws(spanned(expr::name))),
+            delimited(
+                context("`(`", ws(char('('))),
+                separated_list0(ws(char(',')), param),
+                context("`,` or `)`", list_end(')')),
+            ),
+            context("`{`", preceded(space, spanned(char('{')))),
+        ))),
+    )(input)?;
+
+    let mut seen: HashMap<&str, Span> = HashMap::new();
+    for (param, _) in params.iter() {
+        match seen.get(param.item.as_str()) {
+            Some(first) => state.report(
+                Diagnostic::error(
+                    Code::DuplicateDefinition,
+                    param.span,
+                    format!("parameter `{}` is declared more than once", param.item),
+                )
+                .with_label(*first, "first declared here"),
+            ),
+            None => {
+                seen.insert(&param.item, param.span);
+            }
+        }
+    }
+
+    let template = Template {
+        name,
+        params,
+        namespace: state.namespace.borrow().clone(),
+        path: state.path.clone(),
+        text: state.text.clone(),
+        body: open.span.end,
+    };
+
+    // The body is parsed once with stand-in arguments, to find where
+    // it ends and to report any syntax errors in it. Anything else
+    // depends on the arguments, so it is left to each instantiation.
+    let stand_ins = template
+        .params
+        .iter()
+        .map(|(param, kind)| {
+            let binding = match kind {
+                ParamKind::Id => Binding::Id(param.item.clone()),
+                ParamKind::Str => Binding::Str(String::new()),
+                ParamKind::Number => Binding::Number(1),
+            };
+            (param.item.clone(), binding)
+        })
+        .collect();
+    let (remain, units) = stand_in(rest, state.nested(&template, stand_ins))?;
+
+    let (_, text) = input.take_split(remain.location_offset() - input.location_offset());
+    let id = Spanned::new(DefKey(template.name.item.clone()), template.name.span);
+    state.declare_template(template);
+    Ok((
+        remain,
+        Unit::Template {
+            id,
+            units,
+            span: span_of(&text),
+        },
+    ))
+}
+
+/// Parses a `name!(args);` instantiation of a template.
+pub fn instantiate(input: Input) -> IResult<Unit> {
+    let state = input.extra;
+    let (rest, (name, _)) = pair(ws(spanned(expr::path)), char('!'))(input)?;
+    let template = state.template(&name.item);
+    let (rest, args) = cut(|i| arguments(i, template.as_ref()))(rest)?;
+    let (remain, _) = context("`;`", cut(ws(char(';'))))(rest)?;
+    let (_, text) = input.take_split(remain.location_offset() - input.location_offset());
+    let site = span_of(&text);
+    let id = Spanned::new(DefKey(name.item.clone()), name.span);
+    let empty = |id| Ok((remain, Unit::Expansion { template: id, units: Vec::new(), span: site }));
+
+    let template = match template {
+        Some(template) => template,
+        None => {
+            state.report(
+                Diagnostic::error(
+                    Code::UnresolvedTemplate,
+                    name.span,
+                    format!("cannot find template `{}` in this scope", name.item),
+                )
+                .with_note("templates must be declared before they are used"),
+            );
+            return empty(id);
+        }
+    };
+    if args.len() != template.params.len() {
+        state.report(
+            Diagnostic::error(
+                Code::MismatchedArguments,
+                site,
+                format!(
+                    "template `{}` takes {} argument(s), but was given {}",
+                    name.item,
+                    template.params.len(),
+                    args.len()
+                ),
+            )
+            .with_label(template.name.span, "template declared here"),
+        );
+        return empty(id);
+    }
+    if state.expanding.contains(&template.name.item) {
+        state.report(
+            Diagnostic::error(
+                Code::RecursiveTemplate,
+                site,
+                format!("template `{}` instantiates itself", name.item),
+            )
+            .with_label(template.name.span, "template declared here"),
+        );
+        return empty(id);
+    }
+
+    let bindings = template
+        .params
+        .iter()
+        .zip(args)
+        .map(|((param, _), arg)| (param.item.clone(), arg))
+        .collect();
+    let child = state.nested(&template, bindings);
+    let units = state.expand(child, template.body, |d| d.in_expansion(&name.item, site));
+    Ok((remain, Unit::Expansion { template: id, units, span: site }))
+}
+
+/// Parses the units of a block whose `{` was just parsed, up to and
+/// including its `}`, with `scratch` as the state: one which binds
+/// stand-ins for the values the block will later be expanded with.
+/// Anything but a syntax error depends on those values, so only the
+/// syntax errors found are reported.
+pub(super) fn stand_in(input: Input, scratch: ParseState) -> IResult<Vec<Unit>> {
+    let rebase = |at: Input| input.take_split(at.location_offset() - input.location_offset()).0;
+    let (remain, units) = match cut(|i| units(i, true))(input.map_extra(|_| &scratch)) {
+        Ok((remain, units)) => (rebase(remain), units),
+        Err(e) => {
+            return Err(e.map(|e| Error {
+                input: rebase(e.input),
+                kind: e.kind,
+                expected: e.expected,
+            }))
+        }
+    };
+    for diagnostic in scratch.finish().0 {
+        if diagnostic.code == Code::Syntax {
+            input.extra.report(diagnostic);
+        }
+    }
+    let (remain, _) = context("`}`", cut(ws(char('}'))))(remain)?;
+    Ok((remain, units))
+}
+
+/// Parses the arguments of an instantiation of `template`, each
+/// of the kind of the parameter it is given for. The arguments of
+/// an unknown template, or any past the last parameter, are parsed
+/// as anything that could be an argument.
+fn arguments<'a>(input: Input<'a>, template: Option<&Template>) -> IResult<'a, Vec<Binding>> {
+    let (mut input, _) = context("`(`", ws(char('(')))(input)?;
+    let mut args = Vec::new();
+    if let Ok((rest, _)) = ws(char(')'))(input) {
+        return Ok((rest, args));
+    }
+    loop {
+        let kind = template.and_then(|t| t.params.get(args.len())).map(|(_, kind)| *kind);
+        let (rest, arg) = match kind {
+            Some(ParamKind::Id) => context("an id", map(ws(defkey), |k| Binding::Id(k.0)))(input)?,
+            Some(ParamKind::Str) => context("a string", map(ws(string), Binding::Str))(input)?,
+            Some(ParamKind::Number) => context("a number", map(ws(expr::integer), Binding::Number))(input)?,
+            None => context(
+                "an argument",
+                alt((
+                    map(ws(string), Binding::Str),
+                    map(ws(expr::unevaluated), |t| Binding::Id(t.fragment().to_string())),
+                    map(ws(defkey), |k| Binding::Id(k.0)),
+                )),
+            )(input)?,
+        };
+        args.push(arg);
+        if let Ok((rest, _)) = list_end(')')(rest) {
+            return Ok((rest, args));
+        }
+        let (rest, _) = context("`,` or `)`", ws(char(',')))(rest)?;
+        input = rest;
+    }
+}
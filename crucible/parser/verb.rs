@@ -1,5 +1,6 @@
 #![allow(unused_imports)]
 
+use std::collections::HashMap;
 use std::path::Path;
 use std::path::PathBuf;
 use mothlib::lantern::Attribute;
@@ -8,7 +9,6 @@ use tracing::{event, Level};
 use anyhow::{bail, Result};
 
 use nom::{
-    IResult,
     bytes::complete::*,
     character::complete::*,
     multi::*,
@@ -19,18 +19,54 @@ use nom::{
 };
 use super::*;
 
-pub fn parse(input: &str) -> IResult<&str, Component> {
-    let (remain, (_, id, label, description, slot)) = tuple((
-        ws(tag_no_case("verb")),
-        ws(defkey),
-        ws(string::parse),
-        ws(string::parse),
-        opt(delimited(
-            ws(char('(')),
-            ws(slot),
-            ws(char(')')),
-        )),
-    ))(input)?;
+#[derive(Debug, Clone)]
+pub struct VerbDef {
+    pub id: Spanned<DefKey>,
+    pub label: String,
+    pub description: String,
+    pub slot: Option<SlotDef>,
+    pub others: HashMap<DefKey, json::Value>,
+    /// Whether this verb was written inline in a recipe, for the
+    /// token it spawns, rather than being one the player keeps.
+    pub temporary: bool,
+}
+
+pub fn parse(input: Input) -> IResult<Component> {
+    let (remain, (_, (id, label, description, slot, statements))) = pair(
+        ws(keyword("verb")),
+        cut(tuple((
+            ws(spanned(defkey)),
+            ws(string),
+            ws(string),
+            opt(delimited(
+                ws(char('(')),
+                ws(slot),
+                context("`)`", ws(char(')'))),
+            )),
+            opt(block(set_statement)),
+        ))),
+    )(input)?;
 
-    Ok((remain, Component::Verb(Box::new(Verb{ id, label, description, slot }))))
+    let others = assignments(input.extra, statements.unwrap_or_default(), &["id", "label", "description"], "verb");
+    Ok((remain, Component::Verb(Box::new(VerbDef{ id, label, description, slot, others, temporary: false }))))
+}
+
+/// Parses a temporary verb written inline in a recipe, which has
+/// no id until the recipe gives it one.
+pub fn temporary(input: Input) -> IResult<VerbDef> {
+    let (remain, verb) = spanned(pair(
+        keyword("verb"),
+        cut(tuple((
+            ws(string),
+            ws(string),
+            opt(delimited(
+                ws(char('(')),
+                ws(slot),
+                context("`)`", ws(char(')'))),
+            )),
+        ))),
+    ))(input)?;
+    let (_, (label, description, slot)) = verb.item;
+    let id = Spanned::new(DefKey(String::new()), verb.span);
+    Ok((remain, VerbDef { id, label, description, slot, others: HashMap::new(), temporary: true }))
 }
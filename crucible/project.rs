@@ -0,0 +1,167 @@
+//! Finding the files to compile.
+//!
+//! Files named on the command line are compiled as they are.
+//! Directories are searched recursively for `.crucible` files,
+//! in sorted order so that every build sees them the same way.
+//!
+//! A directory may contain a `Crucible.toml` project file,
+//! which describes the mod it holds:
+//!
+//! ```toml
+//! [mod]
+//! name = "examplemod"
+//! # The files to compile. Anything they import is compiled too.
+//! # If this is left out, every matching file is compiled instead.
+//! entry = ["src/main.crucible"]
+//! # Which files to search for, relative to the project file.
+//! include = ["src/**/*.crucible"]
+//! exclude = ["src/drafts/**"]
+//!
+//! [output]
+//! path = "build/examplemod.lirc"
+//! compression = true
+//! ```
+//!
+//! Every setting is optional, and options given on the
+//! command line take precedence over the project file.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::Deserialize;
+use tracing::{event, Level};
+use walkdir::WalkDir;
+
+pub const PROJECT_FILE: &str = "Crucible.toml";
+
+/// The pattern used to find source files when no `include` is given.
+const DEFAULT_INCLUDE: &str = "**/*.crucible";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProjectFile {
+    #[serde(rename = "mod", default)]
+    module: ModSection,
+    #[serde(default)]
+    output: OutputSection,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ModSection {
+    name: Option<String>,
+    #[serde(default)]
+    entry: Vec<PathBuf>,
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OutputSection {
+    path: Option<PathBuf>,
+    compression: Option<bool>,
+}
+
+/// Everything needed to know what to build, and where to put it.
+#[derive(Debug, Default)]
+pub struct Project {
+    /// The name of the mod, if a project file gave one.
+    pub name: Option<String>,
+    /// The files to compile, in the order they should be read.
+    pub files: Vec<PathBuf>,
+    /// Where the project file asked for output to be written.
+    pub output: Option<PathBuf>,
+    /// Whether the project file asked for compressed output.
+    pub compression: Option<bool>,
+}
+
+/// Works out which files to compile from the paths given on the
+/// command line, along with any extra `include` and `exclude` globs.
+pub fn discover(inputs: &[PathBuf], include: &[String], exclude: &[String]) -> Result<Project> {
+    let mut project = Project::default();
+    let mut project_file: Option<PathBuf> = None;
+
+    for input in inputs {
+        let meta = std::fs::metadata(input)
+            .with_context(|| format!("Could not read input path `{}`", input.display()))?;
+        if meta.is_file() {
+            project.files.push(input.clone());
+            continue;
+        }
+
+        let manifest = input.join(PROJECT_FILE);
+        if !manifest.is_file() {
+            project.files.extend(walk(input, include, exclude)?);
+            continue;
+        }
+        if let Some(first) = &project_file {
+            bail!(
+                "Found more than one project file: `{}` and `{}`",
+                first.display(),
+                manifest.display()
+            );
+        }
+
+        event!(Level::DEBUG, "Reading project file {}", manifest.display());
+        let text = std::fs::read_to_string(&manifest)?;
+        let file: ProjectFile = toml::from_str(&text)
+            .with_context(|| format!("Invalid project file `{}`", manifest.display()))?;
+
+        if file.module.entry.is_empty() {
+            let include: Vec<String> = file.module.include.iter().chain(include).cloned().collect();
+            let exclude: Vec<String> = file.module.exclude.iter().chain(exclude).cloned().collect();
+            project.files.extend(walk(input, &include, &exclude)?);
+        } else {
+            project
+                .files
+                .extend(file.module.entry.iter().map(|entry| input.join(entry)));
+        }
+        project.name = file.module.name;
+        project.output = file.output.path.map(|path| input.join(path));
+        project.compression = file.output.compression;
+        project_file = Some(manifest);
+    }
+
+    if project.files.is_empty() {
+        bail!("No Crucible source files were found in the given paths")
+    }
+    Ok(project)
+}
+
+/// Finds every file under `root` whose path relative to `root` matches
+/// one of the `include` globs and none of the `exclude` globs.
+fn walk(root: &Path, include: &[String], exclude: &[String]) -> Result<Vec<PathBuf>> {
+    let include = if include.is_empty() {
+        globs(&[DEFAULT_INCLUDE.to_owned()])?
+    } else {
+        globs(include)?
+    };
+    let exclude = globs(exclude)?;
+
+    let mut files = Vec::new();
+    for entry in WalkDir::new(root).sort_by_file_name() {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(root).unwrap_or_else(|_| entry.path());
+        if include.is_match(relative) && !exclude.is_match(relative) {
+            event!(Level::DEBUG, "Found source file {}", entry.path().display());
+            files.push(entry.into_path());
+        }
+    }
+    Ok(files)
+}
+
+fn globs(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern).with_context(|| format!("Invalid glob pattern `{}`", pattern))?;
+        builder.add(glob);
+    }
+    Ok(builder.build()?)
+}
@@ -0,0 +1,294 @@
+//! Name resolution.
+//!
+//! Components are declared with ids relative to the namespace
+//! they appear in, and refer to each other the same way. This
+//! pass rewrites every declared id into its full, in-game form,
+//! and every reference into the full id of the component it
+//! names.
+//!
+//! A reference is looked up in the enclosing namespace first,
+//! then in each namespace that contains it, so that inside of
+//! `a.b.c`, the reference `x` may name `a.b.c.x`, `a.b.x`,
+//! `a.x` or `x`. A reference starting with `.` names something
+//! at the root, such as content from the base game, and is left
+//! unchecked. A reference whose first part is a `use` alias has
+//! that part replaced by the path the alias stands for.
+//!
+//! Components removed by [crate::cfg] are not declared, but a
+//! reference to one says that it was compiled out, and why. Nor
+//! are components which failed to parse, but as their error has
+//! already been reported, references to them are left unreported.
+
+use std::collections::{HashMap, HashSet};
+
+use mothlib::lantern::DefKey;
+
+use crate::cfg::CompiledOut;
+use crate::diagnostic::{Code, Diagnostic, Diagnostics, FileId, Span, Spanned};
+use crate::parser::{Crucible, Iteration, Unit};
+
+/// Resolves every id in `crucible` in place, reporting
+/// any that cannot be resolved to `diagnostics`.
+pub fn resolve(crucible: &mut Crucible, compiled_out: CompiledOut, diagnostics: &mut Diagnostics) {
+    let mut decls = Declarations {
+        compiled_out,
+        dropped: crucible.dropped().iter().cloned().collect(),
+        ..Declarations::default()
+    };
+    declare(crucible.units_mut(), "", &mut decls, diagnostics);
+    resolve_units(crucible.units_mut(), "", &[], &decls, diagnostics);
+}
+
+#[derive(Default)]
+struct Declarations {
+    /// The full id of every component, and where it was declared.
+    components: HashMap<String, Span>,
+    /// Every namespace, including the implicit ones
+    /// such as `a` and `a.b` for `namespace a.b.c`.
+    namespaces: HashSet<String>,
+    /// The components removed by `#[cfg]`.
+    compiled_out: CompiledOut,
+    /// The components which failed to parse.
+    dropped: HashSet<String>,
+}
+
+impl Declarations {
+    fn contains(&self, path: &str) -> bool {
+        self.components.contains_key(path) || self.namespaces.contains(path)
+    }
+}
+
+/// A `use` alias, which only applies to references
+/// in the same file as the directive.
+#[derive(Clone)]
+struct Alias {
+    file: FileId,
+    alias: String,
+    path: String,
+}
+
+fn join(prefix: &str, id: &str) -> String {
+    if prefix.is_empty() {
+        id.to_owned()
+    } else {
+        format!("{}.{}", prefix, id)
+    }
+}
+
+/// The namespace which contains `prefix`, or `None` at the root.
+fn parent(prefix: &str) -> Option<&str> {
+    match prefix.rsplit_once('.') {
+        Some((parent, _)) => Some(parent),
+        None if prefix.is_empty() => None,
+        None => Some(""),
+    }
+}
+
+fn declare(units: &mut [Unit], prefix: &str, decls: &mut Declarations, diagnostics: &mut Diagnostics) {
+    for unit in units {
+        match unit {
+            Unit::Namespace { id, units, .. } => {
+                let full = join(prefix, &id.0);
+                let mut ns = Some(full.as_str());
+                while let Some(name) = ns.filter(|n| !n.is_empty()) {
+                    decls.namespaces.insert(name.to_owned());
+                    ns = parent(name);
+                }
+                declare(units, &full, decls, diagnostics);
+            }
+            Unit::Component { id, component, .. } => {
+                let full = join(prefix, &id.0);
+                if let Some(first) = decls.components.get(&full) {
+                    diagnostics.push(
+                        Diagnostic::error(
+                            Code::DuplicateDefinition,
+                            id.span,
+                            format!("`{}` is defined more than once", full),
+                        )
+                        .with_label(*first, "first defined here"),
+                    );
+                } else {
+                    decls.components.insert(full.clone(), id.span);
+                }
+                id.item = DefKey(full.clone());
+                component.id_mut().item = DefKey(full);
+            }
+            Unit::Expansion { template, units, span } => {
+                let mut found = Diagnostics::new();
+                declare(units, prefix, decls, &mut found);
+                diagnostics.extend(found.into_iter().map(|d| d.in_expansion(&template.0, *span)));
+            }
+            Unit::For { variable, iterations, .. } => {
+                for Iteration { value, units } in iterations {
+                    let mut found = Diagnostics::new();
+                    declare(units, prefix, decls, &mut found);
+                    let label = |d: Diagnostic| d.in_iteration(&variable.0, &value.item, value.span);
+                    diagnostics.extend(found.into_iter().map(label));
+                }
+            }
+            Unit::Use { .. } | Unit::Const { .. } | Unit::Template { .. } => (),
+        }
+    }
+}
+
+fn resolve_units(
+    units: &mut [Unit],
+    prefix: &str,
+    outer: &[Alias],
+    decls: &Declarations,
+    diagnostics: &mut Diagnostics,
+) {
+    let mut aliases = outer.to_vec();
+
+    // Aliases apply to the whole of the block they are declared
+    // in, so they are all collected before resolving anything.
+    for unit in units.iter() {
+        if let Unit::Use { path, alias, span } = unit {
+            let target = path.0.strip_prefix('.').unwrap_or(&path.0);
+            if decls.contains(target) || decls.dropped.contains(target) {
+                aliases.push(Alias {
+                    file: span.file,
+                    alias: alias.0.clone(),
+                    path: target.to_owned(),
+                });
+            } else {
+                diagnostics.push(Diagnostic::error(
+                    Code::UnresolvedUse,
+                    path.span,
+                    format!("no namespace or component named `{}` was declared", target),
+                ));
+            }
+        }
+    }
+
+    // Recipes lifted out of another one share its verb, span and
+    // all, so a reference is only resolved and reported once.
+    let mut resolved: HashMap<Span, Option<DefKey>> = HashMap::new();
+    for unit in units {
+        match unit {
+            Unit::Namespace { id, units, .. } => {
+                resolve_units(units, &join(prefix, &id.0), &aliases, decls, diagnostics);
+            }
+            Unit::Component { component, inherits, .. } => {
+                let refs = component.references_mut().into_iter().map(|(_, r)| r).chain(inherits.as_mut());
+                for reference in refs {
+                    let full = resolved.entry(reference.span).or_insert_with(|| {
+                        lookup(reference, prefix, &aliases, decls)
+                            .map_err(|diagnostic| diagnostics.extend(diagnostic))
+                            .ok()
+                            .map(DefKey)
+                    });
+                    if let Some(full) = full {
+                        reference.item = full.clone();
+                    }
+                }
+            }
+            Unit::Expansion { template, units, span } => {
+                let mut found = Diagnostics::new();
+                resolve_units(units, prefix, &aliases, decls, &mut found);
+                diagnostics.extend(found.into_iter().map(|d| d.in_expansion(&template.0, *span)));
+            }
+            Unit::For { variable, iterations, .. } => {
+                for Iteration { value, units } in iterations {
+                    let mut found = Diagnostics::new();
+                    resolve_units(units, prefix, &aliases, decls, &mut found);
+                    let label = |d: Diagnostic| d.in_iteration(&variable.0, &value.item, value.span);
+                    diagnostics.extend(found.into_iter().map(label));
+                }
+            }
+            Unit::Use { .. } | Unit::Const { .. } | Unit::Template { .. } => (),
+        }
+    }
+}
+
+/// The full id `reference` names, or the diagnostic saying why it
+/// names nothing. There is no diagnostic if it names a component
+/// which failed to parse, as that has been reported already.
+fn lookup(
+    reference: &Spanned<DefKey>,
+    prefix: &str,
+    aliases: &[Alias],
+    decls: &Declarations,
+) -> Result<String, Option<Diagnostic>> {
+    let name = reference.0.as_str();
+    if let Some(root) = name.strip_prefix('.') {
+        return Ok(root.to_owned());
+    }
+
+    let (head, rest) = match name.split_once('.') {
+        Some((head, rest)) => (head, Some(rest)),
+        None => (name, None),
+    };
+    let alias = aliases
+        .iter()
+        .rev()
+        .find(|a| a.file == reference.span.file && a.alias == head);
+    if let Some(alias) = alias {
+        let full = match rest {
+            Some(rest) => join(&alias.path, rest),
+            None => alias.path.clone(),
+        };
+        return if decls.components.contains_key(&full) {
+            Ok(full)
+        } else if decls.dropped.contains(&full) {
+            Err(None)
+        } else if let Some(diagnostic) = compiled_out(reference, &full, decls) {
+            Err(Some(diagnostic))
+        } else {
+            Err(Some(
+                Diagnostic::error(
+                    Code::UnresolvedReference,
+                    reference.span,
+                    format!("cannot find `{}`", full),
+                )
+                .with_note(format!("`{}` is an alias for `{}`", alias.alias, alias.path)),
+            ))
+        };
+    }
+
+    let mut scope = Some(prefix);
+    while let Some(ns) = scope {
+        let candidate = join(ns, name);
+        if decls.components.contains_key(&candidate) {
+            return Ok(candidate);
+        }
+        scope = parent(ns);
+    }
+    let mut scope = Some(prefix);
+    while let Some(ns) = scope {
+        let candidate = join(ns, name);
+        if decls.dropped.contains(&candidate) {
+            return Err(None);
+        }
+        if let Some(diagnostic) = compiled_out(reference, &candidate, decls) {
+            return Err(Some(diagnostic));
+        }
+        scope = parent(ns);
+    }
+    Err(Some(
+        Diagnostic::error(
+            Code::UnresolvedReference,
+            reference.span,
+            format!("cannot find `{}` in this scope", name),
+        )
+        .with_note(format!(
+            "to refer to a component from the base game, write `.{}`",
+            name
+        )),
+    ))
+}
+
+/// The diagnostic for `reference`, if the component `full`
+/// it would have named was compiled out.
+fn compiled_out(reference: &Spanned<DefKey>, full: &str, decls: &Declarations) -> Option<Diagnostic> {
+    let predicate = decls.compiled_out.get(full)?;
+    Some(
+        Diagnostic::error(
+            Code::UnresolvedReference,
+            reference.span,
+            format!("`{}` is compiled out", full),
+        )
+        .with_label(predicate.span, format!("`{}` is only compiled when this holds", full))
+        .with_note(format!("the flags passed with `--cfg` do not satisfy `{}`", predicate.item)),
+    )
+}
@@ -9,6 +9,7 @@ use anyhow::{Result, bail};
 use walkdir::DirEntry;
 use std::{path::PathBuf, collections::HashMap};
 use clap::{Parser, Subcommand, ValueEnum};
+use mothlib::lantern::lir;
 
 /// Read files from a directory and convert them
 /// to a vector of common dictionary types.
@@ -133,12 +134,13 @@ enum Commands {
     },
     /// Convert a stream of data from the standard input from Lantern IR to a specified format.
     Write {
-        /// The path to write data to. Laidlaw may write files or a directory tree depending on the input data.
-        /// If no value is specified, Laidlaw will write its data on the standard input.
-        dst: Option<PathBuf>, 
-
         /// The format to write the translated data in.
-        format: SupportedFormat
+        #[arg(value_enum)]
+        format: SupportedFormat,
+
+        /// The path to write data to. Laidlaw may write files or a directory tree depending on the input data.
+        /// If no value is specified, Laidlaw will write its data on the standard output.
+        dst: Option<PathBuf>,
     },
     /// Convert a stream of data from one arbitrary source to another.
     Translate {
@@ -195,7 +197,7 @@ async fn main() -> Result<()> {
 
     match cli.command {
         Commands::Read { src, format } => read(src, format),
-        Commands::Write { dst, format } => write(dst, Some(format)),
+        Commands::Write { format, dst } => write(dst, Some(format)),
         Commands::Translate { src, dst, from, to } => translate(src, from, dst, Some(to)),
     }
     /*
@@ -234,12 +236,25 @@ fn read(src: Option<PathBuf>, src_demand: Option<SupportedFormat>) -> Result<()>
 fn write(dst: Option<PathBuf>, dst_demand: Option<SupportedFormat>) -> Result<()> {
     event!(Level::DEBUG, 
         action = "write",
-        dst = format!("{:?}", dst.map(|p| p.into_os_string().into_string().expect("Invalid input path!"))), 
+        dst = format!("{:?}", dst.clone().map(|p| p.into_os_string().into_string().expect("Invalid input path!"))), 
         dst_format = format!("{dst_demand:?}"), 
         "Laidlaw has been Invoked"
     );
 
-    Ok(())
+    // Producers such as `crucible` send a single LIR or LIRC file.
+    let mut stdin = std::io::stdin().lock();
+    let lantern = match lir::read_frame(&mut stdin)? {
+        Some(file) => lir::decode(&file)?,
+        None => bail!("No Lantern IR was sent on the standard input"),
+    };
+    if lir::read_frame(&mut stdin)?.is_some() {
+        bail!("Only a single Lantern IR file can be written at a time");
+    }
+
+    match dst_demand {
+        Some(SupportedFormat::JSON) | None => serialize::serialize_lantern(dst.as_deref(), &lantern),
+        Some(format) => bail!("Writing {format:?} is not supported yet"),
+    }
 }
 
 fn translate(src: Option<PathBuf>, src_demand: Option<SupportedFormat>, dst: Option<PathBuf>, dst_demand: Option<SupportedFormat>) -> Result<()> {
@@ -3,7 +3,9 @@ use tokio::task::JoinHandle;
 use tokio_stream::StreamExt;
 use tracing::{Level, event};
 use anyhow::{anyhow, Result, bail};
+use mothlib::lantern::{vanilla, Lantern};
 use std::collections::HashMap;
+use std::io::Write;
 use std::fmt::Display;
 use std::path::{Path, PathBuf};
 
@@ -55,6 +57,40 @@ pub async fn serialize_sources(mod_root: &Path, data: Vec<crate::Record>, namesp
     Ok(())
 }
 
+/// Write a whole mod as the JSON read by Cultist Simulator.
+///
+/// Each kind of component goes in its own file in `dst`, such as `<DST>/recipes.json`,
+/// holding an object with that kind as its only key. Without `dst`, the whole mod is
+/// written to the standard output as a single object.
+pub fn serialize_lantern(dst: Option<&Path>, lantern: &Lantern) -> Result<()> {
+    let json = vanilla::to_json(lantern);
+    let dst = match dst {
+        Some(dst) => dst,
+        None => {
+            let mut stdout = std::io::stdout().lock();
+            serde_json::to_writer_pretty(&mut stdout, &json)?;
+            writeln!(stdout)?;
+            return Ok(());
+        }
+    };
+
+    std::fs::create_dir_all(dst)?;
+    let kinds = json.as_object().expect("A mod is always written as an object");
+    for (kind, items) in kinds {
+        if items.as_array().map_or(true, |items| items.is_empty()) {
+            continue;
+        }
+        let path = dst.join(format!("{kind}.json"));
+        event!(Level::DEBUG, path = path.display().to_string(), "Writing file.");
+        let file = serde_json::json!({ kind: items });
+        std::fs::write(&path, serde_json::to_string_pretty(&file)?)?;
+    }
+
+    event!(Level::INFO, "Successfully wrote all records.");
+
+    Ok(())
+}
+
 /// Convert a path to a single filename describing the compiled object.
 /// 
 /// `<MOD_ROOT>/src/content/card/fragment/edge.json` becomes `<MOD_ROOT>/content/card.fragment.edge.json`. 
@@ -32,13 +32,13 @@ WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 */
 use nom::{
   branch::alt,
-  bytes::complete::{escaped, tag, take_while},
-  character::complete::{alphanumeric1 as alphanumeric, char, one_of},
-  combinator::{cut, map, opt, value},
+  bytes::complete::{escaped_transform, is_not, tag, take_while, take_while_m_n},
+  character::complete::char,
+  combinator::{cut, map, map_opt, opt, value},
   error::{context, ContextError, ParseError},
   multi::separated_list0,
-  number::complete::double,
-  sequence::{delimited, preceded, separated_pair, terminated},
+  number::complete::recognize_float,
+  sequence::{preceded, separated_pair, terminated},
   IResult,
 };
 use serde::{Serialize, Deserialize};
@@ -77,12 +77,50 @@ fn sp<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, &'a str, E> {
 /// the input type, and work directly with `&[u8]` or any other type that
 /// implements the required traits.
 ///
-/// Finally, we can see here that the input and output type are both `&str`
-/// with the same lifetime tag. This means that the produced value is a subslice
-/// of the input data. and there is no allocation needed. This is the main idea
-/// behind nom's performance.
-fn parse_str<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, &'a str, E> {
-  escaped(alphanumeric, '\\', one_of("\"n\\"))(i)
+/// This parser recognizes the interior of a string, replacing each of
+/// the escape sequences JSON allows with the character it stands for.
+/// Since that means the output can differ from the input, it has to
+/// allocate a new `String` rather than returning a subslice.
+fn parse_str<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, String, E> {
+  fn hex<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, u32, E> {
+    map_opt(
+      preceded(char('u'), take_while_m_n(4, 4, |c: char| c.is_ascii_hexdigit())),
+      |hex: &str| u32::from_str_radix(hex, 16).ok(),
+    )(i)
+  }
+  // A character outside of the Basic Multilingual Plane is escaped
+  // as a pair of surrogates, which only mean something together.
+  fn unicode<'a, E: ParseError<&'a str>>(i: &'a str) -> IResult<&'a str, char, E> {
+    let (rest, first) = hex(i)?;
+    if (0xD800..0xDC00).contains(&first) {
+      let low = map_opt(preceded(char('\\'), hex), |low| match low {
+        0xDC00..=0xDFFF => Some(0x10000 + ((first - 0xD800) << 10) + (low - 0xDC00)),
+        _ => None,
+      });
+      return map_opt(low, char::from_u32)(rest);
+    }
+    match char::from_u32(first) {
+      Some(c) => Ok((rest, c)),
+      None => Err(nom::Err::Error(E::from_error_kind(i, nom::error::ErrorKind::MapOpt))),
+    }
+  }
+
+  let (i, s) = opt(escaped_transform(
+    is_not("\"\\"),
+    '\\',
+    alt((
+      value('"', char('"')),
+      value('\\', char('\\')),
+      value('/', char('/')),
+      value('\u{8}', char('b')),
+      value('\u{c}', char('f')),
+      value('\n', char('n')),
+      value('\r', char('r')),
+      value('\t', char('t')),
+      unicode,
+    )),
+  ))(i)?;
+  Ok((i, s.unwrap_or_default()))
 }
 
 /// `tag(string)` generates a parser that recognizes the argument string.
@@ -107,8 +145,9 @@ fn boolean<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, bool,
   alt((parse_true, parse_false))(input)
 }
 
+/// Crucible also spells `null` as `NULL`.
 fn null<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, (), E> {
-  value((), tag("null"))(input)
+  value((), alt((tag("null"), tag("NULL"))))(input)
 }
 
 /// this parser combines the previous `parse_str` parser, that recognizes the
@@ -124,10 +163,10 @@ fn null<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, (), E> {
 /// error chain (to indicate which parser had an error)
 fn string<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
   i: &'a str,
-) -> IResult<&'a str, &'a str, E> {
+) -> IResult<&'a str, String, E> {
   context(
-    "string",
-    preceded(char('\"'), cut(terminated(parse_str, char('\"')))),
+    "a string",
+    preceded(char('\"'), cut(terminated(parse_str, context("a closing `\"`", char('\"'))))),
   )(i)
 }
 
@@ -139,12 +178,12 @@ fn array<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
   i: &'a str,
 ) -> IResult<&'a str, Vec<Value>, E> {
   context(
-    "array",
+    "an array",
     preceded(
       char('['),
       cut(terminated(
         separated_list0(preceded(sp, char(',')), json_value),
-        preceded(sp, char(']')),
+        preceded(sp, context("`,` or `]`", char(']'))),
       )),
     ),
   )(i)
@@ -152,11 +191,11 @@ fn array<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
 
 fn key_value<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
   i: &'a str,
-) -> IResult<&'a str, (&'a str, Value), E> {
+) -> IResult<&'a str, (String, Value), E> {
   separated_pair(
     preceded(sp, string),
-    cut(preceded(sp, char(':'))),
-    json_value,
+    cut(preceded(sp, context("`:`", char(':')))),
+    cut(context("a value", json_value)),
   )(i)
 }
 
@@ -164,20 +203,17 @@ fn hash<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
   i: &'a str,
 ) -> IResult<&'a str, HashMap<String, Value>, E> {
   context(
-    "map",
+    "an object",
     preceded(
       char('{'),
       cut(terminated(
         map(
           separated_list0(preceded(sp, char(',')), key_value),
           |tuple_vec| {
-            tuple_vec
-              .into_iter()
-              .map(|(k, v)| (String::from(k), v))
-              .collect()
+            tuple_vec.into_iter().collect()
           },
         ),
-        preceded(sp, char('}')),
+        preceded(sp, context("`,` or `}`", char('}'))),
       )),
     ),
   )(i)
@@ -192,25 +228,20 @@ fn json_value<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
     alt((
       map(hash, Value::Object),
       map(array, Value::Array),
-      map(string, |s| Value::Str(String::from(s))),
-      map(double, Value::Num),
+      map(string, Value::Str),
+      // Unlike nom's `double`, this does not accept `inf` or `nan`,
+      // which have no JSON representation.
+      map(map_opt(recognize_float, |s: &str| s.parse().ok()), Value::Num),
       map(boolean, Value::Boolean),
       map(null, |_| Value::Null),
     )),
   )(i)
 }
 
-/// the root element of a JSON parser is either an object or an array
+/// the root element may be any JSON value, so that scalars
+/// can be written just as easily as objects and arrays
 pub fn parse<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
   i: &'a str,
 ) -> IResult<&'a str, Value, E> {
-  delimited(
-    sp,
-    alt((
-      map(hash, Value::Object),
-      map(array, Value::Array),
-      map(null, |_| Value::Null),
-    )),
-    opt(sp),
-  )(i)
-}
\ No newline at end of file
+  terminated(json_value, opt(sp))(i)
+}
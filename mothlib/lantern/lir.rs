@@ -0,0 +1,139 @@
+//! The LIR file format, and the framed stream used
+//! to send LIR files between Mothtools programs.
+//!
+//! A `.lir` file is the magic bytes `LIR.\r\n` followed by
+//! the [Lantern] serialized as JSON. A `.lirc` file is the
+//! magic bytes `LIRC\r\n` followed by a whole `.lir` file,
+//! magic bytes included, compressed with Brotli.
+//!
+//! When written to a stream, each file is wrapped in a frame:
+//!
+//! ```text
+//! SOH | header size: u32 | data size: u32 | major: u8 | minor: u8 | patch: u16 | STX | file | ETX
+//! ```
+//!
+//! The header size counts the bytes from SOH to STX, and the data
+//! size counts the bytes from STX to ETX, both inclusive. Every
+//! integer is big-endian. After the last frame comes a single EOT.
+
+use std::io::{Read, Write};
+
+use anyhow::{bail, Result};
+
+use super::Lantern;
+
+pub const LIR_MAGIC: &[u8; 6] = b"LIR.\r\n";
+pub const LIRC_MAGIC: &[u8; 6] = b"LIRC\r\n";
+
+/// The version of the stream protocol written by [write_frame].
+pub const PROTOCOL_VERSION: (u8, u8, u16) = (0, 1, 0);
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const ETX: u8 = 0x03;
+const EOT: u8 = 0x04;
+
+/// SOH, the two sizes, the version and STX.
+const HEADER_SIZE: u32 = 1 + 4 + 4 + 1 + 1 + 2 + 1;
+
+/// Serializes `lantern` as a `.lirc` file if `compress`
+/// is set, or as a `.lir` file otherwise.
+pub fn encode(lantern: &Lantern, compress: bool) -> Result<Vec<u8>> {
+    // Going through a `serde_json::Value` sorts every object
+    // by key, so the same mod always produces the same file.
+    let value = serde_json::to_value(lantern)?;
+    let mut lir = LIR_MAGIC.to_vec();
+    serde_json::to_writer(&mut lir, &value)?;
+    if !compress {
+        return Ok(lir);
+    }
+
+    let mut lirc = LIRC_MAGIC.to_vec();
+    {
+        let mut writer = brotli::CompressorWriter::new(&mut lirc, 4096, 11, 22);
+        writer.write_all(&lir)?;
+    }
+    Ok(lirc)
+}
+
+/// Reads a `.lir` or `.lirc` file, telling them
+/// apart by their magic bytes.
+pub fn decode(file: &[u8]) -> Result<Lantern> {
+    if let Some(compressed) = file.strip_prefix(LIRC_MAGIC.as_slice()) {
+        let mut lir = Vec::new();
+        brotli::Decompressor::new(compressed, 4096).read_to_end(&mut lir)?;
+        if lir.starts_with(LIRC_MAGIC) {
+            bail!("A LIRC file may not contain another LIRC file");
+        }
+        return decode(&lir);
+    }
+    match file.strip_prefix(LIR_MAGIC.as_slice()) {
+        Some(json) => Ok(serde_json::from_slice(json)?),
+        None => bail!("Not a LIR or LIRC file"),
+    }
+}
+
+/// Writes `file` to `writer` as a single frame of the stream protocol.
+pub fn write_frame<W: Write>(writer: &mut W, file: &[u8]) -> Result<()> {
+    let data_size = match u32::try_from(file.len() + 2) {
+        Ok(size) => size,
+        Err(_) => bail!("A LIR file of {} bytes is too large to send", file.len()),
+    };
+    let (major, minor, patch) = PROTOCOL_VERSION;
+
+    writer.write_all(&[SOH])?;
+    writer.write_all(&HEADER_SIZE.to_be_bytes())?;
+    writer.write_all(&data_size.to_be_bytes())?;
+    writer.write_all(&[major, minor])?;
+    writer.write_all(&patch.to_be_bytes())?;
+    writer.write_all(&[STX])?;
+    writer.write_all(file)?;
+    writer.write_all(&[ETX])?;
+    Ok(())
+}
+
+/// Reads the next frame of the stream protocol from `reader`,
+/// returning the file it holds, or `None` once the stream ends.
+pub fn read_frame<R: Read>(reader: &mut R) -> Result<Option<Vec<u8>>> {
+    let mut start = [0u8];
+    reader.read_exact(&mut start)?;
+    match start[0] {
+        EOT => return Ok(None),
+        SOH => (),
+        byte => bail!("Expected the start of a frame, found byte {:#04x}", byte),
+    }
+
+    let mut sizes = [0u8; 8];
+    reader.read_exact(&mut sizes)?;
+    let header_size = u32::from_be_bytes([sizes[0], sizes[1], sizes[2], sizes[3]]);
+    let data_size = u32::from_be_bytes([sizes[4], sizes[5], sizes[6], sizes[7]]);
+    if header_size < HEADER_SIZE || data_size < 2 {
+        bail!("A frame's header of {} bytes or data of {} bytes is too small", header_size, data_size);
+    }
+
+    // Later versions may add to the header, so whatever
+    // follows the version is skipped up to STX.
+    let mut header = vec![0u8; (header_size - 9) as usize];
+    reader.read_exact(&mut header)?;
+    let (major, _, _) = PROTOCOL_VERSION;
+    if header[0] != major {
+        bail!("Frames of protocol version {}.x can't be read, only {}.x", header[0], major);
+    }
+    if header.last() != Some(&STX) {
+        bail!("A frame's header must end with STX");
+    }
+
+    let mut data = vec![0u8; (data_size - 1) as usize];
+    reader.read_exact(&mut data)?;
+    if data.pop() != Some(ETX) {
+        bail!("A frame's data must end with ETX");
+    }
+    Ok(Some(data))
+}
+
+/// Ends a stream of frames.
+pub fn write_end<W: Write>(writer: &mut W) -> Result<()> {
+    writer.write_all(&[EOT])?;
+    writer.flush()?;
+    Ok(())
+}
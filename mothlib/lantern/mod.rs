@@ -4,6 +4,8 @@ use either::Either;
 use serde::{Serialize, Deserialize};
 
 pub mod json;
+pub mod lir;
+pub mod vanilla;
 
 /// An ID referencing an in-game component.
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
@@ -20,13 +22,16 @@ impl Display for DefKey {
 /// 
 /// The Lantern struct represents an entire
 /// mod. 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct Lantern {
     /// List of all attributes that apply to the whole mod.
     attributes: Vec<Attribute>,
     /// List of metadata for each namespace.
     /// The keys are the full path to the namespace.
     namespaces: HashMap<DefKey, NamespaceMeta>,
+    /// The attributes applied to each component,
+    /// keyed by the component's full id.
+    component_attributes: HashMap<DefKey, Vec<Attribute>>,
 
     aspects: HashMap<DefKey, Aspect>,
     cards:   HashMap<DefKey, Card>,
@@ -48,10 +53,100 @@ pub struct Attribute{ pub key: DefKey, pub value: Option<json::Value> }
 /// which describe its position within the
 /// namespace hierarchy, and a collection
 /// of attributes that have been applied to it.
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct NamespaceMeta {
-    components: Vec<DefKey>,
-    attributes: Vec<Attribute>,
+    /// The full ids of the components declared
+    /// directly inside this namespace.
+    pub components: Vec<DefKey>,
+    pub attributes: Vec<Attribute>,
+}
+
+impl Lantern {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn attributes(&self) -> &[Attribute] {
+        &self.attributes
+    }
+
+    pub fn attributes_mut(&mut self) -> &mut Vec<Attribute> {
+        &mut self.attributes
+    }
+
+    pub fn namespaces(&self) -> &HashMap<DefKey, NamespaceMeta> {
+        &self.namespaces
+    }
+
+    /// The metadata for the namespace at `path`,
+    /// which is created if it does not exist yet.
+    pub fn namespace_mut(&mut self, path: DefKey) -> &mut NamespaceMeta {
+        self.namespaces.entry(path).or_default()
+    }
+
+    pub fn component_attributes(&self) -> &HashMap<DefKey, Vec<Attribute>> {
+        &self.component_attributes
+    }
+
+    pub fn component_attributes_mut(&mut self) -> &mut HashMap<DefKey, Vec<Attribute>> {
+        &mut self.component_attributes
+    }
+
+    pub fn aspects(&self) -> &HashMap<DefKey, Aspect> {
+        &self.aspects
+    }
+
+    pub fn aspects_mut(&mut self) -> &mut HashMap<DefKey, Aspect> {
+        &mut self.aspects
+    }
+
+    pub fn cards(&self) -> &HashMap<DefKey, Card> {
+        &self.cards
+    }
+
+    pub fn cards_mut(&mut self) -> &mut HashMap<DefKey, Card> {
+        &mut self.cards
+    }
+
+    pub fn decks(&self) -> &HashMap<DefKey, Deck> {
+        &self.decks
+    }
+
+    pub fn decks_mut(&mut self) -> &mut HashMap<DefKey, Deck> {
+        &mut self.decks
+    }
+
+    pub fn recipes(&self) -> &HashMap<DefKey, Recipe> {
+        &self.recipes
+    }
+
+    pub fn recipes_mut(&mut self) -> &mut HashMap<DefKey, Recipe> {
+        &mut self.recipes
+    }
+
+    pub fn verbs(&self) -> &HashMap<DefKey, Verb> {
+        &self.verbs
+    }
+
+    pub fn verbs_mut(&mut self) -> &mut HashMap<DefKey, Verb> {
+        &mut self.verbs
+    }
+
+    pub fn legacies(&self) -> &HashMap<DefKey, Legacy> {
+        &self.legacies
+    }
+
+    pub fn legacies_mut(&mut self) -> &mut HashMap<DefKey, Legacy> {
+        &mut self.legacies
+    }
+
+    pub fn endings(&self) -> &HashMap<DefKey, Ending> {
+        &self.endings
+    }
+
+    pub fn endings_mut(&mut self) -> &mut HashMap<DefKey, Ending> {
+        &mut self.endings
+    }
 }
 
 /// Aspects are one of the two variants of the type
@@ -200,6 +295,8 @@ pub struct Card {
     /// The list of [Xtrigger]s to run on this card when their
     /// conditions are met.
     pub xtriggers: Vec<Xtrigger>,
+    /// Any other JSON members not otherwise specified in this struct.
+    pub others: HashMap<DefKey, json::Value>,
 }
 
 
@@ -241,6 +338,8 @@ pub struct Deck {
     /// deck, these values are ignored.
     pub cards: Vec<(DefKey, Option<String>)>,
     pub is_portal_deck: bool,
+    /// Any other JSON members not otherwise specified in this struct.
+    pub others: HashMap<DefKey, json::Value>,
 }
 
 /// Defines the types of colors a recipe's
@@ -515,6 +614,10 @@ pub struct Recipe {
     /// number of times and added to the element list at 
     /// the conclusion of the recipe.
     pub draws: HashMap<DefKey, i32>,
+    /// A deck belonging to this recipe alone, which is drawn
+    /// from the given number of times along with [Recipe::draws].
+    /// The deck has the same id as the recipe.
+    pub internal_deck: Option<(Deck, i32)>,
     /// A list of all [Mutation]s to apply to the
     /// elements in this recipe.
     pub mutations: Vec<Mutation>,
@@ -536,7 +639,9 @@ pub struct Recipe {
     /// Audiovisual style for the warmup circle.
     pub style: WarmupStyle,
     /// All the possible [Branch]es that this element could take.
-    pub branches: Vec<Branch>
+    pub branches: Vec<Branch>,
+    /// Any other JSON members not otherwise specified in this struct.
+    pub others: HashMap<DefKey, json::Value>,
 }
 
 /// A place to put a card, which could be used in
@@ -612,6 +717,32 @@ pub struct Verb {
     /// additional slots that appear
     /// when they are inserted.
     pub slot: Option<Slot>,
+    /// Any other JSON members not otherwise specified in this struct.
+    pub others: HashMap<DefKey, json::Value>,
+}
+
+/// A Legacy is a character the player can
+/// begin a new game as, either from the
+/// main menu or after an ending.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Legacy {
+    /// This is the in-game representation for
+    /// this legacy, e.g. "apostlelantern".
+    pub id: DefKey,
+    /// Any JSON members describing this legacy.
+    pub others: HashMap<DefKey, json::Value>,
+}
+
+/// An Ending is shown when a recipe ends the
+/// game, and decides which legacies the
+/// player may continue as.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Ending {
+    /// This is the in-game representation for
+    /// this ending, e.g. "deathofthebody".
+    pub id: DefKey,
+    /// Any JSON members describing this ending.
+    pub others: HashMap<DefKey, json::Value>,
 }
 
 /// XTriggers allow a mutated aspect to modify itself. 
@@ -0,0 +1,881 @@
+//! Converting between Lantern and the JSON read by Cultist Simulator.
+//!
+//! Each kind of component becomes a list under the key the game
+//! expects (`elements`, `recipes`, `decks`, `verbs`, `legacies` and
+//! `endings`), sorted by id. Members left at their defaults are
+//! omitted, and each component's `others` are written last, so
+//! they take precedence over anything Lantern models itself.
+//!
+//! Reading JSON goes the other way. Any member which Lantern cannot
+//! model exactly, such as a requirement on another element's amount,
+//! is kept in the component's `others` instead, so that writing the
+//! result back out produces the same JSON.
+
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use either::Either;
+use serde_json::{json, Map, Value};
+
+use super::*;
+
+/// Converts a whole mod into a single JSON object
+/// holding one list for each kind of component.
+pub fn to_json(lantern: &Lantern) -> Value {
+    let mut elements: Vec<Value> = lantern.aspects().values().map(aspect).collect();
+    elements.extend(lantern.cards().values().map(card));
+
+    let mut out = Map::new();
+    out.insert("elements".to_owned(), sorted(elements));
+    out.insert("recipes".to_owned(), sorted(lantern.recipes().values().map(recipe).collect()));
+    out.insert("decks".to_owned(), sorted(lantern.decks().values().map(deck).collect()));
+    out.insert("verbs".to_owned(), sorted(lantern.verbs().values().map(verb).collect()));
+    out.insert("legacies".to_owned(), sorted(lantern.legacies().values().map(|l| component(json!({ "id": l.id.0 }), &l.others)).collect()));
+    out.insert("endings".to_owned(), sorted(lantern.endings().values().map(|e| component(json!({ "id": e.id.0 }), &e.others)).collect()));
+    Value::Object(out)
+}
+
+/// Converts a [json::Value] into a `serde_json` value. Numbers
+/// without a fractional part are written as integers, since the
+/// game rejects `2.0` in most places that expect a count.
+pub fn value(json: &json::Value) -> Value {
+    match json {
+        json::Value::Null => Value::Null,
+        json::Value::Str(s) => Value::String(s.clone()),
+        json::Value::Boolean(b) => Value::Bool(*b),
+        json::Value::Num(n) if n.fract() == 0.0 && n.abs() < 9_007_199_254_740_992.0 => Value::from(*n as i64),
+        json::Value::Num(n) => serde_json::Number::from_f64(*n).map_or(Value::Null, Value::Number),
+        json::Value::Array(items) => Value::Array(items.iter().map(value).collect()),
+        json::Value::Object(members) => Value::Object(members.iter().map(|(k, v)| (k.clone(), value(v))).collect()),
+    }
+}
+
+fn sorted(mut items: Vec<Value>) -> Value {
+    items.sort_by(|a, b| a["id"].as_str().cmp(&b["id"].as_str()));
+    Value::Array(items)
+}
+
+/// Drops members left at their defaults from `modelled`,
+/// then adds the component's unmodelled members.
+fn component(modelled: Value, others: &HashMap<DefKey, json::Value>) -> Value {
+    let mut out = match modelled {
+        Value::Object(members) => members,
+        _ => unreachable!("components are always objects"),
+    };
+    out.retain(|_, v| !is_default(v));
+    for (key, v) in others {
+        out.insert(key.0.clone(), value(v));
+    }
+    Value::Object(out)
+}
+
+fn is_default(value: &Value) -> bool {
+    match value {
+        Value::Null | Value::Bool(false) => true,
+        Value::String(s) => s.is_empty(),
+        Value::Array(a) => a.is_empty(),
+        Value::Object(o) => o.is_empty(),
+        Value::Number(_) | Value::Bool(true) => false,
+    }
+}
+
+fn amounts<'a, N: Into<Value> + Copy + 'a>(items: impl IntoIterator<Item = (&'a DefKey, &'a N)>) -> Value {
+    Value::Object(items.into_iter().map(|(k, n)| (k.0.clone(), (*n).into())).collect())
+}
+
+fn operation(op: &ValueOperation) -> Value {
+    match op {
+        ValueOperation::Set(n) => Value::from(*n),
+        ValueOperation::Add(n) => Value::from(*n),
+    }
+}
+
+fn induces(induces: &Option<(DefKey, Probability)>) -> Value {
+    match induces {
+        Some((id, chance)) => json!([{ "id": id.0, "chance": **chance }]),
+        None => Value::Null,
+    }
+}
+
+fn xtriggers(xtriggers: &[Xtrigger]) -> Value {
+    let mut out: Map<String, Value> = Map::new();
+    for xtrigger in xtriggers {
+        let (catalyst, morph) = match xtrigger {
+            Xtrigger::Transform { catalyst, transforms_to, amount, chance } => {
+                (catalyst, json!({ "id": transforms_to.0, "morpheffect": "transform", "level": amount, "chance": **chance }))
+            }
+            Xtrigger::Spawn { catalyst, creates, amount, chance } => {
+                (catalyst, json!({ "id": creates.0, "morpheffect": "spawn", "level": amount, "chance": **chance }))
+            }
+            Xtrigger::Mutate { catalyst, adds_to_catalyst, amount, chance } => {
+                (catalyst, json!({ "id": adds_to_catalyst.0, "morpheffect": "mutate", "level": amount, "chance": **chance }))
+            }
+        };
+        if let Value::Array(morphs) = out.entry(catalyst.0.clone()).or_insert_with(|| json!([])) {
+            morphs.push(morph);
+        }
+    }
+    Value::Object(out)
+}
+
+fn slot(slot: &Slot, verb: Option<&DefKey>) -> Value {
+    let mut required = Map::new();
+    let mut forbidden = Map::new();
+    for filter in &slot.requirements {
+        match filter {
+            SlotFilter::Accept { element, amount } => required.insert(element.0.clone(), Value::from(*amount)),
+            SlotFilter::Forbid { element, amount } => forbidden.insert(element.0.clone(), Value::from(*amount)),
+        };
+    }
+    component(
+        json!({
+            "id": slot.id.0,
+            "label": slot.label,
+            "description": slot.description,
+            "actionId": verb.map(|v| v.0.clone()),
+            "required": required,
+            "forbidden": forbidden,
+            "consumes": slot.consumes,
+            "greedy": slot.greedy,
+        }),
+        &HashMap::new(),
+    )
+}
+
+fn aspect(aspect: &Aspect) -> Value {
+    component(
+        json!({
+            "id": aspect.id.0,
+            "label": aspect.label,
+            "description": aspect.description,
+            "icon": aspect.icon,
+            "verbicon": aspect.verbicon,
+            "isAspect": true,
+            "isHidden": aspect.hidden,
+            "noartneeded": aspect.hidden,
+            "decayTo": aspect.decays_to.as_ref().map(|d| d.0.clone()),
+            "induces": induces(&aspect.induces),
+            "xtriggers": xtriggers(&aspect.xtriggers),
+        }),
+        &aspect.others,
+    )
+}
+
+fn card(card: &Card) -> Value {
+    let mut slots: Vec<Value> = Vec::new();
+    let mut verbs: Vec<&DefKey> = card.slots.keys().collect();
+    verbs.sort_by(|a, b| a.0.cmp(&b.0));
+    for verb in verbs {
+        slots.extend(card.slots[verb].iter().map(|s| slot(s, Some(verb))));
+    }
+
+    component(
+        json!({
+            "id": card.id.0,
+            "label": card.label,
+            "description": card.description,
+            "icon": card.icon,
+            "verbicon": card.verbicon,
+            "isHidden": card.hidden,
+            "noartneeded": card.hidden,
+            "aspects": amounts(&card.aspects),
+            "decayTo": card.decays_to.as_ref().map(|d| d.0.clone()),
+            "lifetime": card.lifetime.filter(|l| *l > 0),
+            "resaturate": card.resaturate,
+            "unique": card.unique,
+            "uniquenessgroup": card.uniqueness_group.as_ref().map(|g| g.0.clone()),
+            "induces": induces(&card.induces),
+            "slots": slots,
+            "xtriggers": xtriggers(&card.xtriggers),
+        }),
+        &card.others,
+    )
+}
+
+fn deck(deck: &Deck) -> Value {
+    let messages: Map<String, Value> = deck
+        .cards
+        .iter()
+        .filter_map(|(card, message)| message.as_ref().map(|m| (card.0.clone(), Value::from(m.clone()))))
+        .collect();
+    component(
+        json!({
+            "id": deck.id.0,
+            "label": deck.label,
+            "description": deck.description,
+            "spec": deck.cards.iter().map(|(card, _)| card.0.clone()).collect::<Vec<_>>(),
+            "defaultcard": deck.default.as_ref().map(|d| d.0.clone()),
+            "resetonexhaustion": deck.default.is_none(),
+            "drawmessages": messages,
+        }),
+        &deck.others,
+    )
+}
+
+/// A recipe's own deck, which the game gives the recipe's id.
+fn internal_deck(internal: &Deck, draws: i32) -> Value {
+    let mut value = deck(internal);
+    if let Some(members) = value.as_object_mut() {
+        members.remove("id");
+        members.insert("draws".to_owned(), Value::from(draws));
+    }
+    value
+}
+
+fn verb(verb: &Verb) -> Value {
+    component(
+        json!({
+            "id": verb.id.0,
+            "label": verb.label,
+            "description": verb.description,
+            "slot": verb.slot.as_ref().map(|s| slot(s, None)),
+        }),
+        &verb.others,
+    )
+}
+
+/// Splits requirements into the three lists the game keeps them in.
+fn requirements(requirements: &[RecipeRequirement]) -> (Value, Value, Value) {
+    let mut lists = (Map::new(), Map::new(), Map::new());
+    for requirement in requirements {
+        let (list, element, amount) = match requirement {
+            RecipeRequirement::Basic { element, amount } => (&mut lists.0, element, amount),
+            RecipeRequirement::Table { element, amount } => (&mut lists.1, element, amount),
+            RecipeRequirement::Extant { element, amount } => (&mut lists.2, element, amount),
+        };
+        let amount = match amount {
+            Either::Left(op) => operation(op),
+            Either::Right(other) => Value::from(other.0.clone()),
+        };
+        list.insert(element.0.clone(), amount);
+    }
+    (Value::Object(lists.0), Value::Object(lists.1), Value::Object(lists.2))
+}
+
+fn branch(target: &DefKey, condition: &BranchCondition) -> Map<String, Value> {
+    let (reqs, table, extant) = requirements(&condition.requirements);
+    let mut out = Map::new();
+    out.insert("id".to_owned(), Value::from(target.0.clone()));
+    out.insert("chance".to_owned(), condition.chance.map_or(Value::Null, |c| Value::from(*c)));
+    out.insert("requirements".to_owned(), reqs);
+    out.insert("tablereqs".to_owned(), table);
+    out.insert("extantreqs".to_owned(), extant);
+    out
+}
+
+fn recipe(recipe: &Recipe) -> Value {
+    let (reqs, table, extant) = requirements(&recipe.requirements);
+    let mut linked = Vec::new();
+    let mut alt = Vec::new();
+    for b in &recipe.branches {
+        match b {
+            Branch::Link { target, condition } => linked.push(component(Value::Object(branch(target, condition)), &HashMap::new())),
+            Branch::Goto { target, condition, action } => {
+                let mut out = branch(target, condition);
+                out.insert("additional".to_owned(), Value::from(action.is_some()));
+                // An expulsion takes up to `limit` cards matching
+                // any of the filter's aspects out of the recipe.
+                if let Some(SpawningKind::Expel(elements)) = action {
+                    let limit: u32 = elements.iter().map(|(_, n)| n).sum();
+                    let filter: Map<String, Value> = elements.iter().map(|(k, n)| (k.0.clone(), Value::from(*n))).collect();
+                    out.insert("expulsion".to_owned(), json!({ "limit": limit, "filter": filter }));
+                }
+                alt.push(component(Value::Object(out), &HashMap::new()));
+            }
+        }
+    }
+    let flavour = match recipe.style {
+        WarmupStyle::Grand => Some("Grand"),
+        WarmupStyle::Mellancholy => Some("Melancholy"),
+        WarmupStyle::Pale => Some("Pale"),
+        WarmupStyle::Vile => Some("Vile"),
+        WarmupStyle::None | WarmupStyle::Important => None,
+    };
+    let mutations: Vec<Value> = recipe
+        .mutations
+        .iter()
+        .map(|m| json!({
+            "filter": m.id.0,
+            "mutate": m.aspect.0,
+            "level": operation(&m.operation),
+            "additive": matches!(m.operation, ValueOperation::Add(_)),
+        }))
+        .collect();
+
+    component(
+        json!({
+            "id": recipe.id.0,
+            "actionId": recipe.verb.0,
+            "label": recipe.label,
+            "startdescription": recipe.description,
+            "description": recipe.end_description,
+            "craftable": recipe.craftable,
+            "hintonly": recipe.hint_only,
+            "warmup": recipe.warmup,
+            "maxexecutions": Some(recipe.max_executions).filter(|n| *n > 0),
+            "requirements": reqs,
+            "tablereqs": table,
+            "extantreqs": extant,
+            "effects": Value::Object(recipe.effects.iter().map(|(k, op)| (k.0.clone(), operation(op))).collect()),
+            "aspects": Value::Object(recipe.aspects.iter().map(|(k, op)| (k.0.clone(), operation(op))).collect()),
+            "purge": amounts(&recipe.purge),
+            "deckeffects": amounts(&recipe.draws),
+            "internaldeck": recipe.internal_deck.as_ref().map(|(internal, draws)| internal_deck(internal, *draws)),
+            "mutations": mutations,
+            "haltverb": recipe.halt.as_ref().map(amounts),
+            "deleteverb": recipe.delete.as_ref().map(amounts),
+            "burnimage": recipe.burn,
+            "portaleffect": recipe.portal,
+            "ending": recipe.ending.as_ref().map(|e| e.0.clone()),
+            "signalEndingFlavour": flavour,
+            "signalimportantloop": matches!(recipe.style, WarmupStyle::Important),
+            "slots": recipe.slot.iter().map(|s| slot(s, None)).collect::<Vec<_>>(),
+            "linked": linked,
+            "alt": alt,
+        }),
+        &recipe.others,
+    )
+}
+
+/// Reads a file of the game's JSON into `lantern`. Components
+/// replace any with the same id that were read before.
+pub fn read_json(lantern: &mut Lantern, json: &Value) -> Result<()> {
+    let lists = match json.as_object() {
+        Some(lists) => lists,
+        None => bail!("Expected an object holding lists of components"),
+    };
+    for (kind, list) in lists {
+        let components = match list.as_array() {
+            Some(components) => components,
+            None => bail!("Expected `{}` to be a list of components", kind),
+        };
+        for component in components {
+            let members = match component.as_object() {
+                Some(members) => Members::new(members),
+                None => bail!("Expected every entry in `{}` to be an object", kind),
+            };
+            match kind.to_ascii_lowercase().as_str() {
+                "elements" if members.is("isAspect", &Value::Bool(true)) => {
+                    let aspect = read_aspect(members)?;
+                    lantern.aspects_mut().insert(aspect.id.clone(), aspect);
+                }
+                "elements" => {
+                    let card = read_card(members)?;
+                    lantern.cards_mut().insert(card.id.clone(), card);
+                }
+                "recipes" => {
+                    let recipe = read_recipe(members)?;
+                    lantern.recipes_mut().insert(recipe.id.clone(), recipe);
+                }
+                "decks" => {
+                    let deck = read_deck(members)?;
+                    lantern.decks_mut().insert(deck.id.clone(), deck);
+                }
+                "verbs" => {
+                    let verb = read_verb(members)?;
+                    lantern.verbs_mut().insert(verb.id.clone(), verb);
+                }
+                "legacies" => {
+                    let mut members = members;
+                    let legacy = Legacy { id: members.id(kind)?, others: members.others() };
+                    lantern.legacies_mut().insert(legacy.id.clone(), legacy);
+                }
+                "endings" => {
+                    let mut members = members;
+                    let ending = Ending { id: members.id(kind)?, others: members.others() };
+                    lantern.endings_mut().insert(ending.id.clone(), ending);
+                }
+                _ => bail!("Lantern has no equivalent of the game's `{}`", kind),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Converts a `serde_json` value into a [json::Value].
+pub fn from_value(value: &Value) -> json::Value {
+    match value {
+        Value::Null => json::Value::Null,
+        Value::Bool(b) => json::Value::Boolean(*b),
+        Value::Number(n) => json::Value::Num(n.as_f64().unwrap_or_default()),
+        Value::String(s) => json::Value::Str(s.clone()),
+        Value::Array(items) => json::Value::Array(items.iter().map(from_value).collect()),
+        Value::Object(members) => json::Value::Object(members.iter().map(|(k, v)| (k.clone(), from_value(v))).collect()),
+    }
+}
+
+/// The members of a component's JSON object, which are taken out
+/// as they are converted. The game ignores the case of member
+/// names, so they are matched regardless of case. Whatever is left
+/// at the end becomes the component's `others`.
+struct Members {
+    members: Vec<(String, Value)>,
+}
+
+impl Members {
+    fn new(members: &Map<String, Value>) -> Self {
+        Members { members: members.iter().map(|(k, v)| (k.clone(), v.clone())).collect() }
+    }
+
+    fn is_present(&self, key: &str) -> bool {
+        self.members.iter().any(|(k, _)| k.eq_ignore_ascii_case(key))
+    }
+
+    fn is(&self, key: &str, value: &Value) -> bool {
+        self.members.iter().any(|(k, v)| k.eq_ignore_ascii_case(key) && v == value)
+    }
+
+    /// Takes the member named `key` out, if `convert` accepts its value.
+    fn take<T>(&mut self, key: &str, convert: impl FnOnce(&Value) -> Option<T>) -> Option<T> {
+        let index = self.members.iter().position(|(k, _)| k.eq_ignore_ascii_case(key))?;
+        let converted = convert(&self.members[index].1)?;
+        self.members.remove(index);
+        Some(converted)
+    }
+
+    fn id(&mut self, kind: &str) -> Result<DefKey> {
+        match self.take("id", key) {
+            Some(id) => Ok(id),
+            None => bail!("An entry in `{}` has no string `id`", kind),
+        }
+    }
+
+    fn others(self) -> HashMap<DefKey, json::Value> {
+        self.members.into_iter().map(|(k, v)| (DefKey(k), from_value(&v))).collect()
+    }
+}
+
+/// The members of a nested object, with lowercased names. Returns
+/// `None` if it has any members besides those in `allowed`, since
+/// those could not be kept.
+fn fields<'v>(value: &'v Value, allowed: &[&str]) -> Option<HashMap<String, &'v Value>> {
+    let mut fields = HashMap::new();
+    for (k, v) in value.as_object()? {
+        let k = k.to_ascii_lowercase();
+        if !allowed.contains(&k.as_str()) || fields.insert(k, v).is_some() {
+            return None;
+        }
+    }
+    Some(fields)
+}
+
+fn string(value: &Value) -> Option<String> {
+    value.as_str().map(str::to_owned)
+}
+
+fn key(value: &Value) -> Option<DefKey> {
+    value.as_str().map(|s| DefKey(s.to_owned()))
+}
+
+fn boolean(value: &Value) -> Option<bool> {
+    value.as_bool()
+}
+
+/// Accepts `null` as `None`, and anything `convert` accepts as `Some`.
+fn nullable<T>(convert: impl Fn(&Value) -> Option<T>) -> impl Fn(&Value) -> Option<Option<T>> {
+    move |value| match value {
+        Value::Null => Some(None),
+        value => convert(value).map(Some),
+    }
+}
+
+/// A whole number, which the game also accepts written as `2.0`.
+fn whole(value: &Value) -> Option<f64> {
+    value.as_f64().filter(|n| n.fract() == 0.0)
+}
+
+fn count(value: &Value) -> Option<u32> {
+    whole(value).filter(|n| (0.0..=u32::MAX as f64).contains(n)).map(|n| n as u32)
+}
+
+fn integer(value: &Value) -> Option<i32> {
+    whole(value).filter(|n| (i32::MIN as f64..=i32::MAX as f64).contains(n)).map(|n| n as i32)
+}
+
+fn chance(value: &Value) -> Option<Probability> {
+    count(value).and_then(|n| u8::try_from(n).ok()).and_then(|n| Probability::new(n).ok())
+}
+
+/// An object mapping ids to values accepted by `convert`.
+fn keyed<T>(value: &Value, convert: impl Fn(&Value) -> Option<T>) -> Option<Vec<(DefKey, T)>> {
+    value.as_object()?.iter().map(|(k, v)| Some((DefKey(k.clone()), convert(v)?))).collect()
+}
+
+fn read_induces(value: &Value) -> Option<Option<(DefKey, Probability)>> {
+    match value.as_array()?.as_slice() {
+        [] => Some(None),
+        [induced] => {
+            let fields = fields(induced, &["id", "chance"])?;
+            Some(Some((key(fields.get("id")?)?, chance(fields.get("chance")?)?)))
+        }
+        _ => None,
+    }
+}
+
+/// Reads xtriggers written either as a list of morph effects for each
+/// catalyst, or in the short form of a catalyst and the id it becomes.
+fn read_xtriggers(value: &Value) -> Option<Vec<Xtrigger>> {
+    let mut xtriggers = Vec::new();
+    for (catalyst, morphs) in value.as_object()? {
+        let catalyst = DefKey(catalyst.clone());
+        match morphs {
+            Value::String(target) => xtriggers.push(Xtrigger::Transform {
+                catalyst,
+                transforms_to: DefKey(target.clone()),
+                amount: 1,
+                chance: Probability::new(100).unwrap(),
+            }),
+            Value::Array(morphs) => {
+                for morph in morphs {
+                    xtriggers.push(read_morph(catalyst.clone(), morph)?);
+                }
+            }
+            _ => return None,
+        }
+    }
+    Some(xtriggers)
+}
+
+fn read_morph(catalyst: DefKey, morph: &Value) -> Option<Xtrigger> {
+    let fields = fields(morph, &["id", "morpheffect", "level", "chance"])?;
+    let target = key(fields.get("id")?)?;
+    let chance = match fields.get("chance") {
+        Some(value) => chance(value)?,
+        None => Probability::new(100).unwrap(),
+    };
+    let effect = match fields.get("morpheffect") {
+        Some(value) => value.as_str()?.to_ascii_lowercase(),
+        None => "transform".to_owned(),
+    };
+    let level = fields.get("level").copied();
+    let xtrigger = match effect.as_str() {
+        "transform" => Xtrigger::Transform { catalyst, transforms_to: target, amount: level.map_or(Some(1), count)?, chance },
+        "spawn" => Xtrigger::Spawn { catalyst, creates: target, amount: level.map_or(Some(1), count)?, chance },
+        "mutate" => Xtrigger::Mutate { catalyst, adds_to_catalyst: target, amount: level.map_or(Some(1), integer)?, chance },
+        _ => return None,
+    };
+    Some(xtrigger)
+}
+
+/// Reads a slot. Slots on cards name the verb they appear in, and
+/// no others may, so `verb` says whether `actionId` is expected.
+fn read_slot(value: &Value, verb: bool) -> Option<(Option<DefKey>, Slot)> {
+    let fields = fields(value, &["id", "label", "description", "actionid", "required", "forbidden", "consumes", "greedy"])?;
+    let action = match fields.get("actionid") {
+        Some(action) if verb => Some(key(action)?),
+        None if !verb => None,
+        _ => return None,
+    };
+    let text = |name: &str| fields.get(name).map_or(Some(String::new()), |v| string(v));
+    let flag = |name: &str| fields.get(name).map_or(Some(false), |v| boolean(v));
+    let filters = |name: &str| fields.get(name).map_or(Some(Vec::new()), |v| keyed(v, count));
+
+    let mut requirements: Vec<SlotFilter> = filters("required")?
+        .into_iter()
+        .map(|(element, amount)| SlotFilter::Accept { element, amount })
+        .collect();
+    requirements.extend(filters("forbidden")?.into_iter().map(|(element, amount)| SlotFilter::Forbid { element, amount }));
+
+    let slot = Slot {
+        id: key(fields.get("id")?)?,
+        label: text("label")?,
+        description: text("description")?,
+        consumes: flag("consumes")?,
+        greedy: flag("greedy")?,
+        requirements,
+    };
+    Some((action, slot))
+}
+
+fn read_card_slots(value: &Value) -> Option<HashMap<DefKey, Vec<Slot>>> {
+    let mut slots: HashMap<DefKey, Vec<Slot>> = HashMap::new();
+    for slot in value.as_array()? {
+        let (verb, slot) = read_slot(slot, true)?;
+        slots.entry(verb?).or_default().push(slot);
+    }
+    Some(slots)
+}
+
+fn read_aspect(mut members: Members) -> Result<Aspect> {
+    let id = members.id("elements")?;
+    members.take("isAspect", |v| (v == &Value::Bool(true)).then_some(()));
+    let hidden = members.take("isHidden", boolean).unwrap_or(false);
+    members.take("noartneeded", |v| (v.as_bool() == Some(hidden)).then_some(()));
+    Ok(Aspect {
+        id,
+        label: members.take("label", string).unwrap_or_default(),
+        description: members.take("description", string).unwrap_or_default(),
+        icon: members.take("icon", nullable(string)).flatten(),
+        verbicon: members.take("verbicon", nullable(string)).flatten(),
+        induces: members.take("induces", read_induces).flatten(),
+        decays_to: members.take("decayTo", nullable(key)).flatten(),
+        hidden,
+        xtriggers: members.take("xtriggers", read_xtriggers).unwrap_or_default(),
+        others: members.others(),
+    })
+}
+
+fn read_card(mut members: Members) -> Result<Card> {
+    let id = members.id("elements")?;
+    members.take("isAspect", |v| (v == &Value::Bool(false)).then_some(()));
+    let hidden = members.take("isHidden", boolean).unwrap_or(false);
+    members.take("noartneeded", |v| (v.as_bool() == Some(hidden)).then_some(()));
+    Ok(Card {
+        id,
+        label: members.take("label", string).unwrap_or_default(),
+        description: members.take("description", string).unwrap_or_default(),
+        icon: members.take("icon", nullable(string)).flatten(),
+        verbicon: members.take("verbicon", nullable(string)).flatten(),
+        induces: members.take("induces", read_induces).flatten(),
+        decays_to: members.take("decayTo", nullable(key)).flatten(),
+        hidden,
+        aspects: members.take("aspects", |v| keyed(v, count)).unwrap_or_default().into_iter().collect(),
+        lifetime: members.take("lifetime", nullable(count)).flatten(),
+        resaturate: members.take("resaturate", boolean).unwrap_or(false),
+        unique: members.take("unique", boolean).unwrap_or(false),
+        uniqueness_group: members.take("uniquenessgroup", nullable(key)).flatten(),
+        slots: members.take("slots", read_card_slots).unwrap_or_default(),
+        xtriggers: members.take("xtriggers", read_xtriggers).unwrap_or_default(),
+        others: members.others(),
+    })
+}
+
+fn read_deck(mut members: Members) -> Result<Deck> {
+    let id = members.id("decks")?;
+    let label = members.take("label", string).unwrap_or_default();
+    let description = members.take("description", string).unwrap_or_default();
+    let spec: Vec<DefKey> = members
+        .take("spec", |v| v.as_array()?.iter().map(key).collect::<Option<_>>())
+        .unwrap_or_default();
+    // Draw messages can only be kept for cards in the deck.
+    let messages: HashMap<DefKey, String> = members
+        .take("drawmessages", |v| {
+            let messages = keyed(v, string)?;
+            messages.iter().all(|(card, _)| spec.contains(card)).then_some(messages)
+        })
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+    // The default card is one of the cards in the deck in Lantern.
+    let default = members.take("defaultcard", |v| match nullable(key)(v)? {
+        Some(card) => spec.contains(&card).then_some(Some(card)),
+        None => Some(None),
+    });
+    let default = default.flatten();
+    // Lantern resets decks without a default card, while
+    // the game only resets decks which ask for it.
+    let resets = default.is_none();
+    let absent = !members.is_present("resetonexhaustion");
+    members.take("resetonexhaustion", |v| (v.as_bool() == Some(resets)).then_some(()));
+    let mut others = members.others();
+    if resets && absent {
+        others.insert(DefKey("resetonexhaustion".to_owned()), json::Value::Boolean(false));
+    }
+
+    let cards: Vec<(DefKey, Option<String>)> = spec
+        .into_iter()
+        .map(|card| {
+            let message = messages.get(&card).cloned();
+            (card, message)
+        })
+        .collect();
+    Ok(Deck {
+        id,
+        label,
+        description,
+        default,
+        is_portal_deck: cards.iter().any(|(_, message)| message.is_some()),
+        cards,
+        others,
+    })
+}
+
+fn read_verb(mut members: Members) -> Result<Verb> {
+    let id = members.id("verbs")?;
+    Ok(Verb {
+        id,
+        label: members.take("label", string).unwrap_or_default(),
+        description: members.take("description", string).unwrap_or_default(),
+        slot: members.take("slot", nullable(|v| read_slot(v, false).map(|(_, slot)| slot))).flatten(),
+        others: members.others(),
+    })
+}
+
+fn read_requirements(
+    value: &Value,
+    kind: fn(DefKey, Either<ValueOperation, DefKey>) -> RecipeRequirement,
+) -> Option<Vec<RecipeRequirement>> {
+    let amounts = keyed(value, threshold)?;
+    Some(amounts.into_iter().map(|(element, amount)| kind(element, amount)).collect())
+}
+
+/// A requirement amount, or the id of an element
+/// whose amount must be matched.
+fn threshold(value: &Value) -> Option<Either<ValueOperation, DefKey>> {
+    match value {
+        Value::String(id) => Some(Either::Right(DefKey(id.clone()))),
+        _ => integer(value).map(|n| Either::Left(ValueOperation::Add(n))),
+    }
+}
+
+fn basic(element: DefKey, amount: Either<ValueOperation, DefKey>) -> RecipeRequirement {
+    RecipeRequirement::Basic { element, amount }
+}
+
+fn table(element: DefKey, amount: Either<ValueOperation, DefKey>) -> RecipeRequirement {
+    RecipeRequirement::Table { element, amount }
+}
+
+fn extant(element: DefKey, amount: Either<ValueOperation, DefKey>) -> RecipeRequirement {
+    RecipeRequirement::Extant { element, amount }
+}
+
+fn read_condition(fields: &HashMap<String, &Value>) -> Option<BranchCondition> {
+    let mut requirements = Vec::new();
+    for (name, kind) in [("requirements", basic as fn(_, _) -> _), ("tablereqs", table), ("extantreqs", extant)] {
+        if let Some(value) = fields.get(name) {
+            requirements.extend(read_requirements(value, kind)?);
+        }
+    }
+    let chance = match fields.get("chance") {
+        Some(value) => nullable(chance)(value)?,
+        None => None,
+    };
+    Some(BranchCondition { chance, requirements })
+}
+
+fn read_links(value: &Value) -> Option<Vec<Branch>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|link| {
+            let fields = fields(link, &["id", "chance", "requirements", "tablereqs", "extantreqs"])?;
+            Some(Branch::Link { target: key(fields.get("id")?)?, condition: read_condition(&fields)? })
+        })
+        .collect()
+}
+
+fn read_alts(value: &Value) -> Option<Vec<Branch>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|alt| {
+            let fields = fields(alt, &["id", "chance", "requirements", "tablereqs", "extantreqs", "additional", "expulsion"])?;
+            let additional = fields.get("additional").map_or(Some(false), |v| boolean(v))?;
+            let action = match fields.get("expulsion") {
+                // The limit is always written as the sum of the filter.
+                Some(expulsion) if additional => {
+                    let expulsion = self::fields(expulsion, &["limit", "filter"])?;
+                    let filter = keyed(expulsion.get("filter")?, count)?;
+                    let limit: u32 = filter.iter().map(|(_, n)| n).sum();
+                    (count(expulsion.get("limit")?)? == limit).then_some(())?;
+                    Some(SpawningKind::Expel(filter))
+                }
+                Some(_) => return None,
+                None if additional => Some(SpawningKind::Spawn),
+                None => None,
+            };
+            Some(Branch::Goto { target: key(fields.get("id")?)?, condition: read_condition(&fields)?, action })
+        })
+        .collect()
+}
+
+fn read_mutations(value: &Value) -> Option<Vec<Mutation>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|mutation| {
+            let fields = fields(mutation, &["filter", "mutate", "level", "additive"])?;
+            let additive = fields.get("additive").map_or(Some(false), |v| boolean(v))?;
+            let level = fields.get("level")?;
+            let operation = if additive {
+                ValueOperation::Add(integer(level)?)
+            } else {
+                ValueOperation::Set(count(level)?)
+            };
+            Some(Mutation { id: key(fields.get("filter")?)?, aspect: key(fields.get("mutate")?)?, operation })
+        })
+        .collect()
+}
+
+fn read_recipe_slot(value: &Value) -> Option<Option<Slot>> {
+    match value.as_array()?.as_slice() {
+        [] => Some(None),
+        [slot] => read_slot(slot, false).map(|(_, slot)| Some(slot)),
+        _ => None,
+    }
+}
+
+fn read_recipe(mut members: Members) -> Result<Recipe> {
+    let id = members.id("recipes")?;
+    let verb = match members.take("actionId", key) {
+        Some(verb) => verb,
+        None => bail!("The recipe `{}` has no string `actionId`", id),
+    };
+    let mut requirements = Vec::new();
+    for (name, kind) in [("requirements", basic as fn(_, _) -> _), ("tablereqs", table), ("extantreqs", extant)] {
+        requirements.extend(members.take(name, |v| read_requirements(v, kind)).unwrap_or_default());
+    }
+    let mut branches = members.take("linked", read_links).unwrap_or_default();
+    branches.extend(members.take("alt", read_alts).unwrap_or_default());
+    let targets = |v: &Value| {
+        let targets = keyed(v, count)?;
+        Some((!targets.is_empty()).then(|| targets.into_iter().collect::<HashMap<_, _>>()))
+    };
+
+    let mut style = members
+        .take("signalEndingFlavour", |v| match v.as_str()?.to_ascii_lowercase().as_str() {
+            "none" => Some(WarmupStyle::None),
+            "grand" => Some(WarmupStyle::Grand),
+            "melancholy" => Some(WarmupStyle::Mellancholy),
+            "pale" => Some(WarmupStyle::Pale),
+            "vile" => Some(WarmupStyle::Vile),
+            _ => None,
+        })
+        .unwrap_or(WarmupStyle::None);
+    // Lantern has no way to both signal an ending and an important loop.
+    let plain = matches!(style, WarmupStyle::None);
+    let important = members.take("signalimportantloop", |v| match v.as_bool()? {
+        true if plain => Some(true),
+        true => None,
+        false => Some(false),
+    });
+    if important == Some(true) {
+        style = WarmupStyle::Important;
+    }
+
+    Ok(Recipe {
+        id,
+        verb,
+        label: members.take("label", string).unwrap_or_default(),
+        description: members.take("startdescription", string).unwrap_or_default(),
+        end_description: members.take("description", string).unwrap_or_default(),
+        burn: members.take("burnimage", nullable(string)).flatten(),
+        portal: members.take("portaleffect", nullable(string)).flatten(),
+        requirements,
+        max_executions: members.take("maxexecutions", count).unwrap_or(0),
+        warmup: members.take("warmup", count).unwrap_or(0),
+        craftable: members.take("craftable", boolean).unwrap_or(false),
+        hint_only: members.take("hintonly", boolean).unwrap_or(false),
+        slot: members.take("slots", read_recipe_slot).flatten(),
+        effects: members
+            .take("effects", |v| keyed(v, integer))
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(element, n)| (element, ValueOperation::Add(n)))
+            .collect(),
+        purge: members.take("purge", |v| keyed(v, count)).unwrap_or_default().into_iter().collect(),
+        // Changes to the aspect stack are left as they are.
+        aspects: HashMap::new(),
+        draws: members.take("deckeffects", |v| keyed(v, integer)).unwrap_or_default().into_iter().collect(),
+        // Internal decks are kept as they are written.
+        internal_deck: None,
+        mutations: members.take("mutations", read_mutations).unwrap_or_default(),
+        halt: members.take("haltverb", targets).flatten(),
+        delete: members.take("deleteverb", targets).flatten(),
+        ending: members.take("ending", nullable(key)).flatten(),
+        style,
+        branches,
+        others: members.others(),
+    })
+}